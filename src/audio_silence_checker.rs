@@ -1,24 +1,145 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
 use anyhow::Result;
 use data_pipeline_rs::data_handler::{DataTransformer, SomeDataHandler};
-use rtp_parse::rtp::audio_level_header_extension::is_muted;
+use rtp_parse::rtp::audio_level_header_extension::{get_audio_level, is_muted};
+
+use crate::{
+    error::PipelineError,
+    packet_info::{PacketInfo, SomePacket},
+    util::{LiveStateReader, LiveStateWriter},
+};
+
+pub const AUDIO_LEVEL_URI: &str = "urn:ietf:params:rtp-hdrext:ssrc-audio-level";
+
+/// The weight given to each new level when smoothing
+const SMOOTHING_FACTOR: f32 = 0.25;
+/// The level (in -dBov) used for packets marked as not containing voice
+pub const SILENT_LEVEL: u8 = 127;
+
+#[derive(Clone, Debug)]
+struct AudioLevel {
+    smoothed_level: f32,
+    last_received: Instant,
+}
+
+/// The smoothed audio level of each stream, in -dBov as in the RFC 6464 header extension: 0 is
+/// the loudest and 127 is silence.
+#[derive(Clone, Debug, Default)]
+pub struct AudioLevels(HashMap<u32, AudioLevel>);
+
+impl AudioLevels {
+    /// Fold a newly received `level` into the stream's smoothed level, returning the new
+    /// smoothed level
+    pub fn update(&mut self, ssrc: u32, level: u8, received_time: Instant) -> f32 {
+        let audio_level = self.0.entry(ssrc).or_insert(AudioLevel {
+            smoothed_level: level as f32,
+            last_received: received_time,
+        });
+        audio_level.smoothed_level +=
+            SMOOTHING_FACTOR * (level as f32 - audio_level.smoothed_level);
+        audio_level.last_received = received_time;
+        audio_level.smoothed_level
+    }
+
+    pub fn get(&self, ssrc: u32) -> Option<f32> {
+        self.0.get(&ssrc).map(|level| level.smoothed_level)
+    }
+
+    /// When a level was last received for the stream.  Streams may stop sending audio entirely
+    /// (e.g. when muted), so a level which hasn't been updated in a while may be out of date.
+    pub fn last_received(&self, ssrc: u32) -> Option<Instant> {
+        self.0.get(&ssrc).map(|level| level.last_received)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, f32)> + '_ {
+        self.0
+            .iter()
+            .map(|(ssrc, level)| (*ssrc, level.smoothed_level))
+    }
+}
+
+/// Marks audio packets to be discarded once a stream has been silent for longer than the
+/// hangover, using the RFC 6464 audio level header extension.  A packet is silent if it's marked
+/// as not containing voice, or if both its own level and the stream's smoothed level are at or
+/// above (i.e. no louder than) the threshold.  A single loud packet counts as voice so that the
+/// start of an utterance isn't clipped while the smoothed level catches up.
+pub struct AudioSilenceChecker {
+    audio_level_ext_id: LiveStateReader<Option<u8>>,
+    /// The level (in -dBov) at or above which audio is considered silent
+    silence_threshold: u8,
+    hangover: Duration,
+    levels: LiveStateWriter<AudioLevels>,
+    /// When each stream last had a packet which wasn't silent
+    last_voice_activity: HashMap<u32, Instant>,
+}
 
-use crate::packet_info::{PacketInfo, SomePacket};
+impl AudioSilenceChecker {
+    pub fn new(
+        audio_level_ext_id: LiveStateReader<Option<u8>>,
+        silence_threshold: u8,
+        hangover: Duration,
+    ) -> Self {
+        Self {
+            audio_level_ext_id,
+            silence_threshold,
+            hangover,
+            levels: LiveStateWriter::new(AudioLevels::default()),
+            last_voice_activity: HashMap::new(),
+        }
+    }
+
+    pub fn audio_levels(&self) -> LiveStateReader<AudioLevels> {
+        self.levels.reader()
+    }
 
-pub struct AudioSilenceChecker;
+    /// Update the stream's smoothed level, returning true if it has been silent for longer than
+    /// the hangover
+    fn level_received(&mut self, ssrc: u32, level: u8, received_time: Instant) -> bool {
+        let mut smoothed_level = 0.0;
+        self.levels
+            .modify(|levels| smoothed_level = levels.update(ssrc, level, received_time));
+        let threshold = self.silence_threshold as f32;
+        if level < SILENT_LEVEL && (level as f32).min(smoothed_level) < threshold {
+            self.last_voice_activity.insert(ssrc, received_time);
+            return false;
+        }
+        // A stream which has never had voice activity is silent straight away
+        self.last_voice_activity
+            .get(&ssrc)
+            .is_none_or(|last_voice_activity| {
+                received_time.duration_since(*last_voice_activity) > self.hangover
+            })
+    }
+}
 
 impl DataTransformer<PacketInfo> for AudioSilenceChecker {
     fn transform(&mut self, mut packet_info: PacketInfo) -> Result<PacketInfo> {
         let rtp_packet = match packet_info.packet {
             SomePacket::AudioRtpPacket(ref rtp) => rtp,
-            _ => panic!(
-                "AudioLevelReader got non-rtp packet: {:?}",
-                packet_info.packet
-            ),
-        };
-        if let Some(audio_level_ext) = rtp_packet.get_extension_by_id(1) {
-            if is_muted(audio_level_ext) {
-                packet_info.should_discard = true;
+            ref packet => {
+                return Err(
+                    PipelineError::unexpected_packet_type("AudioSilenceChecker", packet).into(),
+                )
             }
+        };
+        let audio_level_ext_id = *self.audio_level_ext_id.value();
+        let Some(audio_level_ext) =
+            audio_level_ext_id.and_then(|id| rtp_packet.get_extension_by_id(id))
+        else {
+            return Ok(packet_info);
+        };
+        let level = if is_muted(audio_level_ext) {
+            SILENT_LEVEL
+        } else {
+            get_audio_level(audio_level_ext).into()
+        };
+        let ssrc = rtp_packet.ssrc();
+        if self.level_received(ssrc, level, packet_info.received_time) {
+            packet_info.should_discard = true;
         }
 
         Ok(packet_info)
@@ -30,3 +151,104 @@ impl From<AudioSilenceChecker> for SomeDataHandler<PacketInfo> {
         SomeDataHandler::Transformer(Box::new(value))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rtp_parse::rtp::rtp_packet::read_rtp_packet;
+
+    use super::*;
+
+    fn create_checker() -> AudioSilenceChecker {
+        let ext_id = LiveStateWriter::new(Some(1));
+        AudioSilenceChecker::new(ext_id.reader(), 60, Duration::from_millis(100))
+    }
+
+    #[test]
+    fn test_silence_hangover() {
+        let mut checker = create_checker();
+        let levels = checker.audio_levels();
+        let start = Instant::now();
+
+        assert!(!checker.level_received(1234, 30, start));
+        assert_eq!(levels.value().get(1234), Some(30.0));
+        // Silent packets are kept until the hangover has passed
+        let mut time = start;
+        let mut discarded = Vec::new();
+        for _ in 0..7 {
+            time += Duration::from_millis(20);
+            discarded.push(checker.level_received(1234, SILENT_LEVEL, time));
+        }
+        assert_eq!(
+            discarded,
+            vec![false, false, false, false, false, true, true]
+        );
+        assert!(levels.value().get(1234).unwrap() > 100.0);
+
+        // Voice is kept straight away, before the smoothed level is back over the threshold
+        time += Duration::from_millis(20);
+        assert!(!checker.level_received(1234, 0, time));
+        assert!(levels.value().get(1234).unwrap() > 60.0);
+        // and it resets the hangover
+        time += Duration::from_millis(20);
+        assert!(!checker.level_received(1234, SILENT_LEVEL, time));
+    }
+
+    #[test]
+    fn test_quiet_stream_is_silent() {
+        let mut checker = create_checker();
+        let start = Instant::now();
+
+        // Never louder than the threshold, so discarded from the start
+        assert!(checker.level_received(1234, 80, start));
+        // Other streams are tracked separately
+        assert!(!checker.level_received(5678, 10, start));
+        assert!(checker.level_received(1234, 80, start + Duration::from_millis(20)));
+    }
+
+    fn audio_packet(ext_id: u8, level: u8) -> PacketInfo {
+        #[rustfmt::skip]
+        let packet = vec![
+            0x90, 0x6F, 0x00, 0x01,
+            0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x04, 0xD2,
+            0xBE, 0xDE, 0x00, 0x01,
+            ext_id << 4, 0x80 | level, 0x00, 0x00,
+        ];
+        PacketInfo::new(
+            SomePacket::AudioRtpPacket(read_rtp_packet(packet).unwrap()),
+            Instant::now(),
+        )
+    }
+
+    #[test]
+    fn test_transform_uses_signaled_ext_id() {
+        let ext_id = LiveStateWriter::new(Some(3));
+        let mut checker = AudioSilenceChecker::new(ext_id.reader(), 60, Duration::from_millis(100));
+
+        assert!(
+            checker
+                .transform(audio_packet(3, 80))
+                .unwrap()
+                .should_discard
+        );
+        assert!(
+            !checker
+                .transform(audio_packet(3, 10))
+                .unwrap()
+                .should_discard
+        );
+        assert_eq!(checker.audio_levels().value().get(1234), Some(62.5));
+
+        // Once the id changes, extensions with the old id are ignored
+        ext_id.set(Some(4));
+        assert!(
+            !checker
+                .transform(audio_packet(3, 80))
+                .unwrap()
+                .should_discard
+        );
+        assert_eq!(checker.audio_levels().value().get(1234), Some(62.5));
+        checker.transform(audio_packet(4, 10)).unwrap();
+        assert_eq!(checker.audio_levels().value().get(1234), Some(49.375));
+    }
+}
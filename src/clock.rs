@@ -0,0 +1,122 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// A source of time, so that nodes which depend on the current time can be driven by something
+/// other than the system clock (e.g. in tests).
+pub trait Clock: Send + Sync {
+    /// The current monotonic time, for measuring intervals
+    fn now(&self) -> Instant;
+    /// The current wallclock time
+    fn system_time(&self) -> SystemTime;
+}
+
+/// A [`Clock`] backed by the system clock
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// The offset between the NTP epoch (1900) and the unix epoch (1970), in seconds
+const NTP_EPOCH_OFFSET_SECS: u64 = 2_208_988_800;
+
+/// Convert the given wallclock time to a 64 bit NTP timestamp: the integer part of the seconds
+/// since 1900 in the upper 32 bits and the fractional part in the lower 32 bits.
+pub fn to_ntp_timestamp(time: SystemTime) -> u64 {
+    let since_unix_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = since_unix_epoch.as_secs() + NTP_EPOCH_OFFSET_SECS;
+    let fraction = ((since_unix_epoch.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    (secs << 32) | fraction
+}
+
+/// The middle 32 bits of a 64 bit NTP timestamp, as used in the LSR field of RTCP report blocks
+pub fn compact_ntp(ntp_timestamp: u64) -> u32 {
+    (ntp_timestamp >> 16) as u32
+}
+
+/// A [`Clock`] which only moves when it's told to, so that time-dependent nodes can be driven
+/// deterministically (e.g. in tests or simulations).
+pub struct ManualClock {
+    now: Mutex<(Instant, SystemTime)>,
+}
+
+impl ManualClock {
+    /// Create a clock whose wallclock time starts at `system_time`
+    pub fn starting_at(system_time: SystemTime) -> Self {
+        Self {
+            now: Mutex::new((Instant::now(), system_time)),
+        }
+    }
+
+    /// Create a clock whose wallclock time starts 1,000,000 seconds after the unix epoch, which
+    /// makes for easy to read NTP timestamps.
+    pub fn new() -> Self {
+        Self::starting_at(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000))
+    }
+
+    /// Move both the monotonic and wallclock time forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        now.0 += duration;
+        now.1 += duration;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.now.lock().unwrap().0
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.now.lock().unwrap().1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_ntp_timestamp() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1500);
+        assert_eq!(
+            to_ntp_timestamp(time),
+            ((NTP_EPOCH_OFFSET_SECS + 1) << 32) | 0x80000000
+        );
+    }
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let start_system_time = clock.system_time();
+        assert_eq!(clock.now(), start);
+
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(clock.now() - start, Duration::from_millis(20));
+        assert_eq!(
+            clock
+                .system_time()
+                .duration_since(start_system_time)
+                .unwrap(),
+            Duration::from_millis(20)
+        );
+    }
+}
@@ -1,9 +1,12 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
 use bit_cursor::bit_cursor::BitCursor;
 use data_pipeline_rs::data_handler::{DataTransformer, SomeDataHandler};
 use rtp_parse::rtcp::rtcp_packet::parse_rtcp_packet;
 
-use crate::packet_info::{PacketInfo, SomePacket};
+use crate::{
+    error::PipelineError,
+    packet_info::{PacketInfo, SomePacket},
+};
 
 #[derive(Default)]
 pub struct CompoundRtcpParser;
@@ -12,10 +15,15 @@ impl DataTransformer<PacketInfo> for CompoundRtcpParser {
     fn transform(&mut self, mut data: PacketInfo) -> Result<PacketInfo> {
         let packet_buf = match data.packet {
             SomePacket::UnparsedPacket(buf) => buf,
-            _ => panic!("RTCP parser got unexpected packet type: {:x?}", data.packet),
+            ref packet => {
+                return Err(
+                    PipelineError::unexpected_packet_type("CompoundRtcpParser", packet).into(),
+                )
+            }
         };
         let mut cursor = BitCursor::from_vec(packet_buf);
-        let parsed = parse_rtcp_packet(&mut cursor).context("rtcp parse")?;
+        let parsed = parse_rtcp_packet(&mut cursor)
+            .map_err(|e| PipelineError::parse_failure("rtcp packet", e))?;
         data.packet = SomePacket::RtcpPacket(parsed);
         Ok(data)
     }
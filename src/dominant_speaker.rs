@@ -0,0 +1,406 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    audio_silence_checker::AudioLevels,
+    timer::{TimerHandle, TimerHandler, TimerScheduler},
+    util::{LiveStateReader, LiveStateWriter, SharedData},
+};
+
+/// How often the audio levels are sampled
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(20);
+/// How often we decide whether the dominant speaker should change
+const DECISION_INTERVAL: Duration = Duration::from_millis(300);
+/// A smoothed level (in -dBov) below this counts as speech
+const SPEECH_LEVEL: f32 = 60.0;
+/// Levels which haven't been updated for this long are considered silent, since the stream may
+/// have stopped sending audio altogether
+const STALE_LEVEL_TIMEOUT: Duration = Duration::from_millis(200);
+/// If we haven't had a level for a speaker for this long it will be forgotten
+const STALE_SPEAKER_TIMEOUT: Duration = Duration::from_secs(10);
+
+type SpeakerChangeListener = Box<dyn FnMut(u32) + Send + Sync>;
+
+/// The number of samples in an immediate block (100ms)
+const IMMEDIATE_BLOCK_SAMPLES: usize = 5;
+/// How many samples in an immediate block need to be speech for it to count as active
+const IMMEDIATE_BLOCK_THRESHOLD: usize = 3;
+/// The number of immediate blocks in a medium block (500ms)
+const MEDIUM_BLOCK_IMMEDIATE_BLOCKS: usize = 5;
+/// How many immediate blocks in a medium block need to be active for it to count as active
+const MEDIUM_BLOCK_THRESHOLD: usize = 2;
+/// The number of immediate blocks the medium score is taken over (1s)
+const MEDIUM_WINDOW: usize = 10;
+/// The number of medium blocks the long score is taken over (3s)
+const LONG_WINDOW: usize = 6;
+
+/// Added to scores before comparing them, so that a speaker with no activity can be compared
+const SCORE_EPSILON: f32 = 0.01;
+/// How much higher (as the log of the ratio) each of a challenger's scores must be than the
+/// dominant speaker's for the challenger to take over.  The long term threshold stops a short
+/// interjection from taking over from someone who has been speaking for a while.
+const IMMEDIATE_THRESHOLD: f32 = 3.0;
+const MEDIUM_THRESHOLD: f32 = 2.0;
+const LONG_THRESHOLD: f32 = 0.0;
+
+/// The fraction of recent time a speaker was active, over three timescales
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ActivityScores {
+    /// The last 100ms
+    immediate: f32,
+    /// The last second
+    medium: f32,
+    /// The last 3 seconds
+    long: f32,
+}
+
+impl ActivityScores {
+    fn beats(&self, other: &ActivityScores) -> bool {
+        let exceeds = |score: f32, other_score: f32, threshold: f32| {
+            ((score + SCORE_EPSILON) / (other_score + SCORE_EPSILON)).ln() > threshold
+        };
+        exceeds(self.immediate, other.immediate, IMMEDIATE_THRESHOLD)
+            && exceeds(self.medium, other.medium, MEDIUM_THRESHOLD)
+            && exceeds(self.long, other.long, LONG_THRESHOLD)
+    }
+}
+
+/// Add `value` to the end of `window`, keeping at most `len` values
+fn push_bounded(window: &mut VecDeque<bool>, value: bool, len: usize) {
+    window.push_back(value);
+    if window.len() > len {
+        window.pop_front();
+    }
+}
+
+fn active_fraction(window: &VecDeque<bool>, len: usize) -> f32 {
+    window.iter().filter(|active| **active).count() as f32 / len as f32
+}
+
+/// Tracks the speech activity of a single stream
+struct Speaker {
+    samples: VecDeque<bool>,
+    immediate_blocks: VecDeque<bool>,
+    medium_blocks: VecDeque<bool>,
+    /// Samples since the last immediate block ended
+    pending_samples: usize,
+    /// Immediate blocks since the last medium block ended
+    pending_immediate_blocks: usize,
+    last_level_time: Instant,
+}
+
+impl Speaker {
+    fn new(now: Instant) -> Self {
+        Self {
+            samples: VecDeque::new(),
+            immediate_blocks: VecDeque::new(),
+            medium_blocks: VecDeque::new(),
+            pending_samples: 0,
+            pending_immediate_blocks: 0,
+            last_level_time: now,
+        }
+    }
+
+    fn sample(&mut self, speech: bool) {
+        push_bounded(&mut self.samples, speech, IMMEDIATE_BLOCK_SAMPLES);
+        self.pending_samples += 1;
+        if self.pending_samples < IMMEDIATE_BLOCK_SAMPLES {
+            return;
+        }
+        self.pending_samples = 0;
+        let active_samples = self.samples.iter().filter(|speech| **speech).count();
+        push_bounded(
+            &mut self.immediate_blocks,
+            active_samples >= IMMEDIATE_BLOCK_THRESHOLD,
+            MEDIUM_WINDOW,
+        );
+        self.pending_immediate_blocks += 1;
+        if self.pending_immediate_blocks < MEDIUM_BLOCK_IMMEDIATE_BLOCKS {
+            return;
+        }
+        self.pending_immediate_blocks = 0;
+        let active_blocks = self
+            .immediate_blocks
+            .iter()
+            .rev()
+            .take(MEDIUM_BLOCK_IMMEDIATE_BLOCKS)
+            .filter(|active| **active)
+            .count();
+        push_bounded(
+            &mut self.medium_blocks,
+            active_blocks >= MEDIUM_BLOCK_THRESHOLD,
+            LONG_WINDOW,
+        );
+    }
+
+    fn scores(&self) -> ActivityScores {
+        ActivityScores {
+            immediate: active_fraction(&self.samples, IMMEDIATE_BLOCK_SAMPLES),
+            medium: active_fraction(&self.immediate_blocks, MEDIUM_WINDOW),
+            long: active_fraction(&self.medium_blocks, LONG_WINDOW),
+        }
+    }
+}
+
+struct SpeakerActivity {
+    sources: Vec<LiveStateReader<AudioLevels>>,
+    speakers: HashMap<u32, Speaker>,
+    dominant_speaker: LiveStateWriter<Option<u32>>,
+    speaker_change_listeners: Vec<SpeakerChangeListener>,
+    next_decision_time: Option<Instant>,
+}
+
+impl SpeakerActivity {
+    fn sample(&mut self, now: Instant) {
+        let mut levels = HashMap::new();
+        for source in &self.sources {
+            let source = source.value();
+            for (ssrc, level) in source.iter() {
+                let last_received = source.last_received(ssrc).unwrap_or(now);
+                // Don't bring back speakers which have been removed
+                if now.duration_since(last_received) <= STALE_SPEAKER_TIMEOUT {
+                    levels.insert(ssrc, (level, last_received));
+                }
+            }
+        }
+        for (ssrc, (_, last_received)) in &levels {
+            self.speakers
+                .entry(*ssrc)
+                .or_insert_with(|| Speaker::new(*last_received))
+                .last_level_time = *last_received;
+        }
+        for (ssrc, speaker) in self.speakers.iter_mut() {
+            let speech = levels.get(ssrc).is_some_and(|(level, last_received)| {
+                *level < SPEECH_LEVEL && now.duration_since(*last_received) < STALE_LEVEL_TIMEOUT
+            });
+            speaker.sample(speech);
+        }
+    }
+
+    fn remove_stale_speakers(&mut self, now: Instant) {
+        self.speakers.retain(|_, speaker| {
+            now.duration_since(speaker.last_level_time) <= STALE_SPEAKER_TIMEOUT
+        });
+        let dominant_speaker = *self.dominant_speaker.value();
+        if dominant_speaker.is_some_and(|ssrc| !self.speakers.contains_key(&ssrc)) {
+            self.dominant_speaker.set(None);
+        }
+    }
+
+    fn decide(&mut self) {
+        let dominant_speaker = *self.dominant_speaker.value();
+        let dominant_scores = dominant_speaker
+            .and_then(|ssrc| self.speakers.get(&ssrc))
+            .map(Speaker::scores);
+        let challenger = self
+            .speakers
+            .iter()
+            .filter(|(ssrc, _)| Some(**ssrc) != dominant_speaker)
+            .map(|(ssrc, speaker)| (*ssrc, speaker.scores()))
+            .max_by(|(_, a), (_, b)| {
+                a.medium
+                    .total_cmp(&b.medium)
+                    .then(a.immediate.total_cmp(&b.immediate))
+                    .then(a.long.total_cmp(&b.long))
+            });
+        let Some((challenger, challenger_scores)) = challenger else {
+            return;
+        };
+        let take_over = match dominant_scores {
+            Some(dominant_scores) => challenger_scores.beats(&dominant_scores),
+            // Anyone who is speaking becomes dominant if there's no dominant speaker yet
+            None => challenger_scores.immediate > 0.0,
+        };
+        if take_over {
+            self.dominant_speaker.set(Some(challenger));
+            for listener in &mut self.speaker_change_listeners {
+                listener(challenger);
+            }
+        }
+    }
+}
+
+impl TimerHandler for SpeakerActivity {
+    fn on_timer(&mut self, now: Instant) -> Option<Instant> {
+        self.sample(now);
+        let next_decision_time = *self
+            .next_decision_time
+            .get_or_insert(now + DECISION_INTERVAL);
+        if now >= next_decision_time {
+            self.remove_stale_speakers(now);
+            self.decide();
+            self.next_decision_time = Some(now + DECISION_INTERVAL);
+        }
+        Some(now + SAMPLE_INTERVAL)
+    }
+}
+
+/// Identifies the dominant speaker across the audio streams of all endpoints, based on their
+/// smoothed audio levels.  Speech activity is measured over immediate (100ms), medium (1s) and
+/// long (3s) timescales, and a new speaker only takes over once they beat the current dominant
+/// speaker on all three, so short interjections and noise don't cause rapid switching.
+pub struct DominantSpeakerDetector {
+    activity: SharedData<SpeakerActivity>,
+    _timer: TimerHandle,
+}
+
+impl DominantSpeakerDetector {
+    pub fn new(timers: &TimerScheduler) -> Self {
+        let activity = SharedData::new(SpeakerActivity {
+            sources: Vec::new(),
+            speakers: HashMap::new(),
+            dominant_speaker: LiveStateWriter::new(None),
+            speaker_change_listeners: Vec::new(),
+            next_decision_time: None,
+        });
+        let timer = timers.register(activity.clone());
+        timer.schedule(timers.clock().now());
+        Self {
+            activity,
+            _timer: timer,
+        }
+    }
+
+    /// Include the streams whose levels are tracked by `levels` (e.g. from an endpoint's
+    /// [`AudioSilenceChecker`](crate::audio_silence_checker::AudioSilenceChecker))
+    pub fn add_audio_levels(&self, levels: LiveStateReader<AudioLevels>) {
+        self.activity.write().sources.push(levels);
+    }
+
+    /// The ssrc of the current dominant speaker.  This changes whenever a new speaker takes over.
+    pub fn dominant_speaker(&self) -> LiveStateReader<Option<u32>> {
+        self.activity.read().dominant_speaker.reader()
+    }
+
+    /// Call `listener` with the ssrc of each new dominant speaker as it takes over (e.g. to
+    /// update a [`LastNSelector`](crate::last_n::LastNSelector)), rather than having to poll
+    /// [`DominantSpeakerDetector::dominant_speaker`]
+    pub fn on_speaker_change<F>(&self, listener: F)
+    where
+        F: FnMut(u32) + Send + Sync + 'static,
+    {
+        self.activity
+            .write()
+            .speaker_change_listeners
+            .push(Box::new(listener));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::clock::{Clock, ManualClock};
+
+    use super::*;
+
+    const A: u32 = 1111;
+    const B: u32 = 2222;
+
+    struct Room {
+        clock: Arc<ManualClock>,
+        timers: TimerScheduler,
+        levels: LiveStateWriter<AudioLevels>,
+        detector: DominantSpeakerDetector,
+    }
+
+    impl Room {
+        fn new() -> Self {
+            let clock = Arc::new(ManualClock::new());
+            let timers = TimerScheduler::new(clock.clone());
+            let detector = DominantSpeakerDetector::new(&timers);
+            let levels = LiveStateWriter::new(AudioLevels::default());
+            detector.add_audio_levels(levels.reader());
+            Self {
+                clock,
+                timers,
+                levels,
+                detector,
+            }
+        }
+
+        /// Send a packet every 20ms for `duration` from each of `speakers`, where a speaker is
+        /// either speaking or silent
+        fn run(&mut self, duration: Duration, speakers: &[(u32, bool)]) {
+            for _ in 0..(duration.as_millis() / SAMPLE_INTERVAL.as_millis()) {
+                let now = self.clock.now();
+                self.levels.modify(|levels| {
+                    for (ssrc, speaking) in speakers {
+                        levels.update(*ssrc, if *speaking { 10 } else { 127 }, now);
+                    }
+                });
+                self.clock.advance(SAMPLE_INTERVAL);
+                self.timers.fire_due();
+            }
+        }
+
+        fn dominant_speaker(&self) -> Option<u32> {
+            *self.detector.dominant_speaker().value()
+        }
+    }
+
+    #[test]
+    fn test_speaker_change() {
+        let mut room = Room::new();
+        room.run(Duration::from_secs(1), &[(A, false), (B, false)]);
+        assert_eq!(room.dominant_speaker(), None);
+
+        room.run(Duration::from_secs(3), &[(A, true), (B, false)]);
+        assert_eq!(room.dominant_speaker(), Some(A));
+
+        // B takes over once they've been speaking for a while and A has gone quiet
+        room.run(Duration::from_millis(500), &[(A, false), (B, true)]);
+        assert_eq!(room.dominant_speaker(), Some(A));
+        room.run(Duration::from_secs(3), &[(A, false), (B, true)]);
+        assert_eq!(room.dominant_speaker(), Some(B));
+    }
+
+    #[test]
+    fn test_speaker_change_listener() {
+        let mut room = Room::new();
+        let changes = SharedData::new(Vec::new());
+        room.detector.on_speaker_change({
+            let changes = changes.clone();
+            move |ssrc| changes.write().push(ssrc)
+        });
+
+        room.run(Duration::from_secs(3), &[(A, true), (B, false)]);
+        room.run(Duration::from_secs(3), &[(A, true), (B, false)]);
+        assert_eq!(*changes.read(), vec![A]);
+        room.run(Duration::from_secs(4), &[(A, false), (B, true)]);
+        assert_eq!(*changes.read(), vec![A, B]);
+    }
+
+    #[test]
+    fn test_interjections_dont_switch() {
+        let mut room = Room::new();
+        room.run(Duration::from_secs(3), &[(A, true), (B, false)]);
+        assert_eq!(room.dominant_speaker(), Some(A));
+
+        for _ in 0..5 {
+            room.run(Duration::from_millis(200), &[(A, false), (B, true)]);
+            room.run(Duration::from_millis(800), &[(A, true), (B, false)]);
+            assert_eq!(room.dominant_speaker(), Some(A));
+        }
+        // Talking over the dominant speaker isn't enough either
+        room.run(Duration::from_secs(2), &[(A, true), (B, true)]);
+        assert_eq!(room.dominant_speaker(), Some(A));
+    }
+
+    #[test]
+    fn test_stream_stopped() {
+        let mut room = Room::new();
+        room.run(Duration::from_secs(3), &[(A, true), (B, false)]);
+        assert_eq!(room.dominant_speaker(), Some(A));
+
+        // A's last level was speech, but it's stale once A stops sending
+        room.run(Duration::from_secs(3), &[(B, true)]);
+        assert_eq!(room.dominant_speaker(), Some(B));
+
+        // A is forgotten once it's been gone long enough
+        room.run(Duration::from_secs(10), &[(B, true)]);
+        assert!(!room.detector.activity.read().speakers.contains_key(&A));
+    }
+}
@@ -0,0 +1,694 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use data_pipeline_rs::{
+    data_handler::{DataObserver, SomeDataHandler},
+    handlers::static_demuxer::{ConditionalPath, StaticDemuxer},
+    node::NodeRef,
+    pipeline_builder::PipelineBuilder,
+};
+use rtp_parse::rtcp::rtcp_packet::SomeRtcpPacket;
+use rtp_parse::rtp::rtp_packet::read_rtp_packet;
+use tokio::sync::{
+    broadcast,
+    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+};
+use webrtc_srtp::config::Config;
+
+use crate::{
+    audio_silence_checker::{AudioLevels, AudioSilenceChecker, AUDIO_LEVEL_URI},
+    av_demuxer::AvDemuxer,
+    clock::Clock,
+    compound_rtcp_parser::CompoundRtcpParser,
+    discardable_discarder::DiscardableDiscarder,
+    error::{DroppedPackets, PipelineError, RecordDroppedPackets},
+    packet_cache::{PacketCache, PacketCacher},
+    packet_info::{looks_like_rtcp, looks_like_rtp, PacketInfo, SomePacket},
+    receive_statistics::{ReceiveStatistics, ReceiveStatisticsSnapshot},
+    rtcp_termination::{RtcpEvent, RtcpTermination},
+    rtp_parser::RtpParser,
+    rtx_handler::RtxHandler,
+    send_statistics::{SendStatistics, SendStatisticsTracker, StreamSendStatistics},
+    srtp::{
+        srtcp_decrypt::SrtcpDecrypt, srtcp_encrypt::SrtcpEncrypt, srtp_decrypt::SrtpDecrypt,
+        srtp_encrypt::SrtpEncrypt,
+    },
+    ssrc_learner::SsrcLearner,
+    stream_information_store::StreamInformationStore,
+    tcc_generator::{TccGenerator, TCC_URI},
+    timer::TimerScheduler,
+    util::{LiveStateReader, SharedData},
+};
+
+const TCC_FEEDBACK_INTERVAL: Duration = Duration::from_millis(100);
+/// See [`AudioSilenceChecker`]'s `silence_threshold`
+const SILENCE_THRESHOLD: u8 = 60;
+const SILENCE_HANGOVER: Duration = Duration::from_millis(500);
+const RTCP_EVENTS_CAPACITY: usize = 64;
+
+/// Where an [`Endpoint`]'s output goes
+pub struct EndpointOutputs {
+    /// Media received from the remote side, after it has been decrypted and parsed and silence
+    /// has been discarded
+    pub received_media: UnboundedSender<PacketInfo>,
+    /// Encrypted RTP and RTCP, ready to be sent to the remote side
+    pub outgoing: UnboundedSender<Vec<u8>>,
+}
+
+#[derive(Default)]
+struct TransportCounters {
+    packets_received: u64,
+    bytes_received: u64,
+    packets_sent: u64,
+    bytes_sent: u64,
+}
+
+/// A snapshot of everything an [`Endpoint`] has received and sent
+#[derive(Clone, Debug, Default)]
+pub struct EndpointStats {
+    /// Everything received, including RTCP and packets which were dropped
+    pub packets_received: u64,
+    pub bytes_received: u64,
+    /// Everything sent, including RTCP
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    pub receive_streams: ReceiveStatisticsSnapshot,
+    pub send_streams: HashMap<u32, StreamSendStatistics>,
+    /// Packets dropped by the pipelines, by the reason they were dropped
+    pub dropped_packets: DroppedPackets,
+}
+
+/// Copies each received media packet to the endpoint's `received_media` output.  The pipeline
+/// doesn't give up ownership of packets, so they have to be copied.
+struct MediaOutput {
+    received_media: UnboundedSender<PacketInfo>,
+    dropped_packets: SharedData<DroppedPackets>,
+}
+
+impl DataObserver<PacketInfo> for MediaOutput {
+    fn observe(&mut self, data: &PacketInfo) {
+        let copy = match data.packet {
+            SomePacket::AudioRtpPacket(ref rtp) => {
+                read_rtp_packet(rtp.buf().to_vec()).map(SomePacket::AudioRtpPacket)
+            }
+            SomePacket::VideoRtpPacket(ref rtp) => {
+                read_rtp_packet(rtp.buf().to_vec()).map(SomePacket::VideoRtpPacket)
+            }
+            ref packet => {
+                self.dropped_packets
+                    .write()
+                    .record(&PipelineError::unexpected_packet_type(
+                        "MediaOutput",
+                        packet,
+                    ));
+                return;
+            }
+        };
+        match copy {
+            Ok(packet) => {
+                let _ = self.received_media.send(PacketInfo {
+                    is_retransmission: data.is_retransmission,
+                    ..PacketInfo::new(packet, data.received_time)
+                });
+            }
+            Err(e) => self
+                .dropped_packets
+                .write()
+                .record(&PipelineError::parse_failure("rtp packet copy", e)),
+        }
+    }
+}
+
+impl From<MediaOutput> for SomeDataHandler<PacketInfo> {
+    fn from(value: MediaOutput) -> Self {
+        SomeDataHandler::Observer(Box::new(value))
+    }
+}
+
+/// Hands encrypted packets to the endpoint's `outgoing` output
+struct WireOutput {
+    outgoing: UnboundedSender<Vec<u8>>,
+    counters: SharedData<TransportCounters>,
+    dropped_packets: SharedData<DroppedPackets>,
+}
+
+impl DataObserver<PacketInfo> for WireOutput {
+    fn observe(&mut self, data: &PacketInfo) {
+        let SomePacket::UnparsedPacket(ref buf) = data.packet else {
+            self.dropped_packets
+                .write()
+                .record(&PipelineError::unexpected_packet_type(
+                    "WireOutput",
+                    &data.packet,
+                ));
+            return;
+        };
+        let mut counters = self.counters.write();
+        counters.packets_sent += 1;
+        counters.bytes_sent += buf.len() as u64;
+        let _ = self.outgoing.send(buf.clone());
+    }
+}
+
+impl From<WireOutput> for SomeDataHandler<PacketInfo> {
+    fn from(value: WireOutput) -> Self {
+        SomeDataHandler::Observer(Box::new(value))
+    }
+}
+
+/// A single participant: the stream information negotiated with it, its SRTP keys, and the
+/// pipelines for the media it sends us and the media we send it.
+///
+/// Incoming packets are handed to [`Endpoint::receive`] and go through RTP/RTCP demuxing,
+/// decryption, ssrc learning, RTX handling, parsing, TCC feedback generation, receive statistics
+/// and A/V demuxing (with silent audio being discarded) before coming out on
+/// [`EndpointOutputs::received_media`].  Packets to send are handed to [`Endpoint::send`], and come
+/// out encrypted on [`EndpointOutputs::outgoing`] along with any RTCP the endpoint generates.
+pub struct Endpoint {
+    id: String,
+    clock: Arc<dyn Clock>,
+    stream_information: SharedData<StreamInformationStore>,
+    receive_pipeline: NodeRef<PacketInfo>,
+    send_rtp_pipeline: NodeRef<PacketInfo>,
+    send_rtcp_pipeline: NodeRef<PacketInfo>,
+    /// RTCP generated by the receive pipeline (e.g. TCC feedback)
+    rtcp_rx: UnboundedReceiver<SomeRtcpPacket>,
+    rtcp_events: broadcast::Sender<RtcpEvent>,
+    counters: SharedData<TransportCounters>,
+    receive_statistics: LiveStateReader<ReceiveStatisticsSnapshot>,
+    send_statistics: SharedData<SendStatistics>,
+    audio_levels: LiveStateReader<AudioLevels>,
+    packet_cache: SharedData<PacketCache>,
+    dropped_packets: SharedData<DroppedPackets>,
+}
+
+impl Endpoint {
+    /// `local_ssrc` is used as the sender ssrc of the RTCP the endpoint generates.  The RTCP
+    /// generated from `timers` (e.g. TCC feedback once a burst of packets has ended) is sent by
+    /// [`Endpoint::send_pending_rtcp`].
+    pub fn new(
+        id: &str,
+        local_ssrc: u32,
+        srtp_config: Config,
+        timers: &TimerScheduler,
+        outputs: EndpointOutputs,
+    ) -> Self {
+        let clock = timers.clock();
+        let srtp_config = SharedData::new(srtp_config);
+        let stream_information = SharedData::new(StreamInformationStore::new());
+        let (rtcp_tx, rtcp_rx) = unbounded_channel();
+        let (rtcp_events, _) = broadcast::channel(RTCP_EVENTS_CAPACITY);
+        let counters = SharedData::new(TransportCounters::default());
+        let dropped_packets = SharedData::new(DroppedPackets::default());
+        let ssrc_learner = SsrcLearner::new(stream_information.clone(), dropped_packets.clone());
+        let mut store = stream_information.write();
+
+        let receive_statistics =
+            ReceiveStatistics::new(store.subscribe_to_pt_changes(), dropped_packets.clone());
+        let receive_statistics_reader = receive_statistics.reader();
+        let audio_silence_checker = AudioSilenceChecker::new(
+            store.subscribe_to_header_extension_id_change(AUDIO_LEVEL_URI.to_owned()),
+            SILENCE_THRESHOLD,
+            SILENCE_HANGOVER,
+        );
+        let audio_levels = audio_silence_checker.audio_levels();
+        let receive_pipeline = PipelineBuilder::new()
+            .demux(
+                "RTP/RTCP demuxer",
+                StaticDemuxer::new(vec![
+                    ConditionalPath {
+                        predicate: Box::new(looks_like_rtp),
+                        next: PipelineBuilder::new()
+                            .attach_handler(
+                                "rtp decrypt",
+                                RecordDroppedPackets::new(
+                                    SrtpDecrypt {
+                                        config: srtp_config.clone(),
+                                        contexts: HashMap::new(),
+                                    },
+                                    dropped_packets.clone(),
+                                ),
+                            )
+                            .attach_handler("ssrc learner", ssrc_learner)
+                            .attach_handler(
+                                "RTX handler",
+                                RecordDroppedPackets::new(
+                                    RtxHandler::new(store.subscribe_to_rtx_changes()),
+                                    dropped_packets.clone(),
+                                ),
+                            )
+                            .attach_handler("RTX padding discarder", DiscardableDiscarder)
+                            .attach_handler(
+                                "RTP parser",
+                                RecordDroppedPackets::new(
+                                    RtpParser::new(store.subscribe_to_pt_changes()),
+                                    dropped_packets.clone(),
+                                ),
+                            )
+                            .attach_handler(
+                                "TCC generator",
+                                TccGenerator::new(
+                                    store.subscribe_to_header_extension_id_change(
+                                        TCC_URI.to_owned(),
+                                    ),
+                                    local_ssrc,
+                                    TCC_FEEDBACK_INTERVAL,
+                                    rtcp_tx,
+                                    timers,
+                                    dropped_packets.clone(),
+                                ),
+                            )
+                            .attach_handler("receive statistics", receive_statistics)
+                            .demux(
+                                "A/V demuxer",
+                                AvDemuxer::new(
+                                    PipelineBuilder::new()
+                                        .attach_handler(
+                                            "audio silence checker",
+                                            RecordDroppedPackets::new(
+                                                audio_silence_checker,
+                                                dropped_packets.clone(),
+                                            ),
+                                        )
+                                        .attach_handler("audio discarder", DiscardableDiscarder)
+                                        .attach_handler(
+                                            "audio output",
+                                            MediaOutput {
+                                                received_media: outputs.received_media.clone(),
+                                                dropped_packets: dropped_packets.clone(),
+                                            },
+                                        )
+                                        .build(),
+                                    PipelineBuilder::new()
+                                        .attach_handler("video discarder", DiscardableDiscarder)
+                                        .attach_handler(
+                                            "video output",
+                                            MediaOutput {
+                                                received_media: outputs.received_media,
+                                                dropped_packets: dropped_packets.clone(),
+                                            },
+                                        )
+                                        .build(),
+                                ),
+                            )
+                            .build(),
+                    },
+                    ConditionalPath {
+                        predicate: Box::new(looks_like_rtcp),
+                        next: PipelineBuilder::new()
+                            .attach_handler(
+                                "rtcp decrypt",
+                                RecordDroppedPackets::new(
+                                    SrtcpDecrypt {
+                                        config: srtp_config.clone(),
+                                        contexts: HashMap::new(),
+                                    },
+                                    dropped_packets.clone(),
+                                ),
+                            )
+                            .attach_handler(
+                                "RTCP parser",
+                                RecordDroppedPackets::new(
+                                    CompoundRtcpParser,
+                                    dropped_packets.clone(),
+                                ),
+                            )
+                            .attach_handler(
+                                "RTCP termination",
+                                RtcpTermination::new(rtcp_events.clone(), dropped_packets.clone()),
+                            )
+                            .build(),
+                    },
+                ]),
+            )
+            .build();
+
+        let send_statistics = SharedData::new(SendStatistics::default());
+        let packet_cache = SharedData::new(PacketCache::default());
+        let send_rtp_pipeline = PipelineBuilder::new()
+            .attach_handler(
+                "send statistics",
+                SendStatisticsTracker::new(
+                    send_statistics.clone(),
+                    store.subscribe_to_pt_changes(),
+                    dropped_packets.clone(),
+                ),
+            )
+            .attach_handler(
+                "packet cache",
+                PacketCacher::new(packet_cache.clone(), dropped_packets.clone()),
+            )
+            .attach_handler(
+                "rtp encrypt",
+                RecordDroppedPackets::new(
+                    SrtpEncrypt {
+                        config: srtp_config.clone(),
+                        contexts: HashMap::new(),
+                    },
+                    dropped_packets.clone(),
+                ),
+            )
+            .attach_handler(
+                "rtp output",
+                WireOutput {
+                    outgoing: outputs.outgoing.clone(),
+                    counters: counters.clone(),
+                    dropped_packets: dropped_packets.clone(),
+                },
+            )
+            .build();
+        let send_rtcp_pipeline = PipelineBuilder::new()
+            .attach_handler(
+                "rtcp encrypt",
+                RecordDroppedPackets::new(
+                    SrtcpEncrypt {
+                        config: srtp_config,
+                        contexts: HashMap::new(),
+                    },
+                    dropped_packets.clone(),
+                ),
+            )
+            .attach_handler(
+                "rtcp output",
+                WireOutput {
+                    outgoing: outputs.outgoing,
+                    counters: counters.clone(),
+                    dropped_packets: dropped_packets.clone(),
+                },
+            )
+            .build();
+
+        drop(store);
+
+        Self {
+            id: id.to_owned(),
+            clock,
+            stream_information,
+            receive_pipeline,
+            send_rtp_pipeline,
+            send_rtcp_pipeline,
+            rtcp_rx,
+            rtcp_events,
+            counters,
+            receive_statistics: receive_statistics_reader,
+            send_statistics,
+            audio_levels,
+            packet_cache,
+            dropped_packets,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The stream information for this endpoint, e.g. to apply a negotiated session to
+    pub fn stream_information(&self) -> SharedData<StreamInformationStore> {
+        self.stream_information.clone()
+    }
+
+    /// Process a packet received from the remote side.  Any RTCP generated as a result (e.g.
+    /// TCC feedback) is sent straight away.
+    pub fn receive(&mut self, data: Vec<u8>) {
+        {
+            let mut counters = self.counters.write();
+            counters.packets_received += 1;
+            counters.bytes_received += data.len() as u64;
+        }
+        self.receive_pipeline
+            .process_data(PacketInfo::received_now(data, self.clock.as_ref()));
+        self.send_pending_rtcp();
+    }
+
+    /// Send any RTCP generated by the receive pipeline since the last call
+    pub fn send_pending_rtcp(&mut self) {
+        while let Ok(rtcp) = self.rtcp_rx.try_recv() {
+            self.send(PacketInfo::new(
+                SomePacket::RtcpPacket(rtcp),
+                self.clock.now(),
+            ));
+        }
+    }
+
+    /// Send a packet to the remote side.  RTP (e.g. from a [`Router`](crate::router::Router), or
+    /// unparsed retransmissions from a [`NackResponder`](crate::nack_responder::NackResponder))
+    /// goes through the send RTP pipeline and RTCP through the send RTCP pipeline.
+    pub fn send(&self, packet_info: PacketInfo) {
+        match packet_info.packet {
+            SomePacket::RtpPacket(_)
+            | SomePacket::AudioRtpPacket(_)
+            | SomePacket::VideoRtpPacket(_) => self.send_rtp_pipeline.process_data(packet_info),
+            SomePacket::UnparsedPacket(_) if looks_like_rtp(&packet_info) => {
+                self.send_rtp_pipeline.process_data(packet_info)
+            }
+            SomePacket::RtcpPacket(_) | SomePacket::UnparsedRtcpPacket(_) => {
+                self.send_rtcp_pipeline.process_data(packet_info)
+            }
+            ref packet => {
+                self.dropped_packets
+                    .write()
+                    .record(&PipelineError::unexpected_packet_type(
+                        "Endpoint::send",
+                        packet,
+                    ))
+            }
+        }
+    }
+
+    pub fn subscribe_to_rtcp_events(&self) -> broadcast::Receiver<RtcpEvent> {
+        self.rtcp_events.subscribe()
+    }
+
+    /// The smoothed levels of the audio received from this endpoint, e.g. for a
+    /// [`DominantSpeakerDetector`](crate::dominant_speaker::DominantSpeakerDetector)
+    pub fn audio_levels(&self) -> LiveStateReader<AudioLevels> {
+        self.audio_levels.clone()
+    }
+
+    /// The packets sent to this endpoint, for responding to nacks
+    pub fn packet_cache(&self) -> SharedData<PacketCache> {
+        self.packet_cache.clone()
+    }
+
+    pub fn stats(&self) -> EndpointStats {
+        let counters = self.counters.read();
+        EndpointStats {
+            packets_received: counters.packets_received,
+            bytes_received: counters.bytes_received,
+            packets_sent: counters.packets_sent,
+            bytes_sent: counters.bytes_sent,
+            receive_streams: self.receive_statistics.value().clone(),
+            send_streams: self
+                .send_statistics
+                .read()
+                .iter()
+                .map(|(ssrc, stats)| (*ssrc, stats.clone()))
+                .collect(),
+            dropped_packets: self.dropped_packets.read().clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bit_cursor::nsw_types::u7;
+    use webrtc_srtp::{config::SessionKeys, protection_profile::ProtectionProfile};
+
+    use crate::{
+        clock::ManualClock, error::PipelineErrorKind, nack_responder::NackResponder,
+        rtp_parser::MediaType, rtp_util::RtpPacketBuilder, stream_information_store::PayloadType,
+    };
+
+    use super::*;
+
+    struct TestEndpoint {
+        endpoint: Endpoint,
+        received_media: UnboundedReceiver<PacketInfo>,
+        outgoing: UnboundedReceiver<Vec<u8>>,
+    }
+
+    impl TestEndpoint {
+        /// `key` and `remote_key` are used for both the master key and salt
+        fn new(id: &str, key: u8, remote_key: u8, timers: &TimerScheduler) -> Self {
+            let profile = ProtectionProfile::Aes128CmHmacSha1_80;
+            let config = Config {
+                keys: SessionKeys {
+                    local_master_key: vec![key; profile.key_len()],
+                    local_master_salt: vec![key; profile.salt_len()],
+                    remote_master_key: vec![remote_key; profile.key_len()],
+                    remote_master_salt: vec![remote_key; profile.salt_len()],
+                },
+                profile,
+                ..Default::default()
+            };
+            let (received_media_tx, received_media) = unbounded_channel();
+            let (outgoing_tx, outgoing) = unbounded_channel();
+            let endpoint = Endpoint::new(
+                id,
+                key as u32,
+                config,
+                timers,
+                EndpointOutputs {
+                    received_media: received_media_tx,
+                    outgoing: outgoing_tx,
+                },
+            );
+            {
+                let stream_information = endpoint.stream_information();
+                let mut stream_information = stream_information.write();
+                stream_information.add_payload_type(PayloadType::new(
+                    u7::new(111),
+                    MediaType::Audio,
+                    "opus",
+                    48000,
+                ));
+                stream_information.add_payload_type(PayloadType::new(
+                    u7::new(96),
+                    MediaType::Video,
+                    "VP8",
+                    90000,
+                ));
+            }
+            Self {
+                endpoint,
+                received_media,
+                outgoing,
+            }
+        }
+
+        /// Deliver everything this endpoint has sent to `other`
+        fn send_to(&mut self, other: &mut TestEndpoint) {
+            while let Ok(data) = self.outgoing.try_recv() {
+                other.endpoint.receive(data);
+            }
+        }
+    }
+
+    fn rtp_packet(pt: u8, ssrc: u32, seq_num: u16, clock: &ManualClock) -> PacketInfo {
+        let rtp = RtpPacketBuilder::default()
+            .payload_type(pt)
+            .ssrc(ssrc)
+            .seq_num(seq_num)
+            .payload(&[0xAA, 0xBB, 0xCC, 0xDD])
+            .build_parsed();
+        PacketInfo::new(SomePacket::RtpPacket(rtp), clock.now())
+    }
+
+    #[test]
+    fn test_send_and_receive() {
+        let clock = Arc::new(ManualClock::new());
+        let timers = TimerScheduler::new(clock.clone());
+        let mut a = TestEndpoint::new("a", 0x11, 0x22, &timers);
+        let mut b = TestEndpoint::new("b", 0x22, 0x11, &timers);
+
+        a.endpoint.send(rtp_packet(111, 1234, 1, &clock));
+        a.endpoint.send(rtp_packet(96, 5678, 1, &clock));
+        a.endpoint.send(rtp_packet(96, 5678, 2, &clock));
+        a.send_to(&mut b);
+
+        let mut received = Vec::new();
+        while let Ok(packet_info) = b.received_media.try_recv() {
+            match packet_info.packet {
+                SomePacket::AudioRtpPacket(rtp) => received.push((rtp.ssrc(), false)),
+                SomePacket::VideoRtpPacket(rtp) => {
+                    assert_eq!(rtp.payload(), &[0xAA, 0xBB, 0xCC, 0xDD]);
+                    received.push((rtp.ssrc(), true))
+                }
+                packet => panic!("unexpected packet {packet}"),
+            }
+        }
+        assert_eq!(received, vec![(1234, false), (5678, true), (5678, true)]);
+
+        let a_stats = a.endpoint.stats();
+        assert_eq!(a_stats.packets_sent, 3);
+        assert_eq!(a_stats.send_streams[&1234].packet_count, 1);
+        assert_eq!(a_stats.send_streams[&5678].packet_count, 2);
+        assert!(a.endpoint.packet_cache().read().get(5678, 2).is_some());
+
+        let b_stats = b.endpoint.stats();
+        assert_eq!(b_stats.packets_received, 3);
+        assert_eq!(b_stats.bytes_received, a_stats.bytes_sent);
+        assert_eq!(b_stats.receive_streams.len(), 2);
+        assert_eq!(b_stats.packets_sent, 0);
+        assert_eq!(b_stats.dropped_packets.total(), 0);
+
+        // Packets which can't be sent are counted rather than sent
+        a.endpoint
+            .send(PacketInfo::new_unparsed(vec![0x00, 0x60], clock.now()));
+        assert_eq!(
+            a.endpoint
+                .stats()
+                .dropped_packets
+                .count(PipelineErrorKind::UnexpectedPacketType),
+            1
+        );
+    }
+
+    #[test]
+    fn test_receive_errors_counted() {
+        let clock = Arc::new(ManualClock::new());
+        let timers = TimerScheduler::new(clock.clone());
+        let mut a = TestEndpoint::new("a", 0x11, 0x22, &timers);
+        let mut b = TestEndpoint::new("b", 0x22, 0x11, &timers);
+        // Encrypts with keys b doesn't expect
+        let mut c = TestEndpoint::new("c", 0x22, 0x33, &timers);
+
+        a.endpoint.send(rtp_packet(100, 1234, 1, &clock));
+        a.send_to(&mut b);
+        c.endpoint.send(rtp_packet(96, 5678, 1, &clock));
+        c.send_to(&mut b);
+
+        assert!(b.received_media.try_recv().is_err());
+        let dropped_packets = b.endpoint.stats().dropped_packets;
+        assert_eq!(
+            dropped_packets.count(PipelineErrorKind::UnknownPayloadType),
+            1
+        );
+        assert_eq!(dropped_packets.count(PipelineErrorKind::AuthFailure), 1);
+        assert_eq!(dropped_packets.total(), 2);
+    }
+
+    #[test]
+    fn test_nack_retransmission() {
+        let clock = Arc::new(ManualClock::new());
+        let timers = TimerScheduler::new(clock.clone());
+        let mut a = TestEndpoint::new("a", 0x11, 0x22, &timers);
+        let mut b = TestEndpoint::new("b", 0x22, 0x11, &timers);
+        for endpoint in [&a.endpoint, &b.endpoint] {
+            let stream_information = endpoint.stream_information();
+            let mut stream_information = stream_information.write();
+            stream_information.add_rtx_payload_type(u7::new(97), u7::new(96));
+            stream_information.add_rtx_ssrc(5679, 5678);
+        }
+        let (retransmission_tx, mut retransmission_rx) = unbounded_channel();
+        let mut nack_responder = NackResponder::new(
+            a.endpoint.packet_cache(),
+            a.endpoint
+                .stream_information()
+                .read()
+                .subscribe_to_rtx_changes(),
+            retransmission_tx,
+            SharedData::new(DroppedPackets::default()),
+        );
+
+        a.endpoint.send(rtp_packet(96, 5678, 1, &clock));
+        a.endpoint.send(rtp_packet(96, 5678, 2, &clock));
+        a.send_to(&mut b);
+        while b.received_media.try_recv().is_ok() {}
+
+        // The retransmission is RTX encapsulated, and comes out of b as the original packet
+        nack_responder.handle_nack(5678, &[2]);
+        while let Ok(packet) = retransmission_rx.try_recv() {
+            a.endpoint.send(PacketInfo::new(packet, clock.now()));
+        }
+        a.send_to(&mut b);
+        let packet_info = b.received_media.try_recv().unwrap();
+        assert!(packet_info.is_retransmission);
+        let SomePacket::VideoRtpPacket(rtp) = packet_info.packet else {
+            panic!("unexpected packet {}", packet_info.packet);
+        };
+        assert_eq!((rtp.ssrc(), rtp.seq_num()), (5678, 2));
+        assert_eq!(rtp.payload(), &[0xAA, 0xBB, 0xCC, 0xDD]);
+        assert_eq!(a.endpoint.stats().dropped_packets.total(), 0);
+        assert_eq!(b.endpoint.stats().dropped_packets.total(), 0);
+    }
+}
@@ -0,0 +1,188 @@
+use std::{collections::HashMap, fmt::Display};
+
+use bit_cursor::nsw_types::u7;
+use data_pipeline_rs::data_handler::{DataTransformer, SomeDataHandler};
+
+use crate::{
+    packet_info::{PacketInfo, SomePacket},
+    util::SharedData,
+};
+
+/// Errors for packets that a handler in the pipeline can't process.  These are returned (wrapped
+/// in an [`anyhow::Error`]) from transformers so that the pipeline can drop the packet and carry
+/// on, rather than one bad packet from a remote peer taking everything down.  Callers interested
+/// in the specific failure can get at it via [`anyhow::Error::downcast_ref`].
+#[derive(Debug)]
+pub enum PipelineError {
+    /// A handler was given a [`SomePacket`] variant it doesn't handle
+    UnexpectedPacketType {
+        handler: &'static str,
+        packet_type: &'static str,
+    },
+    UnknownPayloadType(u7),
+    /// An RTX packet arrived on an ssrc which hasn't been paired with a primary stream
+    UnknownRtxSsrc(u32),
+    ParseFailure {
+        what: &'static str,
+        source: anyhow::Error,
+    },
+    AuthFailure {
+        ssrc: u32,
+    },
+    /// A packet that was already received was received again
+    Replay {
+        ssrc: u32,
+    },
+    /// Any other error from SRTP/SRTCP encryption or decryption
+    Srtp {
+        ssrc: u32,
+        source: webrtc_srtp::Error,
+    },
+}
+
+/// The kind of a [`PipelineError`], without any of its details
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PipelineErrorKind {
+    UnexpectedPacketType,
+    UnknownPayloadType,
+    UnknownRtxSsrc,
+    ParseFailure,
+    AuthFailure,
+    Replay,
+    Srtp,
+}
+
+impl PipelineError {
+    pub fn kind(&self) -> PipelineErrorKind {
+        match self {
+            PipelineError::UnexpectedPacketType { .. } => PipelineErrorKind::UnexpectedPacketType,
+            PipelineError::UnknownPayloadType(_) => PipelineErrorKind::UnknownPayloadType,
+            PipelineError::UnknownRtxSsrc(_) => PipelineErrorKind::UnknownRtxSsrc,
+            PipelineError::ParseFailure { .. } => PipelineErrorKind::ParseFailure,
+            PipelineError::AuthFailure { .. } => PipelineErrorKind::AuthFailure,
+            PipelineError::Replay { .. } => PipelineErrorKind::Replay,
+            PipelineError::Srtp { .. } => PipelineErrorKind::Srtp,
+        }
+    }
+
+    pub fn unexpected_packet_type(handler: &'static str, packet: &SomePacket) -> Self {
+        PipelineError::UnexpectedPacketType {
+            handler,
+            packet_type: packet.type_name(),
+        }
+    }
+
+    pub fn parse_failure<E: Into<anyhow::Error>>(what: &'static str, source: E) -> Self {
+        PipelineError::ParseFailure {
+            what,
+            source: source.into(),
+        }
+    }
+
+    /// Categorize an error from SRTP/SRTCP encryption or decryption
+    pub fn from_srtp(ssrc: u32, error: webrtc_srtp::Error) -> Self {
+        match error {
+            webrtc_srtp::Error::ErrFailedToVerifyAuthTag
+            | webrtc_srtp::Error::RtpFailedToVerifyAuthTag
+            | webrtc_srtp::Error::RtcpFailedToVerifyAuthTag => PipelineError::AuthFailure { ssrc },
+            webrtc_srtp::Error::ErrDuplicated
+            | webrtc_srtp::Error::SrtpSsrcDuplicated(..)
+            | webrtc_srtp::Error::SrtcpSsrcDuplicated(..) => PipelineError::Replay { ssrc },
+            source => PipelineError::Srtp { ssrc, source },
+        }
+    }
+}
+
+impl Display for PipelineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PipelineError::UnexpectedPacketType {
+                handler,
+                packet_type,
+            } => write!(f, "{handler} got unexpected packet type {packet_type}"),
+            PipelineError::UnknownPayloadType(pt) => write!(f, "unknown payload type {pt}"),
+            PipelineError::UnknownRtxSsrc(ssrc) => {
+                write!(f, "no primary ssrc found for rtx ssrc {ssrc}")
+            }
+            PipelineError::ParseFailure { what, source } => {
+                write!(f, "failed to parse {what}: {source:#}")
+            }
+            PipelineError::AuthFailure { ssrc } => {
+                write!(f, "authentication failed for packet from ssrc {ssrc}")
+            }
+            PipelineError::Replay { ssrc } => write!(f, "replayed packet from ssrc {ssrc}"),
+            PipelineError::Srtp { ssrc, source } => {
+                write!(f, "srtp error for ssrc {ssrc}: {source}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PipelineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PipelineError::ParseFailure { source, .. } => Some(source.as_ref()),
+            PipelineError::Srtp { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Counts of the packets dropped by a pipeline, by the kind of [`PipelineError`] that caused each
+/// drop.  Handlers which can't return an error to the pipeline (i.e. observers and filters) take a
+/// [`SharedData`] of this so that a single count can be kept for a whole pipeline, and
+/// transformers are wrapped in a [`RecordDroppedPackets`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DroppedPackets(HashMap<PipelineErrorKind, u64>);
+
+impl DroppedPackets {
+    pub fn record(&mut self, error: &PipelineError) {
+        *self.0.entry(error.kind()).or_default() += 1;
+    }
+
+    pub fn count(&self, kind: PipelineErrorKind) -> u64 {
+        self.0.get(&kind).copied().unwrap_or(0)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.0.values().sum()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (PipelineErrorKind, u64)> + '_ {
+        self.0.iter().map(|(kind, count)| (*kind, *count))
+    }
+}
+
+/// Wraps a transformer so that the [`PipelineError`]s it returns are recorded in a
+/// [`DroppedPackets`] before the pipeline drops the packet
+pub struct RecordDroppedPackets<T> {
+    transformer: T,
+    dropped_packets: SharedData<DroppedPackets>,
+}
+
+impl<T> RecordDroppedPackets<T> {
+    pub fn new(transformer: T, dropped_packets: SharedData<DroppedPackets>) -> Self {
+        Self {
+            transformer,
+            dropped_packets,
+        }
+    }
+}
+
+impl<T: DataTransformer<PacketInfo>> DataTransformer<PacketInfo> for RecordDroppedPackets<T> {
+    fn transform(&mut self, data: PacketInfo) -> anyhow::Result<PacketInfo> {
+        self.transformer.transform(data).inspect_err(|e| {
+            if let Some(error) = e.downcast_ref::<PipelineError>() {
+                self.dropped_packets.write().record(error);
+            }
+        })
+    }
+}
+
+impl<T: DataTransformer<PacketInfo> + 'static> From<RecordDroppedPackets<T>>
+    for SomeDataHandler<PacketInfo>
+{
+    fn from(value: RecordDroppedPackets<T>) -> Self {
+        SomeDataHandler::Transformer(Box::new(value))
+    }
+}
@@ -0,0 +1,371 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use data_pipeline_rs::data_handler::{DataObserver, SomeDataHandler};
+use rtp_parse::rtp::rtp_packet::{read_rtp_packet, RtpPacket};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{
+    error::{DroppedPackets, PipelineError},
+    packet_info::{PacketInfo, SomePacket},
+    receive_statistics::InterarrivalJitter,
+    rfc_3711_index::Rfc3711IndexTracker,
+    stream_information_store::PayloadTypes,
+    timer::{TimerHandle, TimerHandler, TimerScheduler},
+    util::{LiveStateReader, SharedData},
+};
+
+/// The target delay is this multiple of the measured jitter, which covers the large majority of
+/// arrival time variation without adding much latency.
+const JITTER_MULTIPLIER: f64 = 4.0;
+const MIN_TARGET_DELAY: Duration = Duration::from_millis(20);
+const MAX_TARGET_DELAY: Duration = Duration::from_millis(500);
+/// The most packets we'll hold for a single ssrc.  Past this the oldest packets are released
+/// early rather than growing the buffer.
+const MAX_BUFFERED_PACKETS: usize = 1000;
+
+/// A packet released from the [`JitterBuffer`]
+#[derive(Debug)]
+pub struct ReleasedPacket {
+    pub packet_info: PacketInfo,
+    /// How many packets on this ssrc were given up on immediately before this one
+    pub lost_before: u32,
+}
+
+struct BufferedPacket {
+    packet_info: PacketInfo,
+    release_time: Instant,
+}
+
+struct SsrcJitterBuffer {
+    index_tracker: Rfc3711IndexTracker,
+    packets: BTreeMap<u32, BufferedPacket>,
+    /// The index of the next packet to be released, once we've released one
+    next_index: Option<u32>,
+    jitter: InterarrivalJitter,
+}
+
+impl SsrcJitterBuffer {
+    fn new(now: Instant) -> Self {
+        Self {
+            index_tracker: Rfc3711IndexTracker::default(),
+            packets: BTreeMap::new(),
+            next_index: None,
+            jitter: InterarrivalJitter::new(now),
+        }
+    }
+
+    fn target_delay(&self) -> Duration {
+        Duration::from_secs_f64(self.jitter.jitter_secs() * JITTER_MULTIPLIER)
+            .clamp(MIN_TARGET_DELAY, MAX_TARGET_DELAY)
+    }
+
+    fn insert(&mut self, rtp_packet: &RtpPacket, packet_info: PacketInfo, clock_rate: u32) {
+        let now = packet_info.received_time;
+        // Retransmissions arrive late by design and shouldn't raise the target delay
+        if clock_rate != 0 && !packet_info.is_retransmission {
+            self.jitter.update(rtp_packet.timestamp(), clock_rate, now);
+        }
+        let index = self.index_tracker.update(rtp_packet.seq_num());
+        if self.next_index.is_some_and(|next_index| index < next_index) {
+            // Anything before this has already been released (or given up on), so it's too late
+            return;
+        }
+        let release_time = now + self.target_delay();
+        self.packets.entry(index).or_insert(BufferedPacket {
+            packet_info,
+            release_time,
+        });
+        if self.packets.len() > MAX_BUFFERED_PACKETS {
+            if let Some(mut first) = self.packets.first_entry() {
+                first.get_mut().release_time = now;
+            }
+        }
+    }
+
+    fn next_release_time(&self) -> Option<Instant> {
+        self.packets
+            .first_key_value()
+            .map(|(_, first)| first.release_time)
+    }
+
+    /// Release packets in order, as long as the first one has been held long enough.  Any gap
+    /// before a released packet is considered lost.
+    fn release(&mut self, now: Instant, released: &mut Vec<ReleasedPacket>) {
+        while let Some(entry) = self.packets.first_entry() {
+            if entry.get().release_time > now {
+                break;
+            }
+            let (index, packet) = entry.remove_entry();
+            let lost_before = self.next_index.map_or(0, |next_index| index - next_index);
+            self.next_index = Some(index + 1);
+            released.push(ReleasedPacket {
+                packet_info: packet.packet_info,
+                lost_before,
+            });
+        }
+    }
+}
+
+#[derive(Default)]
+struct JitterBufferState {
+    ssrcs: HashMap<u32, SsrcJitterBuffer>,
+}
+
+impl JitterBufferState {
+    fn next_release_time(&self) -> Option<Instant> {
+        self.ssrcs
+            .values()
+            .filter_map(SsrcJitterBuffer::next_release_time)
+            .min()
+    }
+
+    fn release(&mut self, now: Instant) -> Vec<ReleasedPacket> {
+        let mut released = Vec::new();
+        for state in self.ssrcs.values_mut() {
+            state.release(now, &mut released);
+        }
+        released
+    }
+}
+
+/// A receive-side jitter buffer for consumers which need packets in order and evenly paced (e.g.
+/// recording or transcoding).  This should be placed at the end of the audio or video path after
+/// the [`crate::av_demuxer::AvDemuxer`].  Packets are reordered by their extended sequence
+/// number and held for a target delay derived from the measured jitter, then released in order
+/// to `output_tx` by a [`TimerHandler`], so that they go out on time whether or not any more
+/// packets arrive.  Packets which arrive after a later packet has already been released are
+/// dropped, and gaps are reported via [`ReleasedPacket::lost_before`].
+pub struct JitterBuffer {
+    payload_types: LiveStateReader<PayloadTypes>,
+    state: SharedData<JitterBufferState>,
+    timer: TimerHandle,
+    dropped_packets: SharedData<DroppedPackets>,
+}
+
+impl JitterBuffer {
+    pub fn new(
+        payload_types: LiveStateReader<PayloadTypes>,
+        timers: &TimerScheduler,
+        output_tx: UnboundedSender<ReleasedPacket>,
+        dropped_packets: SharedData<DroppedPackets>,
+    ) -> Self {
+        let state = SharedData::default();
+        let timer = timers.register(JitterBufferReleaser {
+            state: state.clone(),
+            output_tx,
+        });
+        Self {
+            payload_types,
+            state,
+            timer,
+            dropped_packets,
+        }
+    }
+
+    fn packet_received(&mut self, data: &PacketInfo) -> Result<(), PipelineError> {
+        let rtp_packet = match data.packet {
+            SomePacket::AudioRtpPacket(ref rtp) | SomePacket::VideoRtpPacket(ref rtp) => rtp,
+            ref packet => {
+                return Err(PipelineError::unexpected_packet_type(
+                    "JitterBuffer",
+                    packet,
+                ))
+            }
+        };
+        let clock_rate = self
+            .payload_types
+            .value()
+            .clock_rate(&rtp_packet.payload_type())
+            .unwrap_or(0);
+        // The packet is only borrowed here, so the buffer holds its own copy
+        let copy = read_rtp_packet(rtp_packet.buf().to_vec())
+            .map_err(|e| PipelineError::parse_failure("rtp packet", e))?;
+        let packet = match data.packet {
+            SomePacket::AudioRtpPacket(_) => SomePacket::AudioRtpPacket(copy),
+            _ => SomePacket::VideoRtpPacket(copy),
+        };
+        let mut packet_info = PacketInfo::new(packet, data.received_time);
+        packet_info.is_retransmission = data.is_retransmission;
+
+        let mut state = self.state.write();
+        let ssrc_state = state
+            .ssrcs
+            .entry(rtp_packet.ssrc())
+            .or_insert_with(|| SsrcJitterBuffer::new(data.received_time));
+        ssrc_state.insert(rtp_packet, packet_info, clock_rate);
+        if let Some(release_time) = ssrc_state.next_release_time() {
+            self.timer.schedule(release_time);
+        }
+        Ok(())
+    }
+}
+
+impl DataObserver<PacketInfo> for JitterBuffer {
+    fn observe(&mut self, data: &PacketInfo) {
+        if let Err(e) = self.packet_received(data) {
+            self.dropped_packets.write().record(&e);
+        }
+    }
+}
+
+impl From<JitterBuffer> for SomeDataHandler<PacketInfo> {
+    fn from(value: JitterBuffer) -> Self {
+        SomeDataHandler::Observer(Box::new(value))
+    }
+}
+
+struct JitterBufferReleaser {
+    state: SharedData<JitterBufferState>,
+    output_tx: UnboundedSender<ReleasedPacket>,
+}
+
+impl TimerHandler for JitterBufferReleaser {
+    fn on_timer(&mut self, now: Instant) -> Option<Instant> {
+        let mut state = self.state.write();
+        for packet in state.release(now) {
+            let _ = self.output_tx.send(packet);
+        }
+        state.next_release_time()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bit_cursor::nsw_types::u7;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use crate::{
+        clock::{Clock, ManualClock},
+        rtp_parser::MediaType,
+        rtp_util::RtpPacketBuilder,
+        stream_information_store::{PayloadType, StreamInformationStore},
+    };
+
+    use super::*;
+
+    fn video_packet(seq_num: u16, timestamp: u32, received_time: Instant) -> PacketInfo {
+        let rtp = RtpPacketBuilder::default()
+            .seq_num(seq_num)
+            .timestamp(timestamp)
+            .build_parsed();
+        PacketInfo::new(SomePacket::VideoRtpPacket(rtp), received_time)
+    }
+
+    fn released_seq_nums(
+        rx: &mut tokio::sync::mpsc::UnboundedReceiver<ReleasedPacket>,
+    ) -> Vec<(u16, u32)> {
+        let mut released = Vec::new();
+        while let Ok(packet) = rx.try_recv() {
+            let SomePacket::VideoRtpPacket(ref rtp) = packet.packet_info.packet else {
+                panic!("expected video packet");
+            };
+            released.push((rtp.seq_num(), packet.lost_before));
+        }
+        released
+    }
+
+    fn create_jitter_buffer(
+        timers: &TimerScheduler,
+        output_tx: UnboundedSender<ReleasedPacket>,
+    ) -> JitterBuffer {
+        let mut store = StreamInformationStore::new();
+        store.add_payload_type(PayloadType::new(
+            u7::new(96),
+            MediaType::Video,
+            "VP8",
+            90000,
+        ));
+        JitterBuffer::new(
+            store.subscribe_to_pt_changes(),
+            timers,
+            output_tx,
+            SharedData::default(),
+        )
+    }
+
+    #[test]
+    fn test_reorder_and_loss() {
+        let clock = Arc::new(ManualClock::new());
+        let timers = TimerScheduler::new(clock.clone());
+        let (tx, mut rx) = unbounded_channel();
+        let mut jitter_buffer = create_jitter_buffer(&timers, tx);
+        let start = clock.now();
+
+        // All from the same frame, so the jitter stays low.  65535 arrives after 0, and 1 is lost
+        jitter_buffer.observe(&video_packet(65534, 0, start));
+        jitter_buffer.observe(&video_packet(0, 0, start));
+        jitter_buffer.observe(&video_packet(65535, 0, start + Duration::from_millis(5)));
+        jitter_buffer.observe(&video_packet(2, 0, start));
+
+        // Nothing is released until it's been held for the minimum delay
+        assert_eq!(timers.fire_due(), Some(start + MIN_TARGET_DELAY));
+        assert!(released_seq_nums(&mut rx).is_empty());
+
+        clock.advance(MIN_TARGET_DELAY);
+        // 0 and 2 have to wait for 65535 to be released
+        assert_eq!(
+            timers.fire_due(),
+            Some(start + Duration::from_millis(5) + MIN_TARGET_DELAY)
+        );
+        assert_eq!(released_seq_nums(&mut rx), vec![(65534, 0)]);
+
+        clock.advance(Duration::from_millis(5));
+        assert_eq!(timers.fire_due(), None);
+        assert_eq!(released_seq_nums(&mut rx), vec![(65535, 0), (0, 0), (2, 1)]);
+
+        // 1 shows up too late
+        jitter_buffer.observe(&video_packet(1, 0, clock.now()));
+        assert_eq!(timers.fire_due(), None);
+    }
+
+    #[test]
+    fn test_adaptive_delay() {
+        let mut state = SsrcJitterBuffer::new(Instant::now());
+        let start = Instant::now();
+        assert_eq!(state.target_delay(), MIN_TARGET_DELAY);
+
+        // Packets sent every 20ms arriving alternately 0 and 100ms late
+        for i in 0..100u32 {
+            let received_time = start + Duration::from_millis(20 * i as u64 + (i % 2) as u64 * 100);
+            let packet_info = video_packet(i as u16, i * 1800, received_time);
+            let SomePacket::VideoRtpPacket(ref rtp) = packet_info.packet else {
+                unreachable!();
+            };
+            let rtp = read_rtp_packet(rtp.buf().to_vec()).unwrap();
+            state.insert(&rtp, packet_info, 90000);
+        }
+        assert!((state.jitter.jitter_secs() - 0.1).abs() < 0.001);
+        assert_eq!(
+            state.target_delay(),
+            Duration::from_secs_f64(state.jitter.jitter_secs() * 4.0)
+        );
+    }
+
+    #[test]
+    fn test_timestamp_wrap() {
+        let start = Instant::now();
+        let mut state = SsrcJitterBuffer::new(start);
+
+        // Evenly spaced packets whose timestamps wrap part way through
+        let first_timestamp = u32::MAX - 10 * 1800;
+        for i in 0..20u32 {
+            let received_time = start + Duration::from_millis(20 * i as u64);
+            let packet_info = video_packet(
+                i as u16,
+                first_timestamp.wrapping_add(i * 1800),
+                received_time,
+            );
+            let SomePacket::VideoRtpPacket(ref rtp) = packet_info.packet else {
+                unreachable!();
+            };
+            let rtp = read_rtp_packet(rtp.buf().to_vec()).unwrap();
+            state.insert(&rtp, packet_info, 90000);
+        }
+        assert_eq!(state.target_delay(), MIN_TARGET_DELAY);
+    }
+}
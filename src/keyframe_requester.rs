@@ -0,0 +1,188 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use rtp_parse::rtcp::{
+    rtcp_fb_header::RtcpFbHeader, rtcp_fb_pli::RtcpFbPliPacket, rtcp_packet::SomeRtcpPacket,
+};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{
+    clock::Clock,
+    rtcp_writer::{new_rtcp_header, FMT_PLI, RTCP_PT_PSFB},
+    timer::{TimerHandle, TimerHandler, TimerScheduler},
+    util::SharedData,
+};
+
+struct SsrcRequests {
+    last_sent_time: Instant,
+    /// Whether a request came in too soon after the last one was sent, and needs to be sent once
+    /// the throttle interval has passed
+    pending: bool,
+}
+
+struct KeyframeRequestState {
+    sender_ssrc: u32,
+    min_interval: Duration,
+    rtcp_tx: UnboundedSender<SomeRtcpPacket>,
+    ssrcs: HashMap<u32, SsrcRequests>,
+}
+
+impl KeyframeRequestState {
+    /// Returns when the request will be sent, if it couldn't be sent straight away
+    fn request(&mut self, media_ssrc: u32, now: Instant) -> Option<Instant> {
+        match self.ssrcs.get_mut(&media_ssrc) {
+            Some(requests) if now.duration_since(requests.last_sent_time) < self.min_interval => {
+                requests.pending = true;
+                Some(requests.last_sent_time + self.min_interval)
+            }
+            _ => {
+                self.send_pli(media_ssrc);
+                self.ssrcs.insert(
+                    media_ssrc,
+                    SsrcRequests {
+                        last_sent_time: now,
+                        pending: false,
+                    },
+                );
+                None
+            }
+        }
+    }
+
+    fn send_pli(&self, media_ssrc: u32) {
+        let _ = self
+            .rtcp_tx
+            .send(SomeRtcpPacket::RtcpFbPliPacket(RtcpFbPliPacket {
+                header: new_rtcp_header(FMT_PLI, RTCP_PT_PSFB),
+                fb_header: RtcpFbHeader {
+                    sender_ssrc: self.sender_ssrc,
+                    media_source_ssrc: media_ssrc,
+                },
+            }));
+    }
+
+    fn next_request_time(&self) -> Option<Instant> {
+        self.ssrcs
+            .values()
+            .filter(|requests| requests.pending)
+            .map(|requests| requests.last_sent_time + self.min_interval)
+            .min()
+    }
+}
+
+impl TimerHandler for KeyframeRequestState {
+    fn on_timer(&mut self, now: Instant) -> Option<Instant> {
+        let due_ssrcs = self
+            .ssrcs
+            .iter()
+            .filter(|(_, requests)| {
+                requests.pending && now.duration_since(requests.last_sent_time) >= self.min_interval
+            })
+            .map(|(&ssrc, _)| ssrc)
+            .collect::<Vec<_>>();
+        for ssrc in due_ssrcs {
+            self.send_pli(ssrc);
+            self.ssrcs.insert(
+                ssrc,
+                SsrcRequests {
+                    last_sent_time: now,
+                    pending: false,
+                },
+            );
+        }
+        self.next_request_time()
+    }
+}
+
+/// Sends PLIs asking the sender of a stream for a keyframe (e.g. when a receiver has asked for
+/// one, or has switched layers), sending at most one per `min_interval` for each stream.  A
+/// keyframe takes a while to arrive and any of the receivers can ask for one, so without
+/// throttling the sender would be flooded with requests.  A request which comes in too soon after
+/// the previous one is held back and sent from a [`TimerHandler`] once the interval has passed,
+/// so it isn't lost if nothing else asks.
+pub struct KeyframeRequester {
+    clock: Arc<dyn Clock>,
+    state: SharedData<KeyframeRequestState>,
+    timer: TimerHandle,
+}
+
+impl KeyframeRequester {
+    pub fn new(
+        sender_ssrc: u32,
+        min_interval: Duration,
+        rtcp_tx: UnboundedSender<SomeRtcpPacket>,
+        timers: &TimerScheduler,
+    ) -> Self {
+        let state = SharedData::new(KeyframeRequestState {
+            sender_ssrc,
+            min_interval,
+            rtcp_tx,
+            ssrcs: HashMap::new(),
+        });
+        let timer = timers.register(state.clone());
+        Self {
+            clock: timers.clock(),
+            state,
+            timer,
+        }
+    }
+
+    pub fn request_keyframe(&self, media_ssrc: u32) {
+        let next_request_time = self.state.write().request(media_ssrc, self.clock.now());
+        if let Some(next_request_time) = next_request_time {
+            self.timer.schedule(next_request_time);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use crate::clock::ManualClock;
+
+    use super::*;
+
+    #[test]
+    fn test_requests_throttled() {
+        let clock = Arc::new(ManualClock::new());
+        let timers = TimerScheduler::new(clock.clone());
+        let (tx, mut rx) = unbounded_channel();
+        let requester = KeyframeRequester::new(1234, Duration::from_millis(300), tx, &timers);
+        let start = clock.now();
+
+        requester.request_keyframe(5678);
+        let Ok(SomeRtcpPacket::RtcpFbPliPacket(pli)) = rx.try_recv() else {
+            panic!("expected pli");
+        };
+        assert_eq!(pli.fb_header.sender_ssrc, 1234);
+        assert_eq!(pli.fb_header.media_source_ssrc, 5678);
+        // Other streams aren't affected
+        requester.request_keyframe(9012);
+        assert!(rx.try_recv().is_ok());
+
+        // Requests within the interval are held back and sent as one once it has passed
+        clock.advance(Duration::from_millis(100));
+        requester.request_keyframe(5678);
+        requester.request_keyframe(5678);
+        assert!(rx.try_recv().is_err());
+        assert_eq!(timers.fire_due(), Some(start + Duration::from_millis(300)));
+
+        clock.advance(Duration::from_millis(200));
+        assert_eq!(timers.fire_due(), None);
+        let Ok(SomeRtcpPacket::RtcpFbPliPacket(pli)) = rx.try_recv() else {
+            panic!("expected pli");
+        };
+        assert_eq!(pli.fb_header.media_source_ssrc, 5678);
+        assert!(rx.try_recv().is_err());
+
+        // The interval restarts from when the held back request was sent
+        clock.advance(Duration::from_millis(200));
+        requester.request_keyframe(5678);
+        assert!(rx.try_recv().is_err());
+        assert_eq!(timers.fire_due(), Some(start + Duration::from_millis(600)));
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::{HashMap, HashSet};
+
+use data_pipeline_rs::data_handler::{DataFilter, SomeDataHandler};
+
+use crate::{
+    packet_info::{PacketInfo, SomePacket},
+    util::{LiveStateReader, LiveStateWriter, SharedData},
+};
+
+/// The video ssrcs which should be forwarded to a receiver
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ForwardedSsrcs(HashSet<u32>);
+
+impl ForwardedSsrcs {
+    pub fn contains(&self, ssrc: u32) -> bool {
+        self.0.contains(&ssrc)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.0.iter().copied()
+    }
+}
+
+struct Endpoint {
+    audio_ssrcs: Vec<u32>,
+    video_ssrcs: Vec<u32>,
+}
+
+struct Receiver {
+    /// Overrides the default last n if set
+    last_n: Option<usize>,
+    pinned: Vec<String>,
+    on_stage: Option<String>,
+    forwarded: LiveStateWriter<ForwardedSsrcs>,
+}
+
+impl Receiver {
+    fn new() -> Self {
+        Self {
+            last_n: None,
+            pinned: Vec::new(),
+            on_stage: None,
+            forwarded: LiveStateWriter::new(ForwardedSsrcs::default()),
+        }
+    }
+}
+
+struct LastNState {
+    default_last_n: usize,
+    endpoints: HashMap<String, Endpoint>,
+    /// Endpoint ids, most recently active speaker first.  Endpoints which haven't spoken yet are
+    /// at the end, in the order they joined.
+    speaker_order: Vec<String>,
+    receivers: HashMap<String, Receiver>,
+}
+
+impl LastNState {
+    /// The endpoints whose video should be forwarded to `receiver_id`, in priority order
+    fn selected_endpoints<'a>(&'a self, receiver_id: &str, receiver: &'a Receiver) -> Vec<&'a str> {
+        let last_n = receiver.last_n.unwrap_or(self.default_last_n);
+        let mut selected: Vec<&str> = Vec::new();
+        let candidates = receiver
+            .on_stage
+            .iter()
+            .chain(receiver.pinned.iter())
+            .filter(|endpoint_id| self.endpoints.contains_key(*endpoint_id));
+        for endpoint_id in candidates {
+            // An endpoint's own video is never forwarded back to it
+            if endpoint_id != receiver_id && !selected.contains(&endpoint_id.as_str()) {
+                selected.push(endpoint_id);
+            }
+        }
+        for endpoint_id in &self.speaker_order {
+            if selected.len() >= last_n {
+                break;
+            }
+            if endpoint_id != receiver_id && !selected.contains(&endpoint_id.as_str()) {
+                selected.push(endpoint_id);
+            }
+        }
+        selected
+    }
+
+    fn update_receivers(&self) {
+        for (receiver_id, receiver) in &self.receivers {
+            let forwarded = ForwardedSsrcs(
+                self.selected_endpoints(receiver_id, receiver)
+                    .into_iter()
+                    .flat_map(|endpoint_id| self.endpoints[endpoint_id].video_ssrcs.iter())
+                    .copied()
+                    .collect(),
+            );
+            if *receiver.forwarded.value() != forwarded {
+                receiver.forwarded.set(forwarded);
+            }
+        }
+    }
+
+    fn speaker_active(&mut self, audio_ssrc: u32) {
+        let Some(endpoint_id) = self
+            .endpoints
+            .iter()
+            .find(|(_, endpoint)| endpoint.audio_ssrcs.contains(&audio_ssrc))
+            .map(|(endpoint_id, _)| endpoint_id.clone())
+        else {
+            return;
+        };
+        if self.speaker_order.first() == Some(&endpoint_id) {
+            return;
+        }
+        self.speaker_order.retain(|id| *id != endpoint_id);
+        self.speaker_order.insert(0, endpoint_id);
+        self.update_receivers();
+    }
+}
+
+/// Selects which endpoints' video is forwarded to each receiving endpoint: the N most recently
+/// active speakers, plus any endpoints the receiver has put on stage or pinned (which are always
+/// forwarded, even beyond N).  Speaker activity is signaled via [`LastNSelector::speaker_active`],
+/// e.g. from [`crate::dominant_speaker::DominantSpeakerDetector::on_speaker_change`].
+pub struct LastNSelector {
+    state: SharedData<LastNState>,
+}
+
+impl Clone for LastNSelector {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl LastNSelector {
+    pub fn new(default_last_n: usize) -> Self {
+        Self {
+            state: SharedData::new(LastNState {
+                default_last_n,
+                endpoints: HashMap::new(),
+                speaker_order: Vec::new(),
+                receivers: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Add an endpoint, or update the ssrcs of an existing one.  The video ssrcs should include
+    /// every simulcast layer and rtx ssrc, since all of them are forwarded.
+    pub fn add_endpoint(&self, endpoint_id: &str, audio_ssrcs: Vec<u32>, video_ssrcs: Vec<u32>) {
+        let mut state = self.state.write();
+        let endpoint = Endpoint {
+            audio_ssrcs,
+            video_ssrcs,
+        };
+        if state
+            .endpoints
+            .insert(endpoint_id.to_owned(), endpoint)
+            .is_none()
+        {
+            state.speaker_order.push(endpoint_id.to_owned());
+        }
+        state.update_receivers();
+    }
+
+    pub fn remove_endpoint(&self, endpoint_id: &str) {
+        let mut state = self.state.write();
+        state.endpoints.remove(endpoint_id);
+        state.speaker_order.retain(|id| id != endpoint_id);
+        state.receivers.remove(endpoint_id);
+        state.update_receivers();
+    }
+
+    /// Move the endpoint which sends `audio_ssrc` to the front of the speaker order
+    pub fn speaker_active(&self, audio_ssrc: u32) {
+        self.state.write().speaker_active(audio_ssrc);
+    }
+
+    pub fn set_last_n(&self, receiver_id: &str, last_n: usize) {
+        self.update_receiver(receiver_id, |receiver| receiver.last_n = Some(last_n));
+    }
+
+    pub fn set_pinned(&self, receiver_id: &str, pinned: Vec<String>) {
+        self.update_receiver(receiver_id, |receiver| receiver.pinned = pinned);
+    }
+
+    pub fn set_on_stage(&self, receiver_id: &str, on_stage: Option<String>) {
+        self.update_receiver(receiver_id, |receiver| receiver.on_stage = on_stage);
+    }
+
+    /// The video ssrcs to forward to `receiver_id`, for use with a [`LastNFilter`]
+    pub fn forwarded_video_ssrcs(&self, receiver_id: &str) -> LiveStateReader<ForwardedSsrcs> {
+        let mut state = self.state.write();
+        if !state.receivers.contains_key(receiver_id) {
+            state
+                .receivers
+                .insert(receiver_id.to_owned(), Receiver::new());
+            state.update_receivers();
+        }
+        state.receivers[receiver_id].forwarded.reader()
+    }
+
+    fn update_receiver<F: FnOnce(&mut Receiver)>(&self, receiver_id: &str, update: F) {
+        let mut state = self.state.write();
+        update(
+            state
+                .receivers
+                .entry(receiver_id.to_owned())
+                .or_insert_with(Receiver::new),
+        );
+        state.update_receivers();
+    }
+}
+
+/// Drops video packets which aren't selected to be forwarded to a receiver.  Audio is always
+/// forwarded.
+pub struct LastNFilter {
+    forwarded: LiveStateReader<ForwardedSsrcs>,
+}
+
+impl LastNFilter {
+    pub fn new(forwarded: LiveStateReader<ForwardedSsrcs>) -> Self {
+        Self { forwarded }
+    }
+}
+
+impl DataFilter<PacketInfo> for LastNFilter {
+    fn should_forward(&mut self, packet_info: &PacketInfo) -> bool {
+        match packet_info.packet {
+            SomePacket::VideoRtpPacket(ref rtp) => self.forwarded.value().contains(rtp.ssrc()),
+            _ => true,
+        }
+    }
+}
+
+impl From<LastNFilter> for SomeDataHandler<PacketInfo> {
+    fn from(value: LastNFilter) -> Self {
+        SomeDataHandler::Filter(Box::new(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::rtp_util::RtpPacketBuilder;
+
+    use super::*;
+
+    fn forwarded(reader: &LiveStateReader<ForwardedSsrcs>) -> Vec<u32> {
+        let mut ssrcs = reader.value().iter().collect::<Vec<_>>();
+        ssrcs.sort();
+        ssrcs
+    }
+
+    /// Endpoints "a" to "d", with audio ssrcs 1 to 4 and video ssrcs 10 to 40
+    fn create_selector() -> LastNSelector {
+        let selector = LastNSelector::new(2);
+        for (i, endpoint_id) in ["a", "b", "c", "d"].into_iter().enumerate() {
+            let i = i as u32 + 1;
+            selector.add_endpoint(endpoint_id, vec![i], vec![i * 10]);
+        }
+        selector
+    }
+
+    #[test]
+    fn test_last_n_follows_speakers() {
+        let selector = create_selector();
+        let to_a = selector.forwarded_video_ssrcs("a");
+        let to_d = selector.forwarded_video_ssrcs("d");
+
+        // Nobody has spoken yet, so the first to join are selected
+        assert_eq!(forwarded(&to_a), vec![20, 30]);
+        assert_eq!(forwarded(&to_d), vec![10, 20]);
+
+        selector.speaker_active(4);
+        assert_eq!(forwarded(&to_a), vec![20, 40]);
+        // An endpoint's own video is never forwarded back to it
+        assert_eq!(forwarded(&to_d), vec![10, 20]);
+
+        selector.speaker_active(3);
+        assert_eq!(forwarded(&to_a), vec![30, 40]);
+        assert_eq!(forwarded(&to_d), vec![10, 30]);
+
+        selector.set_last_n("a", 1);
+        assert_eq!(forwarded(&to_a), vec![30]);
+
+        selector.remove_endpoint("c");
+        assert_eq!(forwarded(&to_a), vec![40]);
+    }
+
+    #[test]
+    fn test_pinned_and_on_stage() {
+        let selector = create_selector();
+        let to_a = selector.forwarded_video_ssrcs("a");
+        selector.speaker_active(2);
+        selector.speaker_active(3);
+        assert_eq!(forwarded(&to_a), vec![20, 30]);
+
+        selector.set_pinned("a", vec![String::from("d")]);
+        assert_eq!(forwarded(&to_a), vec![30, 40]);
+
+        // On stage and pinned endpoints are forwarded even beyond last n
+        selector.set_on_stage("a", Some(String::from("b")));
+        selector.set_pinned("a", vec![String::from("c"), String::from("d")]);
+        assert_eq!(forwarded(&to_a), vec![20, 30, 40]);
+
+        selector.set_on_stage("a", None);
+        selector.set_pinned("a", Vec::new());
+        assert_eq!(forwarded(&to_a), vec![20, 30]);
+
+        // A receiver which pins or stages itself still gets last n other endpoints
+        selector.set_on_stage("a", Some(String::from("a")));
+        selector.set_pinned("a", vec![String::from("a"), String::from("d")]);
+        assert_eq!(forwarded(&to_a), vec![30, 40]);
+    }
+
+    #[test]
+    fn test_last_n_filter() {
+        let forwarded = LiveStateWriter::new(ForwardedSsrcs(HashSet::from([10])));
+        let mut filter = LastNFilter::new(forwarded.reader());
+        let packet = |ssrc: u32, video: bool| {
+            let rtp = RtpPacketBuilder::default().ssrc(ssrc).build_parsed();
+            let packet = if video {
+                SomePacket::VideoRtpPacket(rtp)
+            } else {
+                SomePacket::AudioRtpPacket(rtp)
+            };
+            PacketInfo::new(packet, Instant::now())
+        };
+
+        assert!(filter.should_forward(&packet(10, true)));
+        assert!(!filter.should_forward(&packet(20, true)));
+        assert!(filter.should_forward(&packet(20, false)));
+    }
+}
@@ -1,15 +1,40 @@
 pub mod audio_silence_checker;
 pub mod av_demuxer;
+pub mod clock;
 pub mod compound_rtcp_parser;
 pub mod discardable_discarder;
+pub mod dominant_speaker;
+pub mod endpoint;
+pub mod error;
+pub mod jitter_buffer;
+pub mod keyframe_requester;
+pub mod last_n;
+pub mod nack_generator;
+pub mod nack_responder;
+pub mod packet_cache;
 pub mod packet_info;
 pub mod packet_logger;
+pub mod receive_statistics;
 pub mod rfc_3711_index;
+pub mod router;
+pub mod rr_generator;
+pub mod rtcp_scheduler;
 pub mod rtcp_termination;
+pub mod rtcp_writer;
+pub mod rtcp_xr;
 pub mod rtp_parser;
+pub mod rtp_util;
+pub mod rtt_estimator;
+pub mod rtx;
+pub mod rtx_handler;
+pub mod sdp;
+pub mod send_statistics;
+pub mod sr_generator;
 pub mod srtp;
+pub mod ssrc_learner;
 pub mod stream_information_store;
 pub mod tcc_generator;
+pub mod timer;
 pub mod util;
 
 pub use data_pipeline_rs::{
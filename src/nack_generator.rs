@@ -0,0 +1,380 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use data_pipeline_rs::data_handler::{DataObserver, SomeDataHandler};
+use rtp_parse::rtcp::{
+    rtcp_fb_header::RtcpFbHeader, rtcp_fb_nack::RtcpFbNackPacket, rtcp_packet::SomeRtcpPacket,
+};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{
+    error::{DroppedPackets, PipelineError},
+    packet_info::{PacketInfo, SomePacket},
+    rfc_3711_index::Rfc3711IndexTracker,
+    rtcp_writer::{new_rtcp_header, FMT_NACK, RTCP_PT_RTPFB},
+    timer::{TimerHandle, TimerHandler, TimerScheduler},
+    util::{LiveStateReader, SharedData},
+};
+
+/// How many times we'll request a single packet before giving up on it
+const MAX_RETRIES: u32 = 10;
+/// The most missing packets we'll track for a single ssrc.  Loss beyond this is better handled by
+/// a keyframe than retransmissions.
+const MAX_MISSING_PACKETS: u32 = 1000;
+/// How long to wait before requesting a packet the first time, to give packets that were
+/// reordered in the network a chance to arrive
+const REORDERING_DELAY: Duration = Duration::from_millis(10);
+/// The lower bound on the time between retries, used when the rtt is very small (or unknown)
+const MIN_RETRY_INTERVAL: Duration = Duration::from_millis(20);
+/// The most we'll back off between retries, as a multiple of the retry interval
+const MAX_BACKOFF_MULTIPLIER: u32 = 16;
+/// If we haven't received anything on an ssrc for this long its state will be discarded
+const STALE_SSRC_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct MissingPacket {
+    retries: u32,
+    next_nack_time: Instant,
+}
+
+struct SsrcNackState {
+    index_tracker: Rfc3711IndexTracker,
+    highest_index: Option<u32>,
+    /// Packets we haven't received yet, keyed by their RFC 3711 index
+    missing_packets: BTreeMap<u32, MissingPacket>,
+    last_packet_time: Instant,
+}
+
+impl SsrcNackState {
+    fn new(now: Instant) -> Self {
+        Self {
+            index_tracker: Rfc3711IndexTracker::default(),
+            highest_index: None,
+            missing_packets: BTreeMap::new(),
+            last_packet_time: now,
+        }
+    }
+
+    fn packet_received(&mut self, seq_num: u16, now: Instant) {
+        self.last_packet_time = now;
+        let index = self.index_tracker.update(seq_num);
+        match self.highest_index {
+            Some(highest_index) if index > highest_index => {
+                let first_missing =
+                    (highest_index + 1).max(index.saturating_sub(MAX_MISSING_PACKETS));
+                for missing_index in first_missing..index {
+                    self.missing_packets.insert(
+                        missing_index,
+                        MissingPacket {
+                            retries: 0,
+                            next_nack_time: now + REORDERING_DELAY,
+                        },
+                    );
+                }
+                while self.missing_packets.len() > MAX_MISSING_PACKETS as usize {
+                    self.missing_packets.pop_first();
+                }
+                self.highest_index = Some(index);
+            }
+            Some(_) => {
+                // A packet that arrived late or was retransmitted, either way it's no longer
+                // missing
+                self.missing_packets.remove(&index);
+            }
+            None => self.highest_index = Some(index),
+        }
+    }
+
+    /// Get the sequence numbers which are due to be requested, updating their retry state.
+    fn take_due_seq_nums(&mut self, now: Instant, retry_interval: Duration) -> Vec<u16> {
+        let mut seq_nums = Vec::new();
+        self.missing_packets.retain(|index, missing_packet| {
+            if missing_packet.next_nack_time > now {
+                return true;
+            }
+            if missing_packet.retries >= MAX_RETRIES {
+                return false;
+            }
+            missing_packet.retries += 1;
+            let backoff = (1 << (missing_packet.retries - 1)).min(MAX_BACKOFF_MULTIPLIER);
+            missing_packet.next_nack_time = now + retry_interval * backoff;
+            seq_nums.push(*index as u16);
+            true
+        });
+        seq_nums
+    }
+}
+
+struct NackState {
+    sender_ssrc: u32,
+    rtt: LiveStateReader<Duration>,
+    nack_tx: UnboundedSender<SomeRtcpPacket>,
+    ssrcs: HashMap<u32, SsrcNackState>,
+}
+
+impl NackState {
+    fn new(
+        sender_ssrc: u32,
+        rtt: LiveStateReader<Duration>,
+        nack_tx: UnboundedSender<SomeRtcpPacket>,
+    ) -> Self {
+        Self {
+            sender_ssrc,
+            rtt,
+            nack_tx,
+            ssrcs: HashMap::new(),
+        }
+    }
+
+    fn packet_received(&mut self, ssrc: u32, seq_num: u16, now: Instant) {
+        self.ssrcs
+            .entry(ssrc)
+            .or_insert_with(|| SsrcNackState::new(now))
+            .packet_received(seq_num, now);
+    }
+
+    fn remove_stale_ssrcs(&mut self, now: Instant) {
+        self.ssrcs
+            .retain(|_, state| now.duration_since(state.last_packet_time) < STALE_SSRC_TIMEOUT);
+    }
+
+    fn next_nack_time(&self) -> Option<Instant> {
+        self.ssrcs
+            .values()
+            .flat_map(|state| state.missing_packets.values())
+            .map(|missing_packet| missing_packet.next_nack_time)
+            .min()
+    }
+
+    fn build_nacks(&mut self, now: Instant) -> Vec<RtcpFbNackPacket> {
+        let retry_interval = (*self.rtt.value()).max(MIN_RETRY_INTERVAL);
+        let mut nacks = Vec::new();
+        for (ssrc, state) in self.ssrcs.iter_mut() {
+            let missing_seq_nums = state.take_due_seq_nums(now, retry_interval);
+            if !missing_seq_nums.is_empty() {
+                nacks.push(RtcpFbNackPacket {
+                    header: new_rtcp_header(FMT_NACK, RTCP_PT_RTPFB),
+                    fb_header: RtcpFbHeader {
+                        sender_ssrc: self.sender_ssrc,
+                        media_source_ssrc: *ssrc,
+                    },
+                    missing_seq_nums,
+                });
+            }
+        }
+        nacks
+    }
+}
+
+impl TimerHandler for NackState {
+    fn on_timer(&mut self, now: Instant) -> Option<Instant> {
+        self.remove_stale_ssrcs(now);
+        for nack in self.build_nacks(now) {
+            let _ = self.nack_tx.send(SomeRtcpPacket::RtcpFbNackPacket(nack));
+        }
+        self.next_nack_time()
+    }
+}
+
+/// Detects gaps in the sequence numbers of incoming RTP streams and requests retransmission of
+/// the missing packets.  Requests are sent from a [`TimerHandler`], so retries go out on time
+/// even if no more packets arrive.  Retries are spaced according to the current rtt.
+pub struct NackGenerator {
+    state: SharedData<NackState>,
+    timer: TimerHandle,
+    dropped_packets: SharedData<DroppedPackets>,
+}
+
+impl NackGenerator {
+    pub fn new(
+        sender_ssrc: u32,
+        rtt: LiveStateReader<Duration>,
+        nack_tx: UnboundedSender<SomeRtcpPacket>,
+        timers: &TimerScheduler,
+        dropped_packets: SharedData<DroppedPackets>,
+    ) -> Self {
+        let state = SharedData::new(NackState::new(sender_ssrc, rtt, nack_tx));
+        let timer = timers.register(state.clone());
+        Self {
+            state,
+            timer,
+            dropped_packets,
+        }
+    }
+}
+
+impl DataObserver<PacketInfo> for NackGenerator {
+    fn observe(&mut self, data: &PacketInfo) {
+        let rtp_packet = match data.packet {
+            SomePacket::AudioRtpPacket(ref rtp) => rtp,
+            SomePacket::VideoRtpPacket(ref rtp) => rtp,
+            ref packet => {
+                self.dropped_packets
+                    .write()
+                    .record(&PipelineError::unexpected_packet_type(
+                        "NackGenerator",
+                        packet,
+                    ));
+                return;
+            }
+        };
+        let mut state = self.state.write();
+        state.packet_received(rtp_packet.ssrc(), rtp_packet.seq_num(), data.received_time);
+        if let Some(next_nack_time) = state.next_nack_time() {
+            self.timer.schedule(next_nack_time);
+        }
+    }
+}
+
+impl From<NackGenerator> for SomeDataHandler<PacketInfo> {
+    fn from(value: NackGenerator) -> Self {
+        SomeDataHandler::Observer(Box::new(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use crate::{
+        clock::{Clock, ManualClock},
+        rtp_util::RtpPacketBuilder,
+        util::LiveStateWriter,
+    };
+
+    use super::*;
+
+    fn create_generator(rtt: &LiveStateWriter<Duration>) -> NackState {
+        let (tx, _rx) = unbounded_channel();
+        NackState::new(1234, rtt.reader(), tx)
+    }
+
+    fn nacked_seq_nums(generator: &mut NackState, now: Instant) -> Vec<u16> {
+        generator
+            .build_nacks(now)
+            .into_iter()
+            .flat_map(|nack| nack.missing_seq_nums)
+            .collect()
+    }
+
+    #[test]
+    fn test_nack_gap() {
+        let rtt = LiveStateWriter::new(Duration::from_millis(50));
+        let mut generator = create_generator(&rtt);
+        let start = Instant::now();
+
+        generator.packet_received(5678, 10, start);
+        generator.packet_received(5678, 14, start);
+        // Nothing is requested until the reordering delay has passed
+        assert!(nacked_seq_nums(&mut generator, start).is_empty());
+
+        let now = start + REORDERING_DELAY;
+        let nacks = generator.build_nacks(now);
+        assert_eq!(nacks.len(), 1);
+        assert_eq!(nacks[0].fb_header.sender_ssrc, 1234);
+        assert_eq!(nacks[0].fb_header.media_source_ssrc, 5678);
+        assert_eq!(nacks[0].missing_seq_nums, vec![11, 12, 13]);
+        // Nothing more is due until an rtt has passed
+        assert!(nacked_seq_nums(&mut generator, now + Duration::from_millis(49)).is_empty());
+
+        // 12 shows up
+        generator.packet_received(5678, 12, now);
+        assert_eq!(
+            nacked_seq_nums(&mut generator, now + Duration::from_millis(50)),
+            vec![11, 13]
+        );
+    }
+
+    #[test]
+    fn test_nack_reordering() {
+        let rtt = LiveStateWriter::new(Duration::from_millis(50));
+        let mut generator = create_generator(&rtt);
+        let start = Instant::now();
+
+        generator.packet_received(5678, 65534, start);
+        generator.packet_received(5678, 1, start);
+        // 65535 and 0 arrive late, but within the reordering delay
+        generator.packet_received(5678, 0, start + Duration::from_millis(2));
+        generator.packet_received(5678, 65535, start + Duration::from_millis(3));
+
+        assert!(nacked_seq_nums(&mut generator, start + REORDERING_DELAY).is_empty());
+    }
+
+    #[test]
+    fn test_nack_backoff_and_max_retries() {
+        let rtt = LiveStateWriter::new(Duration::from_millis(100));
+        let mut generator = create_generator(&rtt);
+        let start = Instant::now();
+
+        generator.packet_received(5678, 1, start);
+        generator.packet_received(5678, 3, start);
+
+        let mut now = start + REORDERING_DELAY;
+        let mut nack_times = Vec::new();
+        while now < start + Duration::from_secs(30) {
+            if !nacked_seq_nums(&mut generator, now).is_empty() {
+                nack_times.push(now.duration_since(start).as_millis());
+            }
+            now += Duration::from_millis(10);
+        }
+        assert_eq!(
+            nack_times,
+            vec![10, 110, 310, 710, 1510, 3110, 4710, 6310, 7910, 9510]
+        );
+    }
+
+    #[test]
+    fn test_stale_ssrc_removed() {
+        let rtt = LiveStateWriter::new(Duration::from_millis(50));
+        let mut generator = create_generator(&rtt);
+        let start = Instant::now();
+
+        generator.packet_received(5678, 1, start);
+        generator.packet_received(5678, 3, start);
+        generator.remove_stale_ssrcs(start + STALE_SSRC_TIMEOUT);
+        assert!(generator.ssrcs.is_empty());
+        assert!(nacked_seq_nums(&mut generator, start + STALE_SSRC_TIMEOUT).is_empty());
+    }
+
+    #[test]
+    fn test_nacks_sent_without_more_packets() {
+        let clock = Arc::new(ManualClock::new());
+        let timers = TimerScheduler::new(clock.clone());
+        let rtt = LiveStateWriter::new(Duration::from_millis(50));
+        let (tx, mut rx) = unbounded_channel();
+        let mut generator =
+            NackGenerator::new(1234, rtt.reader(), tx, &timers, SharedData::default());
+        let start = clock.now();
+
+        for seq_num in [10, 12] {
+            let rtp = RtpPacketBuilder::default()
+                .ssrc(5678)
+                .seq_num(seq_num)
+                .build_parsed();
+            generator.observe(&PacketInfo::new(SomePacket::VideoRtpPacket(rtp), start));
+        }
+        assert_eq!(timers.fire_due(), Some(start + REORDERING_DELAY));
+        assert!(rx.try_recv().is_err());
+
+        clock.advance(REORDERING_DELAY);
+        assert_eq!(
+            timers.fire_due(),
+            Some(clock.now() + Duration::from_millis(50))
+        );
+        let Ok(SomeRtcpPacket::RtcpFbNackPacket(nack)) = rx.try_recv() else {
+            panic!("expected nack");
+        };
+        assert_eq!(nack.missing_seq_nums, vec![11]);
+
+        // And the retry goes out an rtt later
+        clock.advance(Duration::from_millis(50));
+        timers.fire_due();
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(SomeRtcpPacket::RtcpFbNackPacket(_))
+        ));
+    }
+}
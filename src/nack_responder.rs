@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    mpsc::UnboundedSender,
+};
+
+use crate::{
+    error::DroppedPackets,
+    packet_cache::PacketCache,
+    packet_info::SomePacket,
+    rtcp_termination::RtcpEvent,
+    rtp_util, rtx,
+    stream_information_store::RtxInfo,
+    util::{LiveStateReader, LiveStateWriter, SharedData},
+};
+
+/// Answers NACKs from the remote side by resending the requested packets from the
+/// [`PacketCache`].  If an RTX stream has been signaled for the NACKed stream then the packets are
+/// sent RTX encapsulated, otherwise they're sent as-is.
+pub struct NackResponder {
+    packet_cache: SharedData<PacketCache>,
+    rtx_info: LiveStateReader<RtxInfo>,
+    /// The next sequence number to use for each RTX ssrc
+    rtx_seq_nums: HashMap<u32, u16>,
+    retransmission_tx: UnboundedSender<SomePacket>,
+    dropped_packets: SharedData<DroppedPackets>,
+    lagged_events: LiveStateWriter<u64>,
+}
+
+impl NackResponder {
+    pub fn new(
+        packet_cache: SharedData<PacketCache>,
+        rtx_info: LiveStateReader<RtxInfo>,
+        retransmission_tx: UnboundedSender<SomePacket>,
+        dropped_packets: SharedData<DroppedPackets>,
+    ) -> Self {
+        Self {
+            packet_cache,
+            rtx_info,
+            rtx_seq_nums: HashMap::new(),
+            retransmission_tx,
+            dropped_packets,
+            lagged_events: LiveStateWriter::new(0),
+        }
+    }
+
+    /// How many [`RtcpEvent`]s [`NackResponder::run`] has missed by falling behind, any of which
+    /// may have been a NACK that went unanswered
+    pub fn lagged_events(&self) -> LiveStateReader<u64> {
+        self.lagged_events.reader()
+    }
+
+    /// Handle [`RtcpEvent`]s until the sender side of `events` is closed.
+    pub async fn run(mut self, mut events: Receiver<RtcpEvent>) {
+        loop {
+            match events.recv().await {
+                Ok(RtcpEvent::Nack {
+                    media_source_ssrc,
+                    missing_seq_nums,
+                    ..
+                }) => self.handle_nack(media_source_ssrc, &missing_seq_nums),
+                Ok(_) => {}
+                Err(RecvError::Lagged(num_missed)) => {
+                    self.lagged_events.modify(|lagged| *lagged += num_missed)
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+
+    pub fn handle_nack(&mut self, media_ssrc: u32, missing_seq_nums: &[u16]) {
+        let packet_cache = self.packet_cache.read();
+        let rtx_info = self.rtx_info.value();
+        let rtx_ssrc = rtx_info.rtx_ssrc(media_ssrc);
+        for &seq_num in missing_seq_nums {
+            let Some(packet) = packet_cache.get(media_ssrc, seq_num) else {
+                continue;
+            };
+            let rtx_pt = rtx_info.rtx_payload_type(rtp_util::payload_type(packet));
+            let retransmission = match (rtx_ssrc, rtx_pt) {
+                (Some(rtx_ssrc), Some(rtx_pt)) => {
+                    let rtx_seq_num = self.rtx_seq_nums.entry(rtx_ssrc).or_default();
+                    let rtx_packet = match rtx::encapsulate(packet, rtx_ssrc, rtx_pt, *rtx_seq_num)
+                    {
+                        Ok(rtx_packet) => rtx_packet,
+                        Err(e) => {
+                            self.dropped_packets.write().record(&e);
+                            continue;
+                        }
+                    };
+                    *rtx_seq_num = rtx_seq_num.wrapping_add(1);
+                    rtx_packet
+                }
+                _ => packet.to_vec(),
+            };
+            let _ = self
+                .retransmission_tx
+                .send(SomePacket::UnparsedPacket(retransmission));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bit_cursor::nsw_types::u7;
+    use rtp_parse::rtp::rtp_header::RtpHeader;
+    use tokio::sync::{broadcast, mpsc::unbounded_channel};
+
+    use crate::{
+        error::PipelineErrorKind, rtp_util::RtpPacketBuilder,
+        stream_information_store::StreamInformationStore,
+    };
+
+    use super::*;
+
+    fn packet(ssrc: u32, seq_num: u16) -> Vec<u8> {
+        RtpPacketBuilder::default()
+            .ssrc(ssrc)
+            .seq_num(seq_num)
+            .build()
+    }
+
+    #[test]
+    fn test_plain_retransmission() {
+        let store = StreamInformationStore::new();
+        let cache = SharedData::new(PacketCache::default());
+        cache.write().insert(1234, 10, packet(1234, 10));
+        let (tx, mut rx) = unbounded_channel();
+        let mut responder = NackResponder::new(
+            cache,
+            store.subscribe_to_rtx_changes(),
+            tx,
+            SharedData::new(DroppedPackets::default()),
+        );
+
+        responder.handle_nack(1234, &[9, 10]);
+
+        match rx.try_recv().unwrap() {
+            SomePacket::UnparsedPacket(data) => assert_eq!(data, packet(1234, 10)),
+            p => panic!("unexpected packet {p:?}"),
+        }
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_rtx_retransmission() {
+        let mut store = StreamInformationStore::new();
+        store.add_rtx_payload_type(u7::new(97), u7::new(96));
+        store.add_rtx_ssrc(5678, 1234);
+        let cache = SharedData::new(PacketCache::default());
+        cache.write().insert(1234, 10, packet(1234, 10));
+        cache.write().insert(1234, 11, packet(1234, 11));
+        let (tx, mut rx) = unbounded_channel();
+        let mut responder = NackResponder::new(
+            cache,
+            store.subscribe_to_rtx_changes(),
+            tx,
+            SharedData::new(DroppedPackets::default()),
+        );
+
+        responder.handle_nack(1234, &[10, 11]);
+
+        for (expected_rtx_seq_num, expected_osn) in [(0u16, 10u16), (1, 11)] {
+            match rx.try_recv().unwrap() {
+                SomePacket::UnparsedPacket(data) => {
+                    assert_eq!(RtpHeader::ssrc(&data), 5678);
+                    assert_eq!(rtp_util::payload_type(&data), u7::new(97));
+                    assert_eq!(rtp_util::seq_num(&data), expected_rtx_seq_num);
+                    assert_eq!(data[12..14], expected_osn.to_be_bytes());
+                    assert_eq!(data[14..], [0xAA, 0xBB]);
+                }
+                p => panic!("unexpected packet {p:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_rtx_encapsulation_failure_counted() {
+        let mut store = StreamInformationStore::new();
+        store.add_rtx_payload_type(u7::new(97), u7::new(96));
+        store.add_rtx_ssrc(5678, 1234);
+        let cache = SharedData::new(PacketCache::default());
+        // Claims to have a header extension which isn't there
+        let mut truncated = packet(1234, 10);
+        truncated[0] |= 0x10;
+        truncated.truncate(12);
+        cache.write().insert(1234, 10, truncated);
+        let (tx, mut rx) = unbounded_channel();
+        let dropped_packets = SharedData::new(DroppedPackets::default());
+        let mut responder = NackResponder::new(
+            cache,
+            store.subscribe_to_rtx_changes(),
+            tx,
+            dropped_packets.clone(),
+        );
+
+        responder.handle_nack(1234, &[10]);
+
+        assert!(rx.try_recv().is_err());
+        assert_eq!(
+            dropped_packets
+                .read()
+                .count(PipelineErrorKind::ParseFailure),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_counts_lagged_events() {
+        let store = StreamInformationStore::new();
+        let cache = SharedData::new(PacketCache::default());
+        let (tx, _rx) = unbounded_channel();
+        let responder = NackResponder::new(
+            cache,
+            store.subscribe_to_rtx_changes(),
+            tx,
+            SharedData::new(DroppedPackets::default()),
+        );
+        let lagged_events = responder.lagged_events();
+        let (events_tx, events_rx) = broadcast::channel(1);
+
+        for _ in 0..3 {
+            events_tx
+                .send(RtcpEvent::Nack {
+                    sender_ssrc: 42,
+                    media_source_ssrc: 1234,
+                    missing_seq_nums: vec![10],
+                })
+                .unwrap();
+        }
+        drop(events_tx);
+        responder.run(events_rx).await;
+        assert_eq!(*lagged_events.value(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let store = StreamInformationStore::new();
+        let cache = SharedData::new(PacketCache::default());
+        cache.write().insert(1234, 10, packet(1234, 10));
+        let (tx, mut rx) = unbounded_channel();
+        let responder = NackResponder::new(
+            cache,
+            store.subscribe_to_rtx_changes(),
+            tx,
+            SharedData::new(DroppedPackets::default()),
+        );
+        let (events_tx, events_rx) = broadcast::channel(16);
+        let handle = tokio::spawn(responder.run(events_rx));
+
+        events_tx
+            .send(RtcpEvent::Nack {
+                sender_ssrc: 42,
+                media_source_ssrc: 1234,
+                missing_seq_nums: vec![10],
+            })
+            .unwrap();
+        assert!(matches!(
+            rx.recv().await,
+            Some(SomePacket::UnparsedPacket(_))
+        ));
+        drop(events_tx);
+        handle.await.unwrap();
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+
+use data_pipeline_rs::data_handler::{DataObserver, SomeDataHandler};
+use rtp_parse::rtp::rtp_header::RtpHeader;
+
+use crate::{
+    error::{DroppedPackets, PipelineError},
+    packet_info::{PacketInfo, SomePacket},
+    rfc_3711_index::Rfc3711IndexTracker,
+    rtp_util,
+    util::SharedData,
+};
+
+/// How many packets are cached per ssrc.  This is a power of 2 so that a packet's slot in the
+/// ring can be derived from its sequence number alone.
+const PACKETS_PER_SSRC: usize = 1024;
+
+struct CachedPacket {
+    index: u32,
+    data: Vec<u8>,
+}
+
+struct SsrcPacketCache {
+    index_tracker: Rfc3711IndexTracker,
+    packets: Vec<Option<CachedPacket>>,
+}
+
+impl SsrcPacketCache {
+    fn new() -> Self {
+        Self {
+            index_tracker: Rfc3711IndexTracker::default(),
+            packets: (0..PACKETS_PER_SSRC).map(|_| None).collect(),
+        }
+    }
+
+    fn insert(&mut self, seq_num: u16, data: Vec<u8>) {
+        let index = self.index_tracker.update(seq_num);
+        let slot = &mut self.packets[index as usize % PACKETS_PER_SSRC];
+        // Don't let an old packet (e.g. a late retransmission) overwrite a newer one
+        if slot.as_ref().is_some_and(|cached| cached.index > index) {
+            return;
+        }
+        *slot = Some(CachedPacket { index, data });
+    }
+
+    fn get(&self, seq_num: u16) -> Option<&[u8]> {
+        self.packets[seq_num as usize % PACKETS_PER_SSRC]
+            .as_ref()
+            .filter(|cached| cached.index as u16 == seq_num)
+            .map(|cached| cached.data.as_slice())
+    }
+}
+
+/// A cache of the most recently sent RTP packets for each ssrc, so that they can be
+/// retransmitted if the receiver asks for them.
+#[derive(Default)]
+pub struct PacketCache {
+    ssrcs: HashMap<u32, SsrcPacketCache>,
+}
+
+impl PacketCache {
+    pub fn insert(&mut self, ssrc: u32, seq_num: u16, data: Vec<u8>) {
+        self.ssrcs
+            .entry(ssrc)
+            .or_insert_with(SsrcPacketCache::new)
+            .insert(seq_num, data);
+    }
+
+    pub fn get(&self, ssrc: u32, seq_num: u16) -> Option<&[u8]> {
+        self.ssrcs.get(&ssrc)?.get(seq_num)
+    }
+
+    pub fn remove_ssrc(&mut self, ssrc: u32) {
+        self.ssrcs.remove(&ssrc);
+    }
+}
+
+/// Adds every RTP packet going out on the send pipeline to a [`PacketCache`].  This should be
+/// placed before encryption.
+pub struct PacketCacher {
+    cache: SharedData<PacketCache>,
+    dropped_packets: SharedData<DroppedPackets>,
+}
+
+impl PacketCacher {
+    pub fn new(
+        cache: SharedData<PacketCache>,
+        dropped_packets: SharedData<DroppedPackets>,
+    ) -> Self {
+        Self {
+            cache,
+            dropped_packets,
+        }
+    }
+}
+
+impl DataObserver<PacketInfo> for PacketCacher {
+    fn observe(&mut self, data: &PacketInfo) {
+        let buf = match data.packet {
+            SomePacket::UnparsedPacket(ref buf) => buf.as_slice(),
+            SomePacket::RtpPacket(ref rtp)
+            | SomePacket::AudioRtpPacket(ref rtp)
+            | SomePacket::VideoRtpPacket(ref rtp) => rtp.buf(),
+            ref packet => {
+                self.dropped_packets
+                    .write()
+                    .record(&PipelineError::unexpected_packet_type(
+                        "PacketCacher",
+                        packet,
+                    ));
+                return;
+            }
+        };
+        if let Err(e) = rtp_util::header_length(buf) {
+            self.dropped_packets
+                .write()
+                .record(&PipelineError::parse_failure("rtp header", e));
+            return;
+        }
+        self.cache
+            .write()
+            .insert(RtpHeader::ssrc(buf), rtp_util::seq_num(buf), buf.to_vec());
+    }
+}
+
+impl From<PacketCacher> for SomeDataHandler<PacketInfo> {
+    fn from(value: PacketCacher) -> Self {
+        SomeDataHandler::Observer(Box::new(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::error::PipelineErrorKind;
+
+    use super::*;
+
+    #[test]
+    fn test_packet_cache() {
+        let mut cache = PacketCache::default();
+
+        cache.insert(1234, 65535, vec![1]);
+        cache.insert(1234, 0, vec![2]);
+        cache.insert(5678, 0, vec![3]);
+
+        assert_eq!(cache.get(1234, 65535), Some([1].as_slice()));
+        assert_eq!(cache.get(1234, 0), Some([2].as_slice()));
+        assert_eq!(cache.get(5678, 0), Some([3].as_slice()));
+        assert_eq!(cache.get(1234, 1), None);
+        assert_eq!(cache.get(42, 0), None);
+
+        cache.remove_ssrc(5678);
+        assert_eq!(cache.get(5678, 0), None);
+    }
+
+    #[test]
+    fn test_packet_cache_bounded() {
+        let mut cache = PacketCache::default();
+
+        for seq_num in 0..(PACKETS_PER_SSRC as u16 + 10) {
+            cache.insert(1234, seq_num, seq_num.to_be_bytes().to_vec());
+        }
+        // The oldest packets have been overwritten
+        assert_eq!(cache.get(1234, 9), None);
+        assert_eq!(cache.get(1234, 10), Some(10u16.to_be_bytes().as_slice()));
+        assert_eq!(
+            cache.get(1234, PACKETS_PER_SSRC as u16 + 9),
+            Some((PACKETS_PER_SSRC as u16 + 9).to_be_bytes().as_slice())
+        );
+    }
+
+    #[test]
+    fn test_short_packet_counted() {
+        let cache = SharedData::new(PacketCache::default());
+        let dropped_packets = SharedData::new(DroppedPackets::default());
+        let mut cacher = PacketCacher::new(cache.clone(), dropped_packets.clone());
+
+        cacher.observe(&PacketInfo::new_unparsed(
+            vec![0x80, 0x60, 0x00, 0x01],
+            Instant::now(),
+        ));
+        assert_eq!(
+            dropped_packets
+                .read()
+                .count(PipelineErrorKind::ParseFailure),
+            1
+        );
+        assert!(cache.read().ssrcs.is_empty());
+    }
+}
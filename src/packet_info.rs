@@ -2,6 +2,8 @@ use std::{fmt::Display, time::Instant};
 
 use rtp_parse::{rtcp::rtcp_packet::SomeRtcpPacket, rtp::rtp_packet::RtpPacket};
 
+use crate::clock::Clock;
+
 #[derive(Debug)]
 pub enum SomePacket {
     UnparsedPacket(Vec<u8>),
@@ -13,6 +15,21 @@ pub enum SomePacket {
     VideoRtpPacket(RtpPacket),
 }
 
+impl SomePacket {
+    /// The name of this packet's variant, for use in logs and errors
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            SomePacket::UnparsedPacket(_) => "UnparsedPacket",
+            SomePacket::UnparsedRtcpPacket(_) => "UnparsedRtcpPacket",
+            SomePacket::UnparsedRtpPacket(_) => "UnparsedRtpPacket",
+            SomePacket::RtcpPacket(_) => "RtcpPacket",
+            SomePacket::RtpPacket(_) => "RtpPacket",
+            SomePacket::AudioRtpPacket(_) => "AudioRtpPacket",
+            SomePacket::VideoRtpPacket(_) => "VideoRtpPacket",
+        }
+    }
+}
+
 impl Display for SomePacket {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -22,10 +39,13 @@ impl Display for SomePacket {
     }
 }
 
+#[derive(Debug)]
 pub struct PacketInfo {
     pub received_time: Instant,
     pub packet: SomePacket,
     pub should_discard: bool,
+    /// Whether this packet was a retransmission of a packet sent previously
+    pub is_retransmission: bool,
 }
 
 impl PacketInfo {
@@ -34,14 +54,22 @@ impl PacketInfo {
             received_time,
             packet,
             should_discard: false,
+            is_retransmission: false,
         }
     }
 
+    /// Create a [`PacketInfo`] for data which was just received, taking the received time from
+    /// `clock`.
+    pub fn received_now(data: Vec<u8>, clock: &dyn Clock) -> Self {
+        Self::new_unparsed(data, clock.now())
+    }
+
     pub fn new_unparsed(data: Vec<u8>, received_time: Instant) -> Self {
         Self {
             received_time,
             packet: SomePacket::UnparsedPacket(data),
             should_discard: false,
+            is_retransmission: false,
         }
     }
 }
@@ -49,13 +77,21 @@ impl PacketInfo {
 pub fn looks_like_rtp(packet_info: &PacketInfo) -> bool {
     match &packet_info.packet {
         SomePacket::UnparsedPacket(ref packet) => rtp_parse::util::looks_like_rtp(packet),
-        some_packet => panic!("Unexpected packet type: {some_packet:?}"),
+        SomePacket::UnparsedRtpPacket(_)
+        | SomePacket::RtpPacket(_)
+        | SomePacket::AudioRtpPacket(_)
+        | SomePacket::VideoRtpPacket(_) => true,
+        SomePacket::UnparsedRtcpPacket(_) | SomePacket::RtcpPacket(_) => false,
     }
 }
 
 pub fn looks_like_rtcp(packet_info: &PacketInfo) -> bool {
     match &packet_info.packet {
         SomePacket::UnparsedPacket(ref packet) => rtp_parse::util::looks_like_rtcp(packet),
-        some_packet => panic!("Unexpected packet type: {some_packet:?}"),
+        SomePacket::UnparsedRtcpPacket(_) | SomePacket::RtcpPacket(_) => true,
+        SomePacket::UnparsedRtpPacket(_)
+        | SomePacket::RtpPacket(_)
+        | SomePacket::AudioRtpPacket(_)
+        | SomePacket::VideoRtpPacket(_) => false,
     }
 }
@@ -0,0 +1,377 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use data_pipeline_rs::data_handler::{DataObserver, SomeDataHandler};
+use rtp_parse::rtp::rtp_packet::RtpPacket;
+
+use crate::{
+    error::{DroppedPackets, PipelineError},
+    packet_info::{PacketInfo, SomePacket},
+    rfc_3711_index::Rfc3711IndexTracker,
+    stream_information_store::PayloadTypes,
+    util::{LiveStateReader, LiveStateWriter, SharedData},
+};
+
+/// How often the fraction lost and bitrate are recalculated
+const RATE_INTERVAL: Duration = Duration::from_secs(1);
+/// How many of the most recent sequence numbers we remember in order to detect duplicates
+const DUPLICATE_HISTORY_SIZE: usize = 1024;
+
+/// Receive statistics for a single ssrc, as described in
+/// https://datatracker.ietf.org/doc/html/rfc3550#appendix-A.3 and
+/// https://datatracker.ietf.org/doc/html/rfc3550#appendix-A.8
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StreamReceiveStatistics {
+    /// The extended (RFC 3711 index) sequence number of the first packet received
+    pub base_seq_num: u32,
+    pub extended_highest_seq_num: u32,
+    /// The number of packets received, not counting duplicates
+    pub packets_received: u64,
+    pub bytes_received: u64,
+    pub duplicates: u64,
+    /// The number of packets which arrived after a packet with a higher sequence number
+    pub reordered: u64,
+    /// The fraction of packets lost over the last [`RATE_INTERVAL`], in the RFC 3550 fixed point
+    /// format (the loss fraction multiplied by 256)
+    pub fraction_lost: u8,
+    /// The interarrival jitter, in RTP timestamp units
+    pub jitter: f64,
+    pub clock_rate: u32,
+    /// The receive bitrate over the last [`RATE_INTERVAL`], in bits per second
+    pub bitrate_bps: u64,
+    pub last_packet_time: Option<Instant>,
+}
+
+impl StreamReceiveStatistics {
+    pub fn packets_expected(&self) -> u64 {
+        (self.extended_highest_seq_num - self.base_seq_num) as u64 + 1
+    }
+
+    /// The total number of packets lost.  This can be negative if packets were duplicated in the
+    /// network (or by retransmissions).
+    pub fn cumulative_lost(&self) -> i64 {
+        self.packets_expected() as i64 - self.packets_received as i64
+    }
+}
+
+pub type ReceiveStatisticsSnapshot = HashMap<u32, StreamReceiveStatistics>;
+
+/// An estimate of the interarrival jitter of a single stream, as described in
+/// https://datatracker.ietf.org/doc/html/rfc3550#appendix-A.8.  Transit times are calculated
+/// modulo 2^32 so that the estimate isn't thrown off when the RTP timestamp wraps.
+pub struct InterarrivalJitter {
+    /// Arrival times are converted to RTP timestamp units relative to this
+    time_base: Instant,
+    clock_rate: u32,
+    /// The previous packet's relative transit time, in RTP timestamp units
+    last_transit: Option<u32>,
+    /// The jitter, in RTP timestamp units
+    jitter: f64,
+}
+
+impl InterarrivalJitter {
+    pub fn new(time_base: Instant) -> Self {
+        Self {
+            time_base,
+            clock_rate: 0,
+            last_transit: None,
+            jitter: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, rtp_timestamp: u32, clock_rate: u32, arrival_time: Instant) {
+        if clock_rate != self.clock_rate {
+            // Transit times in different units can't be compared
+            self.clock_rate = clock_rate;
+            self.last_transit = None;
+        }
+        let elapsed = arrival_time.saturating_duration_since(self.time_base);
+        // Truncating to 32 bits keeps the arrival time in the same modular space as the RTP
+        // timestamp
+        let arrival = (elapsed.as_secs_f64() * clock_rate as f64) as u64 as u32;
+        let transit = arrival.wrapping_sub(rtp_timestamp);
+        if let Some(last_transit) = self.last_transit {
+            let d = (transit.wrapping_sub(last_transit) as i32).unsigned_abs() as f64;
+            self.jitter += (d - self.jitter) / 16.0;
+        }
+        self.last_transit = Some(transit);
+    }
+
+    /// The jitter, in RTP timestamp units
+    pub fn jitter(&self) -> f64 {
+        self.jitter
+    }
+
+    /// The jitter, in seconds
+    pub fn jitter_secs(&self) -> f64 {
+        if self.clock_rate == 0 {
+            return 0.0;
+        }
+        self.jitter / self.clock_rate as f64
+    }
+}
+
+struct SsrcReceiveState {
+    index_tracker: Rfc3711IndexTracker,
+    /// The indices of the most recently received packets, slotted by index
+    recent_indices: Vec<Option<u32>>,
+    jitter: InterarrivalJitter,
+    interval_start: Instant,
+    interval_expected_prior: u64,
+    interval_received_prior: u64,
+    interval_bytes: u64,
+    stats: StreamReceiveStatistics,
+}
+
+impl SsrcReceiveState {
+    fn new(seq_num: u16, now: Instant) -> Self {
+        let mut index_tracker = Rfc3711IndexTracker::default();
+        let base_seq_num = index_tracker.update(seq_num);
+        Self {
+            index_tracker,
+            recent_indices: vec![None; DUPLICATE_HISTORY_SIZE],
+            jitter: InterarrivalJitter::new(now),
+            interval_start: now,
+            interval_expected_prior: 0,
+            interval_received_prior: 0,
+            interval_bytes: 0,
+            stats: StreamReceiveStatistics {
+                base_seq_num,
+                extended_highest_seq_num: base_seq_num,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn packet_received(
+        &mut self,
+        seq_num: u16,
+        rtp_timestamp: u32,
+        size: usize,
+        clock_rate: u32,
+        is_retransmission: bool,
+        now: Instant,
+    ) {
+        let index = self.index_tracker.update(seq_num);
+        let slot = &mut self.recent_indices[index as usize % DUPLICATE_HISTORY_SIZE];
+        if *slot == Some(index) {
+            self.stats.duplicates += 1;
+            return;
+        }
+        *slot = Some(index);
+
+        let stats = &mut self.stats;
+        stats.packets_received += 1;
+        stats.bytes_received += size as u64;
+        stats.last_packet_time = Some(now);
+        self.interval_bytes += size as u64;
+        if index >= stats.extended_highest_seq_num {
+            stats.extended_highest_seq_num = index;
+        } else if index < stats.base_seq_num {
+            // A packet from before the first one we saw, which we wouldn't have counted as
+            // expected
+            stats.base_seq_num = index;
+            stats.reordered += 1;
+        } else {
+            stats.reordered += 1;
+        }
+
+        // Retransmissions were sent late on purpose, so they'd skew the jitter
+        if clock_rate != 0 && !is_retransmission {
+            stats.clock_rate = clock_rate;
+            self.jitter.update(rtp_timestamp, clock_rate, now);
+            stats.jitter = self.jitter.jitter();
+        }
+
+        let elapsed = now.duration_since(self.interval_start);
+        if elapsed >= RATE_INTERVAL {
+            let expected = stats.packets_expected();
+            let expected_interval = expected - self.interval_expected_prior;
+            let received_interval = stats.packets_received - self.interval_received_prior;
+            let lost_interval = expected_interval as i64 - received_interval as i64;
+            stats.fraction_lost = if expected_interval == 0 || lost_interval <= 0 {
+                0
+            } else {
+                ((lost_interval << 8) / expected_interval as i64) as u8
+            };
+            stats.bitrate_bps = (self.interval_bytes as f64 * 8.0 / elapsed.as_secs_f64()) as u64;
+
+            self.interval_start = now;
+            self.interval_expected_prior = expected;
+            self.interval_received_prior = stats.packets_received;
+            self.interval_bytes = 0;
+        }
+    }
+}
+
+/// Keeps per-ssrc receive statistics for incoming RTP.  The statistics can be read via the
+/// [`LiveStateReader`] returned from [`ReceiveStatistics::reader`].  This should be placed after
+/// the [`crate::rtp_parser::RtpParser`].
+pub struct ReceiveStatistics {
+    payload_types: LiveStateReader<PayloadTypes>,
+    ssrcs: HashMap<u32, SsrcReceiveState>,
+    stats: LiveStateWriter<ReceiveStatisticsSnapshot>,
+    dropped_packets: SharedData<DroppedPackets>,
+}
+
+impl ReceiveStatistics {
+    pub fn new(
+        payload_types: LiveStateReader<PayloadTypes>,
+        dropped_packets: SharedData<DroppedPackets>,
+    ) -> Self {
+        Self {
+            payload_types,
+            ssrcs: HashMap::new(),
+            stats: LiveStateWriter::new(HashMap::new()),
+            dropped_packets,
+        }
+    }
+
+    pub fn reader(&self) -> LiveStateReader<ReceiveStatisticsSnapshot> {
+        self.stats.reader()
+    }
+
+    pub fn packet_received(
+        &mut self,
+        rtp_packet: &RtpPacket,
+        is_retransmission: bool,
+        now: Instant,
+    ) {
+        let clock_rate = self
+            .payload_types
+            .value()
+            .clock_rate(&rtp_packet.payload_type())
+            .unwrap_or(0);
+        let ssrc = rtp_packet.ssrc();
+        let state = self
+            .ssrcs
+            .entry(ssrc)
+            .or_insert_with(|| SsrcReceiveState::new(rtp_packet.seq_num(), now));
+        state.packet_received(
+            rtp_packet.seq_num(),
+            rtp_packet.timestamp(),
+            rtp_packet.buf().len(),
+            clock_rate,
+            is_retransmission,
+            now,
+        );
+        let stream_stats = state.stats.clone();
+        self.stats
+            .modify(|stats| _ = stats.insert(ssrc, stream_stats));
+    }
+}
+
+impl DataObserver<PacketInfo> for ReceiveStatistics {
+    fn observe(&mut self, data: &PacketInfo) {
+        let rtp_packet = match data.packet {
+            SomePacket::AudioRtpPacket(ref rtp) => rtp,
+            SomePacket::VideoRtpPacket(ref rtp) => rtp,
+            ref packet => {
+                self.dropped_packets
+                    .write()
+                    .record(&PipelineError::unexpected_packet_type(
+                        "ReceiveStatistics",
+                        packet,
+                    ));
+                return;
+            }
+        };
+        self.packet_received(rtp_packet, data.is_retransmission, data.received_time);
+    }
+}
+
+impl From<ReceiveStatistics> for SomeDataHandler<PacketInfo> {
+    fn from(value: ReceiveStatistics) -> Self {
+        SomeDataHandler::Observer(Box::new(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLOCK_RATE: u32 = 90000;
+
+    #[test]
+    fn test_loss_and_reordering() {
+        let now = Instant::now();
+        let mut state = SsrcReceiveState::new(65534, now);
+
+        for seq_num in [65534, 65535, 2, 1, 1, 4] {
+            state.packet_received(seq_num, 0, 100, CLOCK_RATE, false, now);
+        }
+
+        let stats = &state.stats;
+        assert_eq!(stats.base_seq_num, 65534);
+        assert_eq!(stats.extended_highest_seq_num, 0x1_0000 + 4);
+        assert_eq!(stats.packets_expected(), 7);
+        assert_eq!(stats.packets_received, 5);
+        assert_eq!(stats.cumulative_lost(), 2);
+        assert_eq!(stats.duplicates, 1);
+        assert_eq!(stats.reordered, 1);
+        assert_eq!(stats.bytes_received, 500);
+    }
+
+    #[test]
+    fn test_fraction_lost_and_bitrate() {
+        let start = Instant::now();
+        let mut state = SsrcReceiveState::new(0, start);
+
+        // Receive 3 out of every 4 packets
+        for seq_num in (0..100u16).filter(|seq_num| seq_num % 4 != 3) {
+            let time = start + Duration::from_millis(seq_num as u64 * 10);
+            state.packet_received(seq_num, 0, 125, CLOCK_RATE, false, time);
+        }
+        state.packet_received(100, 0, 125, CLOCK_RATE, false, start + RATE_INTERVAL);
+
+        // 25 of the 101 expected packets were lost
+        assert_eq!(state.stats.fraction_lost, (25 * 256 / 101) as u8);
+        // 76 packets of 1000 bits over 1 second
+        assert_eq!(state.stats.bitrate_bps, 76000);
+    }
+
+    #[test]
+    fn test_jitter() {
+        let start = Instant::now();
+        let mut state = SsrcReceiveState::new(0, start);
+
+        // Packets sent every 20ms, with every other one arriving 10ms late
+        for i in 0..1000u32 {
+            let delay = (i % 2) * 10;
+            let time = start + Duration::from_millis((i * 20 + delay) as u64);
+            state.packet_received(i as u16, i * 1800, 100, CLOCK_RATE, false, time);
+        }
+
+        // Every transit difference is 10ms, so the jitter converges on 900 timestamp units
+        assert!((state.stats.jitter - 900.0).abs() < 1.0);
+
+        // A very late retransmission doesn't affect the jitter
+        state.packet_received(
+            2000,
+            0,
+            100,
+            CLOCK_RATE,
+            true,
+            start + Duration::from_secs(60),
+        );
+        assert!((state.stats.jitter - 900.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_jitter_timestamp_wrap() {
+        let start = Instant::now();
+        let mut state = SsrcReceiveState::new(0, start);
+
+        // Evenly spaced packets whose timestamps wrap part way through
+        let first_timestamp = u32::MAX - 10 * 1800;
+        for i in 0..20u32 {
+            let time = start + Duration::from_millis((i * 20) as u64);
+            let timestamp = first_timestamp.wrapping_add(i * 1800);
+            state.packet_received(i as u16, timestamp, 100, CLOCK_RATE, false, time);
+        }
+
+        assert!(state.stats.jitter < 1.0);
+    }
+}
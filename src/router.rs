@@ -0,0 +1,324 @@
+use std::collections::{HashMap, HashSet};
+
+use data_pipeline_rs::data_handler::{DataObserver, SomeDataHandler};
+use rtp_parse::rtp::rtp_packet::{read_rtp_packet, RtpPacket};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{
+    error::{DroppedPackets, PipelineError},
+    packet_info::{PacketInfo, SomePacket},
+    util::SharedData,
+};
+
+#[derive(Default)]
+struct RouterState {
+    /// The channel feeding each endpoint's send pipeline
+    endpoints: HashMap<String, UnboundedSender<PacketInfo>>,
+    /// (receiver, sender) pairs where the receiver doesn't want the sender's media
+    unsubscribed: HashSet<(String, String)>,
+}
+
+/// Routes the media received from each endpoint in a conference to the send pipelines of the
+/// other endpoints.  Every endpoint receives media from every other endpoint unless it has
+/// unsubscribed from it, and endpoints can join and leave at any time.
+#[derive(Default)]
+pub struct Router {
+    state: SharedData<RouterState>,
+}
+
+impl Clone for Router {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an endpoint.  Media routed to it will be sent on `send_tx`, which should feed its
+    /// send pipeline.  The returned [`RouterInput`] goes at the end of the endpoint's receive
+    /// pipeline, to route the media it receives to the other endpoints.  Packets it can't route
+    /// are counted in `dropped_packets`.
+    ///
+    /// Adding an endpoint id which is already present replaces its `send_tx`, e.g. when a
+    /// participant reconnects, and any [`RouterInput`] returned for it before keeps routing its
+    /// media.  Once the endpoint is removed, none of its [`RouterInput`]s route anything.
+    pub fn add_endpoint(
+        &self,
+        endpoint_id: &str,
+        send_tx: UnboundedSender<PacketInfo>,
+        dropped_packets: SharedData<DroppedPackets>,
+    ) -> RouterInput {
+        self.state
+            .write()
+            .endpoints
+            .insert(endpoint_id.to_owned(), send_tx);
+        RouterInput {
+            endpoint_id: endpoint_id.to_owned(),
+            state: self.state.clone(),
+            dropped_packets,
+        }
+    }
+
+    /// Stop routing media to and from `endpoint_id`
+    pub fn remove_endpoint(&self, endpoint_id: &str) {
+        let mut state = self.state.write();
+        state.endpoints.remove(endpoint_id);
+        state
+            .unsubscribed
+            .retain(|(receiver, sender)| receiver != endpoint_id && sender != endpoint_id);
+    }
+
+    /// Have `receiver_id` receive media from `sender_id` (which is the default)
+    pub fn subscribe(&self, receiver_id: &str, sender_id: &str) {
+        self.state
+            .write()
+            .unsubscribed
+            .remove(&(receiver_id.to_owned(), sender_id.to_owned()));
+    }
+
+    /// Stop routing media from `sender_id` to `receiver_id`
+    pub fn unsubscribe(&self, receiver_id: &str, sender_id: &str) {
+        self.state
+            .write()
+            .unsubscribed
+            .insert((receiver_id.to_owned(), sender_id.to_owned()));
+    }
+}
+
+/// Copies each audio and video packet received from an endpoint into the send pipeline of every
+/// other endpoint subscribed to it.
+pub struct RouterInput {
+    endpoint_id: String,
+    state: SharedData<RouterState>,
+    dropped_packets: SharedData<DroppedPackets>,
+}
+
+impl RouterInput {
+    fn copy_packet(
+        packet_info: &PacketInfo,
+        rtp_packet: &RtpPacket,
+        packet: fn(RtpPacket) -> SomePacket,
+    ) -> Result<PacketInfo, PipelineError> {
+        // Each send pipeline needs its own copy, since it will modify the packet (e.g. when
+        // encrypting it)
+        let copy = read_rtp_packet(rtp_packet.buf().to_vec())
+            .map_err(|e| PipelineError::parse_failure("rtp packet copy", e))?;
+        Ok(PacketInfo {
+            is_retransmission: packet_info.is_retransmission,
+            ..PacketInfo::new(packet(copy), packet_info.received_time)
+        })
+    }
+}
+
+impl DataObserver<PacketInfo> for RouterInput {
+    fn observe(&mut self, data: &PacketInfo) {
+        if data.should_discard {
+            return;
+        }
+        let (rtp_packet, packet): (_, fn(RtpPacket) -> SomePacket) = match data.packet {
+            SomePacket::AudioRtpPacket(ref rtp) => (rtp, SomePacket::AudioRtpPacket),
+            SomePacket::VideoRtpPacket(ref rtp) => (rtp, SomePacket::VideoRtpPacket),
+            ref packet => {
+                self.dropped_packets
+                    .write()
+                    .record(&PipelineError::unexpected_packet_type(
+                        "RouterInput",
+                        packet,
+                    ));
+                return;
+            }
+        };
+
+        let state = self.state.read();
+        // The endpoint has been removed, but its receive pipeline may not have stopped yet
+        if !state.endpoints.contains_key(&self.endpoint_id) {
+            return;
+        }
+        for (receiver_id, send_tx) in &state.endpoints {
+            if *receiver_id == self.endpoint_id
+                || state
+                    .unsubscribed
+                    .contains(&(receiver_id.clone(), self.endpoint_id.clone()))
+            {
+                continue;
+            }
+            match Self::copy_packet(data, rtp_packet, packet) {
+                // The endpoint may be shutting down, in which case it doesn't matter that the
+                // packet was dropped
+                Ok(copy) => _ = send_tx.send(copy),
+                Err(e) => self.dropped_packets.write().record(&e),
+            }
+        }
+    }
+}
+
+impl From<RouterInput> for SomeDataHandler<PacketInfo> {
+    fn from(value: RouterInput) -> Self {
+        SomeDataHandler::Observer(Box::new(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Instant};
+
+    use bit_cursor::nsw_types::u7;
+    use data_pipeline_rs::{node::NodeRef, pipeline_builder::PipelineBuilder};
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+    use crate::{
+        clock::ManualClock,
+        discardable_discarder::DiscardableDiscarder,
+        error::PipelineErrorKind,
+        rtp_parser::{MediaType, RtpParser},
+        rtp_util::RtpPacketBuilder,
+        stream_information_store::{PayloadType, StreamInformationStore},
+    };
+
+    use super::*;
+
+    /// A simulated endpoint: a receive pipeline which ends in the router, and the channel
+    /// feeding its send pipeline
+    struct TestEndpoint {
+        receive_pipeline: NodeRef<PacketInfo>,
+        send_rx: UnboundedReceiver<PacketInfo>,
+    }
+
+    impl TestEndpoint {
+        fn join(router: &Router, endpoint_id: &str) -> Self {
+            let mut store = StreamInformationStore::new();
+            store.add_payload_type(PayloadType::new(
+                u7::new(111),
+                MediaType::Audio,
+                "opus",
+                48000,
+            ));
+            store.add_payload_type(PayloadType::new(
+                u7::new(96),
+                MediaType::Video,
+                "VP8",
+                90000,
+            ));
+            let (send_tx, send_rx) = unbounded_channel();
+            let receive_pipeline = PipelineBuilder::new()
+                .attach_handler(
+                    "RTP parser",
+                    RtpParser::new(store.subscribe_to_pt_changes()),
+                )
+                .attach_handler("discarder", DiscardableDiscarder)
+                .attach_handler(
+                    "router",
+                    router.add_endpoint(endpoint_id, send_tx, SharedData::default()),
+                )
+                .build();
+            Self {
+                receive_pipeline,
+                send_rx,
+            }
+        }
+
+        fn receive(&self, pt: u8, ssrc: u32, clock: &ManualClock) {
+            let packet = RtpPacketBuilder::default()
+                .payload_type(pt)
+                .ssrc(ssrc)
+                .build();
+            self.receive_pipeline
+                .process_data(PacketInfo::received_now(packet, clock));
+        }
+
+        /// The (ssrc, is video) of each packet sent to this endpoint since the last call
+        fn sent(&mut self) -> Vec<(u32, bool)> {
+            let mut sent = Vec::new();
+            while let Ok(packet_info) = self.send_rx.try_recv() {
+                match packet_info.packet {
+                    SomePacket::AudioRtpPacket(rtp) => sent.push((rtp.ssrc(), false)),
+                    SomePacket::VideoRtpPacket(rtp) => {
+                        assert_eq!(rtp.payload(), &[0xAA, 0xBB]);
+                        sent.push((rtp.ssrc(), true))
+                    }
+                    packet => panic!("unexpected packet {packet}"),
+                }
+            }
+            sent.sort();
+            sent
+        }
+    }
+
+    #[test]
+    fn test_conference() {
+        let clock = Arc::new(ManualClock::new());
+        let router = Router::new();
+        let mut a = TestEndpoint::join(&router, "a");
+        let mut b = TestEndpoint::join(&router, "b");
+        let mut c = TestEndpoint::join(&router, "c");
+
+        a.receive(111, 1, &clock);
+        a.receive(96, 10, &clock);
+        b.receive(111, 2, &clock);
+        assert_eq!(a.sent(), vec![(2, false)]);
+        assert_eq!(b.sent(), vec![(1, false), (10, true)]);
+        assert_eq!(c.sent(), vec![(1, false), (2, false), (10, true)]);
+
+        router.unsubscribe("c", "a");
+        a.receive(96, 10, &clock);
+        assert_eq!(b.sent(), vec![(10, true)]);
+        assert!(c.sent().is_empty());
+        router.subscribe("c", "a");
+        a.receive(96, 10, &clock);
+        assert_eq!(c.sent(), vec![(10, true)]);
+        b.sent();
+
+        // Endpoints can leave and join at any time
+        router.remove_endpoint("b");
+        let mut d = TestEndpoint::join(&router, "d");
+        c.receive(111, 3, &clock);
+        assert_eq!(a.sent(), vec![(3, false)]);
+        assert!(b.sent().is_empty());
+        assert_eq!(d.sent(), vec![(3, false)]);
+        // Media still making its way through a removed endpoint's receive pipeline goes nowhere
+        b.receive(111, 2, &clock);
+        assert!(a.sent().is_empty());
+        assert!(c.sent().is_empty());
+        assert!(d.sent().is_empty());
+        d.receive(111, 4, &clock);
+        assert_eq!(a.sent(), vec![(4, false)]);
+        assert_eq!(c.sent(), vec![(4, false)]);
+    }
+
+    #[test]
+    fn test_rejoin_replaces_send_channel() {
+        let clock = Arc::new(ManualClock::new());
+        let router = Router::new();
+        let a = TestEndpoint::join(&router, "a");
+        let mut b = TestEndpoint::join(&router, "b");
+        let mut b_rejoined = TestEndpoint::join(&router, "b");
+
+        a.receive(111, 1, &clock);
+        assert!(b.sent().is_empty());
+        assert_eq!(b_rejoined.sent(), vec![(1, false)]);
+    }
+
+    #[test]
+    fn test_unexpected_packet_type_counted() {
+        let router = Router::new();
+        let (send_tx, mut send_rx) = unbounded_channel();
+        router.add_endpoint("a", send_tx, SharedData::default());
+        let dropped_packets = SharedData::default();
+        let (b_tx, _b_rx) = unbounded_channel();
+        let mut input = router.add_endpoint("b", b_tx, dropped_packets.clone());
+
+        input.observe(&PacketInfo::new_unparsed(vec![0x80, 0x60], Instant::now()));
+        assert!(send_rx.try_recv().is_err());
+        assert_eq!(
+            dropped_packets
+                .read()
+                .count(PipelineErrorKind::UnexpectedPacketType),
+            1
+        );
+    }
+}
@@ -0,0 +1,450 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Instant, SystemTime},
+};
+
+use rtp_parse::rtcp::{
+    rtcp_packet::SomeRtcpPacket,
+    rtcp_report_block::RtcpReportBlock,
+    rtcp_rr::RtcpRrPacket,
+    rtcp_sdes::{RtcpSdesPacket, SdesChunk, SdesItem},
+};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{
+    clock::{compact_ntp, to_ntp_timestamp, Clock},
+    receive_statistics::{ReceiveStatisticsSnapshot, StreamReceiveStatistics},
+    rtcp_scheduler::RtcpScheduler,
+    rtcp_termination::RtcpEvent,
+    rtcp_writer::{new_rtcp_header, write_rtcp_packet, RTCP_PT_RR, RTCP_PT_SDES},
+    rtcp_xr::{RtcpXrPacket, XrReportBlock},
+    rtt_estimator::ReportHistory,
+    timer::{TimerHandle, TimerHandler, TimerScheduler},
+    util::{LiveStateReader, SharedData},
+};
+
+/// The most report blocks that fit in a single report (the count field is 5 bits)
+pub const MAX_REPORT_BLOCKS: usize = 31;
+/// The range of the 24 bit signed cumulative lost field
+const MAX_CUMULATIVE_LOST: i64 = 0x7FFFFF;
+const MIN_CUMULATIVE_LOST: i64 = -0x800000;
+
+/// The information from the last SR we received from a given ssrc, needed for the LSR and DLSR
+/// fields in our report blocks.
+struct LastSenderReport {
+    /// The middle 32 bits of the SR's NTP timestamp
+    compact_ntp_timestamp: u32,
+    received_time: Instant,
+}
+
+/// The expected and received counts at the time of our previous report for an ssrc, used to
+/// calculate the fraction lost since then.
+/// https://datatracker.ietf.org/doc/html/rfc3550#appendix-A.3
+#[derive(Default)]
+struct PriorReport {
+    expected: u64,
+    received: u64,
+}
+
+/// Builds the report blocks describing the streams we're receiving, for use in both receiver and
+/// sender reports.
+#[derive(Default)]
+pub struct ReportBlockBuilder {
+    last_sender_reports: HashMap<u32, LastSenderReport>,
+    prior_reports: HashMap<u32, PriorReport>,
+}
+
+impl ReportBlockBuilder {
+    pub fn sender_report_received(
+        &mut self,
+        ssrc: u32,
+        ntp_timestamp: u64,
+        received_time: Instant,
+    ) {
+        self.last_sender_reports.insert(
+            ssrc,
+            LastSenderReport {
+                compact_ntp_timestamp: compact_ntp(ntp_timestamp),
+                received_time,
+            },
+        );
+    }
+
+    /// Build report blocks for every stream in `receive_stats` which has received packets since
+    /// the previous call.
+    pub fn build_report_blocks(
+        &mut self,
+        receive_stats: &ReceiveStatisticsSnapshot,
+        now: Instant,
+    ) -> Vec<RtcpReportBlock> {
+        let mut report_blocks = receive_stats
+            .iter()
+            .filter_map(|(&ssrc, stats)| self.build_report_block(ssrc, stats, now))
+            .collect::<Vec<_>>();
+        report_blocks.sort_by_key(|block| block.ssrc);
+        report_blocks
+    }
+
+    fn build_report_block(
+        &mut self,
+        ssrc: u32,
+        stats: &StreamReceiveStatistics,
+        now: Instant,
+    ) -> Option<RtcpReportBlock> {
+        let prior = self.prior_reports.entry(ssrc).or_default();
+        let expected = stats.packets_expected();
+        if stats.packets_received == prior.received {
+            return None;
+        }
+        let expected_interval = expected.saturating_sub(prior.expected);
+        let received_interval = stats.packets_received - prior.received;
+        let lost_interval = expected_interval as i64 - received_interval as i64;
+        let fraction_lost = if expected_interval == 0 || lost_interval <= 0 {
+            0
+        } else {
+            ((lost_interval << 8) / expected_interval as i64) as u8
+        };
+        prior.expected = expected;
+        prior.received = stats.packets_received;
+
+        let cumulative_lost = stats
+            .cumulative_lost()
+            .clamp(MIN_CUMULATIVE_LOST, MAX_CUMULATIVE_LOST);
+        let (last_sr_timestamp, delay_since_last_sr) = match self.last_sender_reports.get(&ssrc) {
+            Some(sr) => {
+                // DLSR is expressed in units of 1/65536 seconds
+                let delay = now.duration_since(sr.received_time).as_secs_f64() * 65536.0;
+                (sr.compact_ntp_timestamp, delay as u32)
+            }
+            None => (0, 0),
+        };
+
+        Some(RtcpReportBlock {
+            ssrc,
+            fraction_lost,
+            cumulative_lost: (cumulative_lost as u32) & 0xFFFFFF,
+            extended_highest_seq_num: stats.extended_highest_seq_num,
+            interarrival_jitter: stats.jitter as u32,
+            last_sr_timestamp,
+            delay_since_last_sr,
+        })
+    }
+}
+
+/// Build an SDES packet containing just our CNAME, which RFC 3550 requires in every compound
+/// packet.
+pub fn build_cname_sdes(ssrc: u32, cname: &str) -> SomeRtcpPacket {
+    SomeRtcpPacket::RtcpSdesPacket(RtcpSdesPacket {
+        header: new_rtcp_header(1, RTCP_PT_SDES),
+        chunks: vec![SdesChunk {
+            ssrc,
+            sdes_items: vec![SdesItem::Cname(cname.to_owned())],
+        }],
+    })
+}
+
+/// Periodically sends RTCP receiver reports (compounded with our SDES CNAME) describing the
+/// streams we're receiving, using the statistics from
+/// [`crate::receive_statistics::ReceiveStatistics`] and the sender reports seen by
+/// [`crate::rtcp_termination::RtcpTermination`].  Each report also carries an XR receiver
+/// reference time, so that the remote side can respond with a DLRR and we can measure the rtt even
+/// though we aren't sending media.
+pub struct RrGenerator {
+    clock: Arc<dyn Clock>,
+    sender_ssrc: u32,
+    cname: String,
+    receive_stats: LiveStateReader<ReceiveStatisticsSnapshot>,
+    report_block_builder: ReportBlockBuilder,
+    scheduler: RtcpScheduler,
+    rrtr_history: SharedData<ReportHistory>,
+    rtcp_tx: UnboundedSender<SomeRtcpPacket>,
+}
+
+impl RrGenerator {
+    pub fn new(
+        clock: Arc<dyn Clock>,
+        sender_ssrc: u32,
+        cname: String,
+        receive_stats: LiveStateReader<ReceiveStatisticsSnapshot>,
+        scheduler: RtcpScheduler,
+        rtcp_tx: UnboundedSender<SomeRtcpPacket>,
+    ) -> Self {
+        Self {
+            clock,
+            sender_ssrc,
+            cname,
+            receive_stats,
+            report_block_builder: ReportBlockBuilder::default(),
+            scheduler,
+            rrtr_history: SharedData::default(),
+            rtcp_tx,
+        }
+    }
+
+    /// The receiver reference times we've sent, for matching against the DLRR blocks we receive
+    /// (see [`crate::rtt_estimator::RttEstimator`]).
+    pub fn rrtr_history(&self) -> SharedData<ReportHistory> {
+        self.rrtr_history.clone()
+    }
+
+    /// Have reports sent from `timers`, starting with a check straight away, until the returned
+    /// [`TimerHandle`] is dropped.  The generator is shared with the scheduler, so [`RtcpEvent`]s
+    /// should be passed to the returned generator via [`RrGenerator::handle_event`].
+    pub fn register(self, timers: &TimerScheduler) -> (SharedData<Self>, TimerHandle) {
+        let now = self.clock.now();
+        let generator = SharedData::new(self);
+        let timer = timers.register(generator.clone());
+        timer.schedule(now);
+        (generator, timer)
+    }
+
+    pub fn handle_event(&mut self, event: &RtcpEvent) {
+        if let RtcpEvent::SenderReport {
+            ssrc,
+            ntp_timestamp,
+            received_time,
+            ..
+        } = event
+        {
+            self.report_block_builder
+                .sender_report_received(*ssrc, *ntp_timestamp, *received_time);
+        }
+    }
+
+    pub fn maybe_send_report(&mut self) {
+        let now = self.clock.now();
+        let senders = self.receive_stats.value().len();
+        let members = senders + 1;
+        if !self.scheduler.is_due(now, members, senders, false) {
+            return;
+        }
+        let system_time = self.clock.system_time();
+        let report = self.build_report(now, system_time);
+        let size = write_rtcp_packet(&report).map_or(0, |buf| buf.len());
+        let _ = self.rtcp_tx.send(report);
+        self.rrtr_history
+            .write()
+            .report_sent(to_ntp_timestamp(system_time), now);
+        self.scheduler
+            .report_sent(now, size, members, senders, false);
+    }
+
+    /// Build a compound packet of receiver reports for all the streams we've received on since
+    /// the last report, followed by our CNAME and receiver reference time.  `now` and
+    /// `system_time` must refer to the same instant.
+    pub fn build_report(&mut self, now: Instant, system_time: SystemTime) -> SomeRtcpPacket {
+        let report_blocks = self
+            .report_block_builder
+            .build_report_blocks(&self.receive_stats.value(), now);
+        let mut packets = Vec::new();
+        for chunk in report_blocks.chunks(MAX_REPORT_BLOCKS) {
+            packets.push(self.receiver_report(chunk.to_vec()));
+        }
+        // We always need to send at least one report, even if it's empty
+        if packets.is_empty() {
+            packets.push(self.receiver_report(Vec::new()));
+        }
+        packets.push(build_cname_sdes(self.sender_ssrc, &self.cname));
+        let rrtr = RtcpXrPacket {
+            sender_ssrc: self.sender_ssrc,
+            report_blocks: vec![XrReportBlock::ReceiverReferenceTime {
+                ntp_timestamp: to_ntp_timestamp(system_time),
+            }],
+        };
+        packets.push(rrtr.to_rtcp_packet());
+
+        SomeRtcpPacket::CompoundRtcpPacket(packets)
+    }
+
+    fn receiver_report(&self, report_blocks: Vec<RtcpReportBlock>) -> SomeRtcpPacket {
+        SomeRtcpPacket::RtcpRrPacket(RtcpRrPacket {
+            header: new_rtcp_header(report_blocks.len() as u8, RTCP_PT_RR),
+            sender_ssrc: self.sender_ssrc,
+            report_blocks,
+        })
+    }
+}
+
+impl TimerHandler for RrGenerator {
+    fn on_timer(&mut self, _now: Instant) -> Option<Instant> {
+        self.maybe_send_report();
+        self.scheduler.next_report_time()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use crate::{clock::ManualClock, util::LiveStateWriter};
+
+    use super::*;
+
+    fn stats(highest: u32, received: u64, jitter: f64) -> StreamReceiveStatistics {
+        StreamReceiveStatistics {
+            base_seq_num: 0,
+            extended_highest_seq_num: highest,
+            packets_received: received,
+            jitter,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_build_report() {
+        let receive_stats = LiveStateWriter::new(HashMap::from([(1234, stats(99, 90, 12.7))]));
+        let (tx, _rx) = unbounded_channel();
+        let clock = Arc::new(ManualClock::new());
+        let mut generator = RrGenerator::new(
+            clock.clone(),
+            42,
+            String::from("cname"),
+            receive_stats.reader(),
+            RtcpScheduler::new(8000.0, Duration::from_secs(1)),
+            tx,
+        );
+        let start = clock.now();
+        generator.handle_event(&RtcpEvent::SenderReport {
+            ssrc: 1234,
+            ntp_timestamp: 0x1122334455667788,
+            rtp_timestamp: 0,
+            packet_count: 0,
+            octet_count: 0,
+            received_time: start,
+        });
+
+        let report =
+            generator.build_report(start + Duration::from_millis(500), clock.system_time());
+        let SomeRtcpPacket::CompoundRtcpPacket(packets) = report else {
+            panic!("expected compound packet");
+        };
+        assert_eq!(packets.len(), 3);
+        let SomeRtcpPacket::RtcpRrPacket(ref rr) = packets[0] else {
+            panic!("expected rr");
+        };
+        assert_eq!(rr.sender_ssrc, 42);
+        assert_eq!(
+            rr.report_blocks,
+            vec![RtcpReportBlock {
+                ssrc: 1234,
+                // 10 out of 100 lost
+                fraction_lost: 25,
+                cumulative_lost: 10,
+                extended_highest_seq_num: 99,
+                interarrival_jitter: 12,
+                last_sr_timestamp: 0x33445566,
+                delay_since_last_sr: 0x8000,
+            }]
+        );
+        assert!(matches!(packets[1], SomeRtcpPacket::RtcpSdesPacket(_)));
+        let SomeRtcpPacket::UnknownRtcpPacket { ref payload, .. } = packets[2] else {
+            panic!("expected xr");
+        };
+        assert_eq!(
+            RtcpXrPacket::parse(payload).unwrap().report_blocks,
+            vec![XrReportBlock::ReceiverReferenceTime {
+                ntp_timestamp: to_ntp_timestamp(clock.system_time())
+            }]
+        );
+
+        // Nothing new has been received, so there's nothing to report on
+        let report = generator.build_report(start + Duration::from_secs(1), clock.system_time());
+        let SomeRtcpPacket::CompoundRtcpPacket(packets) = report else {
+            panic!("expected compound packet");
+        };
+        assert!(
+            matches!(packets[0], SomeRtcpPacket::RtcpRrPacket(ref rr) if rr.report_blocks.is_empty())
+        );
+
+        // The fraction lost only covers the packets since the last report
+        receive_stats.set(HashMap::from([(1234, stats(199, 190, 12.7))]));
+        let report = generator.build_report(start + Duration::from_secs(2), clock.system_time());
+        let SomeRtcpPacket::CompoundRtcpPacket(packets) = report else {
+            panic!("expected compound packet");
+        };
+        let SomeRtcpPacket::RtcpRrPacket(ref rr) = packets[0] else {
+            panic!("expected rr");
+        };
+        assert_eq!(rr.report_blocks[0].fraction_lost, 0);
+        assert_eq!(rr.report_blocks[0].cumulative_lost, 10);
+    }
+
+    #[test]
+    fn test_negative_cumulative_lost() {
+        let mut builder = ReportBlockBuilder::default();
+        let receive_stats = HashMap::from([(1234, stats(9, 12, 0.0))]);
+
+        let blocks = builder.build_report_blocks(&receive_stats, Instant::now());
+        assert_eq!(blocks[0].cumulative_lost, 0xFFFFFE);
+        assert_eq!(blocks[0].fraction_lost, 0);
+    }
+
+    #[test]
+    fn test_maybe_send_report() {
+        let receive_stats = LiveStateWriter::new(HashMap::new());
+        let (tx, mut rx) = unbounded_channel();
+        let clock = Arc::new(ManualClock::new());
+        let mut generator = RrGenerator::new(
+            clock.clone(),
+            42,
+            String::from("cname"),
+            receive_stats.reader(),
+            RtcpScheduler::new(8000.0, Duration::from_secs(1)),
+            tx,
+        );
+
+        generator.maybe_send_report();
+        assert!(rx.try_recv().is_err());
+        clock.advance(Duration::from_secs(2));
+        generator.maybe_send_report();
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(SomeRtcpPacket::CompoundRtcpPacket(_))
+        ));
+        assert_eq!(
+            generator
+                .rrtr_history()
+                .read()
+                .sent_time(compact_ntp(to_ntp_timestamp(clock.system_time()))),
+            Some(clock.now())
+        );
+    }
+
+    #[test]
+    fn test_reports_sent_from_timer() {
+        let receive_stats = LiveStateWriter::new(HashMap::new());
+        let (tx, mut rx) = unbounded_channel();
+        let clock = Arc::new(ManualClock::new());
+        let timers = TimerScheduler::new(clock.clone());
+        let (generator, _timer) = RrGenerator::new(
+            clock.clone(),
+            42,
+            String::from("cname"),
+            receive_stats.reader(),
+            RtcpScheduler::new(8000.0, Duration::from_secs(1)),
+            tx,
+        )
+        .register(&timers);
+
+        // The first check only schedules the first report
+        let first_report_time = timers.fire_due().unwrap();
+        assert!(rx.try_recv().is_err());
+
+        clock.advance(first_report_time.duration_since(clock.now()));
+        let next_report_time = timers.fire_due().unwrap();
+        assert!(next_report_time > clock.now());
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(SomeRtcpPacket::CompoundRtcpPacket(_))
+        ));
+        assert!(generator
+            .read()
+            .rrtr_history()
+            .read()
+            .sent_time(compact_ntp(to_ntp_timestamp(clock.system_time())))
+            .is_some());
+    }
+}
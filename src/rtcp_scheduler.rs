@@ -0,0 +1,158 @@
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+/// The fraction of the rtcp bandwidth given to senders
+/// https://datatracker.ietf.org/doc/html/rfc3550#section-6.2
+const SENDER_BANDWIDTH_FRACTION: f64 = 0.25;
+const RECEIVER_BANDWIDTH_FRACTION: f64 = 1.0 - SENDER_BANDWIDTH_FRACTION;
+/// Compensates for the "timer reconsideration" algorithm converging to a value below the intended
+/// average. https://datatracker.ietf.org/doc/html/rfc3550#appendix-A.7
+const COMPENSATION: f64 = std::f64::consts::E - 1.5;
+/// The per-packet UDP and IPv4 header overhead, which is included in the average rtcp packet size
+const UDP_IP_OVERHEAD: usize = 28;
+
+/// Decides when the next RTCP report should be sent, using the interval calculation from
+/// https://datatracker.ietf.org/doc/html/rfc3550#section-6.3.1 (minus timer reconsideration,
+/// since in practice the group sizes we deal with are small and stable).  This is shared by the
+/// sender and receiver report generators.
+pub struct RtcpScheduler {
+    /// The bandwidth available for rtcp, in bits per second.  RFC 3550 recommends this be 5% of
+    /// the session bandwidth.
+    rtcp_bandwidth_bps: f64,
+    /// The minimum (deterministic) interval between reports.  RFC 3550 recommends 5 seconds, but
+    /// allows it to be reduced.
+    min_interval: Duration,
+    /// The average size of the rtcp packets we've sent, in bytes
+    avg_rtcp_size: f64,
+    /// Whether we've sent a report yet
+    initial: bool,
+    next_report_time: Option<Instant>,
+}
+
+impl RtcpScheduler {
+    pub fn new(rtcp_bandwidth_bps: f64, min_interval: Duration) -> Self {
+        Self {
+            rtcp_bandwidth_bps,
+            min_interval,
+            // A guess at the size of a compound report with a single report block, refined as
+            // reports are sent
+            avg_rtcp_size: 100.0,
+            initial: true,
+            next_report_time: None,
+        }
+    }
+
+    pub fn next_report_time(&self) -> Option<Instant> {
+        self.next_report_time
+    }
+
+    /// Returns true if it's time to send a report.  The first call schedules the initial report.
+    ///
+    /// * `members`: The number of participants in the session, including us
+    /// * `senders`: The number of participants which are sending media, including us
+    /// * `we_sent`: Whether we've sent media since the last report
+    pub fn is_due(&mut self, now: Instant, members: usize, senders: usize, we_sent: bool) -> bool {
+        match self.next_report_time {
+            Some(next_report_time) => now >= next_report_time,
+            None => {
+                self.next_report_time =
+                    Some(now + self.randomized_interval(members, senders, we_sent));
+                false
+            }
+        }
+    }
+
+    /// Record that a report of `packet_size` bytes was sent at `now` and schedule the next one.
+    pub fn report_sent(
+        &mut self,
+        now: Instant,
+        packet_size: usize,
+        members: usize,
+        senders: usize,
+        we_sent: bool,
+    ) {
+        self.avg_rtcp_size += ((packet_size + UDP_IP_OVERHEAD) as f64 - self.avg_rtcp_size) / 16.0;
+        self.initial = false;
+        self.next_report_time = Some(now + self.randomized_interval(members, senders, we_sent));
+    }
+
+    /// Schedule the next report without having sent one, e.g. because there was nothing to report.
+    pub fn report_skipped(&mut self, now: Instant, members: usize, senders: usize, we_sent: bool) {
+        self.next_report_time = Some(now + self.randomized_interval(members, senders, we_sent));
+    }
+
+    /// The deterministic interval from
+    /// https://datatracker.ietf.org/doc/html/rfc3550#appendix-A.7, before it's randomized.
+    pub fn interval(&self, members: usize, senders: usize, we_sent: bool) -> Duration {
+        let min_interval = if self.initial {
+            self.min_interval / 2
+        } else {
+            self.min_interval
+        };
+
+        // If there are few enough senders then they get their own share of the bandwidth,
+        // otherwise everyone shares it equally.
+        let mut n = members.max(1) as f64;
+        let mut rtcp_bandwidth = self.rtcp_bandwidth_bps / 8.0;
+        if senders as f64 <= members as f64 * SENDER_BANDWIDTH_FRACTION {
+            if we_sent {
+                rtcp_bandwidth *= SENDER_BANDWIDTH_FRACTION;
+                n = senders.max(1) as f64;
+            } else {
+                rtcp_bandwidth *= RECEIVER_BANDWIDTH_FRACTION;
+                n = (members - senders).max(1) as f64;
+            }
+        }
+
+        let interval = Duration::from_secs_f64(self.avg_rtcp_size * n / rtcp_bandwidth);
+        interval.max(min_interval)
+    }
+
+    fn randomized_interval(&self, members: usize, senders: usize, we_sent: bool) -> Duration {
+        let interval = self.interval(members, senders, we_sent);
+        interval.mul_f64(rand::thread_rng().gen_range(0.5..1.5) / COMPENSATION)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interval() {
+        let mut scheduler = RtcpScheduler::new(8000.0, Duration::from_secs(5));
+
+        // The initial interval uses half the minimum
+        assert_eq!(scheduler.interval(2, 1, false), Duration::from_millis(2500));
+        scheduler.report_sent(Instant::now(), 72, 2, 1, false);
+        assert_eq!(scheduler.interval(2, 1, false), Duration::from_secs(5));
+
+        // With lots of receivers the bandwidth limit kicks in: (100 bytes * 100 receivers) / (1000
+        // bytes/s * 0.75)
+        assert_eq!(
+            scheduler.interval(101, 1, false).as_millis(),
+            (100.0 * 100.0 / 750.0 * 1000.0) as u128
+        );
+        // While the single sender gets its own share of the bandwidth
+        assert_eq!(scheduler.interval(101, 1, true), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_is_due() {
+        let mut scheduler = RtcpScheduler::new(8000.0, Duration::from_secs(1));
+        let start = Instant::now();
+
+        assert!(!scheduler.is_due(start, 2, 1, false));
+        let next_report_time = scheduler.next_report_time().unwrap();
+        // The randomized initial interval is between 0.5 and 1.5 times half the minimum, divided
+        // by the compensation factor
+        assert!(next_report_time >= start + Duration::from_millis(500).mul_f64(0.5 / COMPENSATION));
+        assert!(next_report_time <= start + Duration::from_millis(500).mul_f64(1.5 / COMPENSATION));
+        assert!(!scheduler.is_due(start, 2, 1, false));
+        assert!(scheduler.is_due(next_report_time, 2, 1, false));
+
+        scheduler.report_sent(next_report_time, 72, 2, 1, false);
+        assert!(scheduler.next_report_time().unwrap() > next_report_time);
+    }
+}
@@ -1,52 +1,227 @@
+use std::time::Instant;
+
 use data_pipeline_rs::data_handler::{DataFilter, SomeDataHandler};
-use rtp_parse::rtcp::rtcp_packet::SomeRtcpPacket;
+use rtp_parse::rtcp::{
+    rtcp_fb_tcc::RtcpFbTccPacket, rtcp_packet::SomeRtcpPacket, rtcp_report_block::RtcpReportBlock,
+    rtcp_sdes::SdesItem,
+};
+use tokio::sync::broadcast::Sender;
 
-use crate::packet_info::{PacketInfo, SomePacket};
+use crate::{
+    error::{DroppedPackets, PipelineError},
+    packet_info::{PacketInfo, SomePacket},
+    rtcp_writer::RTCP_PT_XR,
+    rtcp_xr::{DlrrSubBlock, RtcpXrPacket, XrReportBlock},
+    util::SharedData,
+};
 
-// TODO: we'll have a tokio::sync::broadcast::Sender here to emit events.  its send method isn't
-// async so we can call it directly
-#[derive(Default)]
-pub struct RtcpTermination;
+/// Events describing the RTCP received from the remote side
+#[derive(Clone, Debug, PartialEq)]
+pub enum RtcpEvent {
+    SenderReport {
+        ssrc: u32,
+        ntp_timestamp: u64,
+        rtp_timestamp: u32,
+        packet_count: u32,
+        octet_count: u32,
+        received_time: Instant,
+    },
+    /// Report blocks carried in either a receiver report or a sender report
+    ReportBlocks {
+        sender_ssrc: u32,
+        report_blocks: Vec<RtcpReportBlock>,
+        received_time: Instant,
+    },
+    Bye {
+        ssrcs: Vec<u32>,
+        reason: Option<String>,
+    },
+    Nack {
+        sender_ssrc: u32,
+        media_source_ssrc: u32,
+        missing_seq_nums: Vec<u16>,
+    },
+    /// A PLI or FIR asking for a keyframe on the given media ssrc
+    KeyframeRequest {
+        sender_ssrc: u32,
+        media_source_ssrc: u32,
+    },
+    TccFeedback {
+        feedback: RtcpFbTccPacket,
+        received_time: Instant,
+    },
+    Cname {
+        ssrc: u32,
+        cname: String,
+    },
+    /// An XR receiver reference time report block
+    ReceiverReferenceTime {
+        ssrc: u32,
+        ntp_timestamp: u64,
+        received_time: Instant,
+    },
+    /// An XR DLRR report block, in response to receiver reference times we sent
+    Dlrr {
+        sender_ssrc: u32,
+        sub_blocks: Vec<DlrrSubBlock>,
+        received_time: Instant,
+    },
+}
 
-impl DataFilter<PacketInfo> for RtcpTermination {
-    fn should_forward(&mut self, data: &PacketInfo) -> bool {
-        let rtcp = match &data.packet {
-            SomePacket::RtcpPacket(rtcp) => rtcp,
-            _ => panic!(
-                "RTCP termination received non-rtcp packet {:x?}",
-                data.packet
-            ),
-        };
-        match rtcp {
+/// Terminates all incoming RTCP, publishing what was received as [`RtcpEvent`]s for whoever is
+/// interested.  Since [`Sender::send`] isn't async, it can be called directly from the pipeline.
+pub struct RtcpTermination {
+    events: Sender<RtcpEvent>,
+    dropped_packets: SharedData<DroppedPackets>,
+}
+
+impl RtcpTermination {
+    pub fn new(events: Sender<RtcpEvent>, dropped_packets: SharedData<DroppedPackets>) -> Self {
+        Self {
+            events,
+            dropped_packets,
+        }
+    }
+
+    fn publish(&self, event: RtcpEvent) {
+        // An error here just means there are currently no subscribers
+        let _ = self.events.send(event);
+    }
+
+    fn handle_packet(&self, packet: &SomeRtcpPacket, received_time: Instant) {
+        match packet {
             SomeRtcpPacket::CompoundRtcpPacket(packets) => {
-                println!("got compound rtcp");
                 for packet in packets {
-                    match packet {
-                        SomeRtcpPacket::RtcpRrPacket(_) => println!("got rr"),
-                        SomeRtcpPacket::RtcpByePacket(_) => println!("got bye"),
-                        SomeRtcpPacket::RtcpSrPacket(_) => println!("got sr"),
-                        SomeRtcpPacket::RtcpSdesPacket(_) => println!("got sdes"),
-                        SomeRtcpPacket::RtcpFbNackPacket(_) => println!("got nack"),
-                        SomeRtcpPacket::RtcpFbFirPacket(_) => println!("got fir"),
-                        SomeRtcpPacket::RtcpFbTccPacket(_) => println!("got tcc"),
-                        SomeRtcpPacket::RtcpFbPliPacket(_) => println!("got pli"),
-                        SomeRtcpPacket::UnknownRtcpPacket { .. } => println!("got unknown"),
-                        SomeRtcpPacket::CompoundRtcpPacket(_) => {
-                            panic!("compound inside compound is invalid")
+                    if matches!(packet, SomeRtcpPacket::CompoundRtcpPacket(_)) {
+                        self.dropped_packets
+                            .write()
+                            .record(&PipelineError::parse_failure(
+                                "compound rtcp packet",
+                                anyhow::anyhow!("compound packet nested in a compound packet"),
+                            ));
+                        continue;
+                    }
+                    self.handle_packet(packet, received_time);
+                }
+            }
+            SomeRtcpPacket::RtcpSrPacket(sr) => {
+                let sender_info = &sr.sender_info;
+                self.publish(RtcpEvent::SenderReport {
+                    ssrc: sr.sender_ssrc,
+                    ntp_timestamp: ((sender_info.ntp_timestamp_msw as u64) << 32)
+                        | sender_info.ntp_timestamp_lsw as u64,
+                    rtp_timestamp: sender_info.rtp_timestamp,
+                    packet_count: sender_info.sender_packet_count,
+                    octet_count: sender_info.sender_octet_count,
+                    received_time,
+                });
+                if !sr.report_blocks.is_empty() {
+                    self.publish(RtcpEvent::ReportBlocks {
+                        sender_ssrc: sr.sender_ssrc,
+                        report_blocks: sr.report_blocks.clone(),
+                        received_time,
+                    });
+                }
+            }
+            SomeRtcpPacket::RtcpRrPacket(rr) => {
+                if !rr.report_blocks.is_empty() {
+                    self.publish(RtcpEvent::ReportBlocks {
+                        sender_ssrc: rr.sender_ssrc,
+                        report_blocks: rr.report_blocks.clone(),
+                        received_time,
+                    });
+                }
+            }
+            SomeRtcpPacket::RtcpByePacket(bye) => self.publish(RtcpEvent::Bye {
+                ssrcs: bye.ssrcs.clone(),
+                reason: bye.reason.clone(),
+            }),
+            SomeRtcpPacket::RtcpSdesPacket(sdes) => {
+                for chunk in &sdes.chunks {
+                    for item in &chunk.sdes_items {
+                        if let SdesItem::Cname(cname) = item {
+                            self.publish(RtcpEvent::Cname {
+                                ssrc: chunk.ssrc,
+                                cname: cname.clone(),
+                            });
                         }
                     }
                 }
             }
-            SomeRtcpPacket::RtcpRrPacket(_) => println!("got rr"),
-            SomeRtcpPacket::RtcpByePacket(_) => println!("got bye"),
-            SomeRtcpPacket::RtcpSrPacket(_) => println!("got sr"),
-            SomeRtcpPacket::RtcpSdesPacket(_) => println!("got sdes"),
-            SomeRtcpPacket::RtcpFbNackPacket(_) => println!("got nack"),
-            SomeRtcpPacket::RtcpFbFirPacket(_) => println!("got fir"),
-            SomeRtcpPacket::RtcpFbTccPacket(_) => println!("got tcc"),
-            SomeRtcpPacket::RtcpFbPliPacket(_) => println!("got pli"),
-            SomeRtcpPacket::UnknownRtcpPacket { .. } => println!("got unknown"),
+            SomeRtcpPacket::RtcpFbNackPacket(nack) => self.publish(RtcpEvent::Nack {
+                sender_ssrc: nack.fb_header.sender_ssrc,
+                media_source_ssrc: nack.fb_header.media_source_ssrc,
+                missing_seq_nums: nack.missing_seq_nums.clone(),
+            }),
+            SomeRtcpPacket::RtcpFbPliPacket(pli) => self.publish(RtcpEvent::KeyframeRequest {
+                sender_ssrc: pli.fb_header.sender_ssrc,
+                media_source_ssrc: pli.fb_header.media_source_ssrc,
+            }),
+            SomeRtcpPacket::RtcpFbFirPacket(fir) => {
+                // The media source ssrc in the feedback header isn't used for FIR, the target
+                // ssrcs are carried in the FCI entries instead
+                for fci in &fir.fcis {
+                    self.publish(RtcpEvent::KeyframeRequest {
+                        sender_ssrc: fir.fb_header.sender_ssrc,
+                        media_source_ssrc: fci.ssrc,
+                    });
+                }
+            }
+            SomeRtcpPacket::RtcpFbTccPacket(tcc) => self.publish(RtcpEvent::TccFeedback {
+                feedback: tcc.clone(),
+                received_time,
+            }),
+            SomeRtcpPacket::UnknownRtcpPacket { header, payload }
+                if header.packet_type == RTCP_PT_XR =>
+            {
+                match RtcpXrPacket::parse(payload) {
+                    Ok(xr) => self.handle_xr(xr, received_time),
+                    Err(e) => self
+                        .dropped_packets
+                        .write()
+                        .record(&PipelineError::parse_failure("xr packet", e)),
+                }
+            }
+            SomeRtcpPacket::UnknownRtcpPacket { .. } => {}
+        }
+    }
+
+    fn handle_xr(&self, xr: RtcpXrPacket, received_time: Instant) {
+        for block in xr.report_blocks {
+            match block {
+                XrReportBlock::ReceiverReferenceTime { ntp_timestamp } => {
+                    self.publish(RtcpEvent::ReceiverReferenceTime {
+                        ssrc: xr.sender_ssrc,
+                        ntp_timestamp,
+                        received_time,
+                    })
+                }
+                XrReportBlock::Dlrr(sub_blocks) => self.publish(RtcpEvent::Dlrr {
+                    sender_ssrc: xr.sender_ssrc,
+                    sub_blocks,
+                    received_time,
+                }),
+                XrReportBlock::Unknown { .. } => {}
+            }
+        }
+    }
+}
+
+impl DataFilter<PacketInfo> for RtcpTermination {
+    fn should_forward(&mut self, data: &PacketInfo) -> bool {
+        let rtcp = match &data.packet {
+            SomePacket::RtcpPacket(rtcp) => rtcp,
+            packet => {
+                self.dropped_packets
+                    .write()
+                    .record(&PipelineError::unexpected_packet_type(
+                        "RtcpTermination",
+                        packet,
+                    ));
+                return false;
+            }
         };
+        self.handle_packet(rtcp, data.received_time);
         false
     }
 }
@@ -56,3 +231,110 @@ impl From<RtcpTermination> for SomeDataHandler<PacketInfo> {
         SomeDataHandler::Filter(Box::new(value))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rtp_parse::rtcp::{
+        rtcp_fb_fir::{RtcpFbFirFci, RtcpFbFirPacket},
+        rtcp_fb_header::RtcpFbHeader,
+        rtcp_sdes::{RtcpSdesPacket, SdesChunk},
+        rtcp_sr::{RtcpSrPacket, RtcpSrSenderInfo},
+    };
+    use tokio::sync::broadcast;
+
+    use crate::{
+        error::PipelineErrorKind,
+        rtcp_writer::{new_rtcp_header, FMT_FIR, RTCP_PT_PSFB, RTCP_PT_SDES, RTCP_PT_SR},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_rtcp_events() {
+        let (tx, mut rx) = broadcast::channel(16);
+        let mut termination = RtcpTermination::new(tx, SharedData::default());
+        let now = Instant::now();
+
+        let compound = SomeRtcpPacket::CompoundRtcpPacket(vec![
+            SomeRtcpPacket::RtcpSrPacket(RtcpSrPacket {
+                header: new_rtcp_header(0, RTCP_PT_SR),
+                sender_ssrc: 1234,
+                sender_info: RtcpSrSenderInfo {
+                    ntp_timestamp_msw: 0x00000001,
+                    ntp_timestamp_lsw: 0x80000000,
+                    rtp_timestamp: 9000,
+                    sender_packet_count: 10,
+                    sender_octet_count: 1000,
+                },
+                report_blocks: vec![],
+            }),
+            SomeRtcpPacket::RtcpSdesPacket(RtcpSdesPacket {
+                header: new_rtcp_header(1, RTCP_PT_SDES),
+                chunks: vec![SdesChunk {
+                    ssrc: 1234,
+                    sdes_items: vec![SdesItem::Cname(String::from("cname"))],
+                }],
+            }),
+            SomeRtcpPacket::RtcpFbFirPacket(RtcpFbFirPacket {
+                header: new_rtcp_header(FMT_FIR, RTCP_PT_PSFB),
+                fb_header: RtcpFbHeader {
+                    sender_ssrc: 1234,
+                    media_source_ssrc: 0,
+                },
+                fcis: vec![RtcpFbFirFci {
+                    ssrc: 5678,
+                    seq_num: 1,
+                }],
+            }),
+        ]);
+
+        assert!(
+            !termination.should_forward(&PacketInfo::new(SomePacket::RtcpPacket(compound), now))
+        );
+
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            RtcpEvent::SenderReport {
+                ssrc: 1234,
+                ntp_timestamp: 0x00000001_80000000,
+                rtp_timestamp: 9000,
+                packet_count: 10,
+                octet_count: 1000,
+                received_time: now,
+            }
+        );
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            RtcpEvent::Cname {
+                ssrc: 1234,
+                cname: String::from("cname"),
+            }
+        );
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            RtcpEvent::KeyframeRequest {
+                sender_ssrc: 1234,
+                media_source_ssrc: 5678,
+            }
+        );
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_unexpected_packets_counted() {
+        let (tx, _rx) = broadcast::channel(16);
+        let dropped_packets = SharedData::default();
+        let mut termination = RtcpTermination::new(tx, dropped_packets.clone());
+
+        let packet_info = PacketInfo::new(SomePacket::UnparsedPacket(vec![]), Instant::now());
+        assert!(!termination.should_forward(&packet_info));
+        assert!(!termination.should_forward(&packet_info));
+
+        let dropped_packets = dropped_packets.read();
+        assert_eq!(
+            dropped_packets.count(PipelineErrorKind::UnexpectedPacketType),
+            2
+        );
+        assert_eq!(dropped_packets.total(), 2);
+    }
+}
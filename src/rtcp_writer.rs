@@ -0,0 +1,514 @@
+use anyhow::{bail, Result};
+use bit_cursor::nsw_types::{u2, u5};
+use rtp_parse::rtcp::{
+    rtcp_bye::RtcpByePacket,
+    rtcp_fb_fir::RtcpFbFirPacket,
+    rtcp_fb_header::RtcpFbHeader,
+    rtcp_fb_nack::RtcpFbNackPacket,
+    rtcp_fb_pli::RtcpFbPliPacket,
+    rtcp_fb_tcc::{PacketReport, RtcpFbTccPacket},
+    rtcp_header::RtcpHeader,
+    rtcp_packet::SomeRtcpPacket,
+    rtcp_report_block::RtcpReportBlock,
+    rtcp_rr::RtcpRrPacket,
+    rtcp_sdes::RtcpSdesPacket,
+    rtcp_sdes::SdesItem,
+    rtcp_sr::RtcpSrPacket,
+};
+
+pub const RTCP_PT_SR: u8 = 200;
+pub const RTCP_PT_RR: u8 = 201;
+pub const RTCP_PT_SDES: u8 = 202;
+pub const RTCP_PT_BYE: u8 = 203;
+pub const RTCP_PT_RTPFB: u8 = 205;
+pub const RTCP_PT_PSFB: u8 = 206;
+pub const RTCP_PT_XR: u8 = 207;
+
+pub const FMT_NACK: u8 = 1;
+pub const FMT_PLI: u8 = 1;
+pub const FMT_FIR: u8 = 4;
+pub const FMT_TCC: u8 = 15;
+
+const SDES_ITEM_CNAME: u8 = 1;
+
+const TCC_SYMBOL_NOT_RECEIVED: u8 = 0;
+const TCC_SYMBOL_SMALL_DELTA: u8 = 1;
+const TCC_SYMBOL_LARGE_DELTA: u8 = 2;
+const TCC_MAX_RUN_LENGTH: usize = 0x1FFF;
+const TCC_ONE_BIT_VECTOR_CAPACITY: usize = 14;
+const TCC_TWO_BIT_VECTOR_CAPACITY: usize = 7;
+
+/// Create an [`RtcpHeader`] for a packet that is going to be built locally.  The length field is
+/// left empty, since it's filled in when the packet is written.
+pub fn new_rtcp_header(count: u8, packet_type: u8) -> RtcpHeader {
+    RtcpHeader {
+        version: u2::new(2),
+        has_padding: false,
+        report_count: u5::new(count),
+        packet_type,
+        length_field: 0,
+    }
+}
+
+/// Serialize the given [`SomeRtcpPacket`] into its wire format.  The values in each packet's
+/// header are not trusted: the length and count fields are derived from the packet's contents.
+pub fn write_rtcp_packet(packet: &SomeRtcpPacket) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    write_rtcp_packet_into(&mut buf, packet)?;
+    Ok(buf)
+}
+
+fn write_rtcp_packet_into(buf: &mut Vec<u8>, packet: &SomeRtcpPacket) -> Result<()> {
+    match packet {
+        SomeRtcpPacket::CompoundRtcpPacket(packets) => {
+            for packet in packets {
+                if matches!(packet, SomeRtcpPacket::CompoundRtcpPacket(_)) {
+                    bail!("compound inside compound is invalid");
+                }
+                write_rtcp_packet_into(buf, packet)?;
+            }
+        }
+        SomeRtcpPacket::RtcpSrPacket(sr) => write_sr(buf, sr)?,
+        SomeRtcpPacket::RtcpRrPacket(rr) => write_rr(buf, rr)?,
+        SomeRtcpPacket::RtcpSdesPacket(sdes) => write_sdes(buf, sdes)?,
+        SomeRtcpPacket::RtcpByePacket(bye) => write_bye(buf, bye)?,
+        SomeRtcpPacket::RtcpFbPliPacket(pli) => write_pli(buf, pli),
+        SomeRtcpPacket::RtcpFbFirPacket(fir) => write_fir(buf, fir)?,
+        SomeRtcpPacket::RtcpFbNackPacket(nack) => write_nack(buf, nack)?,
+        SomeRtcpPacket::RtcpFbTccPacket(tcc) => write_tcc(buf, tcc)?,
+        SomeRtcpPacket::UnknownRtcpPacket { header, payload } => {
+            let start = begin_packet(buf, header.report_count.into(), header.packet_type);
+            buf.extend_from_slice(payload);
+            finish_packet(buf, start);
+        }
+    }
+    Ok(())
+}
+
+/// Write an RTCP header with a placeholder length, returning the offset of the start of the
+/// packet so that [`finish_packet`] can fill the length in once the body has been written.
+pub(crate) fn begin_packet(buf: &mut Vec<u8>, count: u8, packet_type: u8) -> usize {
+    let start = buf.len();
+    // Version 2, no padding
+    buf.push(0b1000_0000 | (count & 0b0001_1111));
+    buf.push(packet_type);
+    buf.extend_from_slice(&[0, 0]);
+    start
+}
+
+/// Pad the packet which began at `start` to a 32 bit boundary and write its length field.
+pub(crate) fn finish_packet(buf: &mut Vec<u8>, start: usize) {
+    pad_to_word_boundary(buf, start);
+    let length_field = ((buf.len() - start) / 4 - 1) as u16;
+    buf[start + 2..start + 4].copy_from_slice(&length_field.to_be_bytes());
+}
+
+fn pad_to_word_boundary(buf: &mut Vec<u8>, start: usize) {
+    let padding = (4 - (buf.len() - start) % 4) % 4;
+    buf.resize(buf.len() + padding, 0);
+}
+
+fn report_count(count: usize) -> Result<u8> {
+    if count > 31 {
+        bail!("too many items for a single rtcp packet: {count}");
+    }
+    Ok(count as u8)
+}
+
+pub(crate) fn write_fb_header(buf: &mut Vec<u8>, fb_header: &RtcpFbHeader) {
+    buf.extend_from_slice(&fb_header.sender_ssrc.to_be_bytes());
+    buf.extend_from_slice(&fb_header.media_source_ssrc.to_be_bytes());
+}
+
+fn write_report_blocks(buf: &mut Vec<u8>, report_blocks: &[RtcpReportBlock]) {
+    for block in report_blocks {
+        buf.extend_from_slice(&block.ssrc.to_be_bytes());
+        buf.push(block.fraction_lost);
+        // Cumulative lost is a 24 bit field
+        buf.extend_from_slice(&block.cumulative_lost.to_be_bytes()[1..]);
+        buf.extend_from_slice(&block.extended_highest_seq_num.to_be_bytes());
+        buf.extend_from_slice(&block.interarrival_jitter.to_be_bytes());
+        buf.extend_from_slice(&block.last_sr_timestamp.to_be_bytes());
+        buf.extend_from_slice(&block.delay_since_last_sr.to_be_bytes());
+    }
+}
+
+fn write_sr(buf: &mut Vec<u8>, sr: &RtcpSrPacket) -> Result<()> {
+    let start = begin_packet(buf, report_count(sr.report_blocks.len())?, RTCP_PT_SR);
+    buf.extend_from_slice(&sr.sender_ssrc.to_be_bytes());
+    let sender_info = &sr.sender_info;
+    buf.extend_from_slice(&sender_info.ntp_timestamp_msw.to_be_bytes());
+    buf.extend_from_slice(&sender_info.ntp_timestamp_lsw.to_be_bytes());
+    buf.extend_from_slice(&sender_info.rtp_timestamp.to_be_bytes());
+    buf.extend_from_slice(&sender_info.sender_packet_count.to_be_bytes());
+    buf.extend_from_slice(&sender_info.sender_octet_count.to_be_bytes());
+    write_report_blocks(buf, &sr.report_blocks);
+    finish_packet(buf, start);
+    Ok(())
+}
+
+fn write_rr(buf: &mut Vec<u8>, rr: &RtcpRrPacket) -> Result<()> {
+    let start = begin_packet(buf, report_count(rr.report_blocks.len())?, RTCP_PT_RR);
+    buf.extend_from_slice(&rr.sender_ssrc.to_be_bytes());
+    write_report_blocks(buf, &rr.report_blocks);
+    finish_packet(buf, start);
+    Ok(())
+}
+
+fn write_sdes(buf: &mut Vec<u8>, sdes: &RtcpSdesPacket) -> Result<()> {
+    let start = begin_packet(buf, report_count(sdes.chunks.len())?, RTCP_PT_SDES);
+    for chunk in &sdes.chunks {
+        let chunk_start = buf.len();
+        buf.extend_from_slice(&chunk.ssrc.to_be_bytes());
+        for item in &chunk.sdes_items {
+            let (item_type, data) = match item {
+                SdesItem::Empty => continue,
+                SdesItem::Cname(cname) => (SDES_ITEM_CNAME, cname.as_bytes()),
+                SdesItem::Unknown { item_type, data } => (*item_type, data.as_slice()),
+            };
+            if data.len() > u8::MAX as usize {
+                bail!("sdes item too long: {} bytes", data.len());
+            }
+            buf.push(item_type);
+            buf.push(data.len() as u8);
+            buf.extend_from_slice(data);
+        }
+        // The item list is terminated by at least one null octet, followed by padding to the
+        // next 32 bit boundary
+        buf.push(0);
+        pad_to_word_boundary(buf, chunk_start);
+    }
+    finish_packet(buf, start);
+    Ok(())
+}
+
+fn write_bye(buf: &mut Vec<u8>, bye: &RtcpByePacket) -> Result<()> {
+    let start = begin_packet(buf, report_count(bye.ssrcs.len())?, RTCP_PT_BYE);
+    for ssrc in &bye.ssrcs {
+        buf.extend_from_slice(&ssrc.to_be_bytes());
+    }
+    if let Some(ref reason) = bye.reason {
+        if reason.len() > u8::MAX as usize {
+            bail!("bye reason too long: {} bytes", reason.len());
+        }
+        buf.push(reason.len() as u8);
+        buf.extend_from_slice(reason.as_bytes());
+    }
+    finish_packet(buf, start);
+    Ok(())
+}
+
+fn write_pli(buf: &mut Vec<u8>, pli: &RtcpFbPliPacket) {
+    let start = begin_packet(buf, FMT_PLI, RTCP_PT_PSFB);
+    write_fb_header(buf, &pli.fb_header);
+    finish_packet(buf, start);
+}
+
+fn write_fir(buf: &mut Vec<u8>, fir: &RtcpFbFirPacket) -> Result<()> {
+    let start = begin_packet(buf, FMT_FIR, RTCP_PT_PSFB);
+    write_fb_header(buf, &fir.fb_header);
+    for fci in &fir.fcis {
+        buf.extend_from_slice(&fci.ssrc.to_be_bytes());
+        buf.push(fci.seq_num);
+        buf.extend_from_slice(&[0, 0, 0]);
+    }
+    finish_packet(buf, start);
+    Ok(())
+}
+
+// https://datatracker.ietf.org/doc/html/rfc4585#section-6.2.1
+//  0                   1                   2                   3
+//  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |            PID                |             BLP               |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+fn write_nack(buf: &mut Vec<u8>, nack: &RtcpFbNackPacket) -> Result<()> {
+    if nack.missing_seq_nums.is_empty() {
+        bail!("nack packet must contain at least one missing sequence number");
+    }
+    let start = begin_packet(buf, FMT_NACK, RTCP_PT_RTPFB);
+    write_fb_header(buf, &nack.fb_header);
+    // Each entry holds a packet id and a bitmask of which of the 16 sequence numbers following it
+    // are also missing.  The missing sequence numbers are expected to be in order.
+    let mut fci: Option<(u16, u16)> = None;
+    for &seq_num in &nack.missing_seq_nums {
+        match fci {
+            Some((pid, ref mut blp)) if (1..=16).contains(&seq_num.wrapping_sub(pid)) => {
+                *blp |= 1 << (seq_num.wrapping_sub(pid) - 1);
+            }
+            _ => {
+                if let Some((pid, blp)) = fci {
+                    buf.extend_from_slice(&pid.to_be_bytes());
+                    buf.extend_from_slice(&blp.to_be_bytes());
+                }
+                fci = Some((seq_num, 0));
+            }
+        }
+    }
+    if let Some((pid, blp)) = fci {
+        buf.extend_from_slice(&pid.to_be_bytes());
+        buf.extend_from_slice(&blp.to_be_bytes());
+    }
+    finish_packet(buf, start);
+    Ok(())
+}
+
+// https://datatracker.ietf.org/doc/html/draft-holmer-rmcat-transport-wide-cc-extensions-01#section-3.1
+//  0                   1                   2                   3
+//  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |V=2|P|  FMT=15 |    PT=205     |           length              |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |                     SSRC of packet sender                     |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |                      SSRC of media source                     |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |      base sequence number     |      packet status count      |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |                 reference time                | fb pkt. count |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |          packet chunk         |         packet chunk          |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// .                                                               .
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |         packet chunk          |  recv delta   |  recv delta   |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// .                                                               .
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |           recv delta          |  recv delta   | zero padding  |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+fn write_tcc(buf: &mut Vec<u8>, tcc: &RtcpFbTccPacket) -> Result<()> {
+    let Some(first_report) = tcc.packet_reports.first() else {
+        bail!("tcc packet must contain at least one packet report");
+    };
+    if tcc.packet_reports.len() > u16::MAX as usize {
+        bail!(
+            "too many packet reports for a tcc packet: {}",
+            tcc.packet_reports.len()
+        );
+    }
+    let start = begin_packet(buf, FMT_TCC, RTCP_PT_RTPFB);
+    write_fb_header(buf, &tcc.fb_header);
+    buf.extend_from_slice(&tcc_report_seq_num(first_report).to_be_bytes());
+    buf.extend_from_slice(&(tcc.packet_reports.len() as u16).to_be_bytes());
+    // Reference time is a 24 bit field
+    buf.extend_from_slice(&tcc.reference_time.to_be_bytes()[1..]);
+    buf.push(tcc.feedback_packet_count);
+
+    let symbols = tcc
+        .packet_reports
+        .iter()
+        .map(tcc_report_symbol)
+        .collect::<Vec<u8>>();
+    write_tcc_status_chunks(buf, &symbols);
+
+    for report in &tcc.packet_reports {
+        match report {
+            PacketReport::UnreceivedPacket { .. } => {}
+            PacketReport::ReceivedPacketSmallDelta { delta_ticks, .. } => buf.push(*delta_ticks),
+            PacketReport::ReceivedPacketLargeOrNegativeDelta { delta_ticks, .. } => {
+                buf.extend_from_slice(&delta_ticks.to_be_bytes())
+            }
+        }
+    }
+    finish_packet(buf, start);
+    Ok(())
+}
+
+fn tcc_report_seq_num(report: &PacketReport) -> u16 {
+    match report {
+        PacketReport::UnreceivedPacket { seq_num }
+        | PacketReport::ReceivedPacketSmallDelta { seq_num, .. }
+        | PacketReport::ReceivedPacketLargeOrNegativeDelta { seq_num, .. } => *seq_num,
+    }
+}
+
+fn tcc_report_symbol(report: &PacketReport) -> u8 {
+    match report {
+        PacketReport::UnreceivedPacket { .. } => TCC_SYMBOL_NOT_RECEIVED,
+        PacketReport::ReceivedPacketSmallDelta { .. } => TCC_SYMBOL_SMALL_DELTA,
+        PacketReport::ReceivedPacketLargeOrNegativeDelta { .. } => TCC_SYMBOL_LARGE_DELTA,
+    }
+}
+
+/// Encode the given status symbols as a series of run length and status vector chunks.  Long runs
+/// use run length chunks, everything else is packed into the densest status vector which can hold
+/// the upcoming symbols.
+fn write_tcc_status_chunks(buf: &mut Vec<u8>, symbols: &[u8]) {
+    let mut remaining = symbols;
+    while let Some(&symbol) = remaining.first() {
+        let run_length = remaining
+            .iter()
+            .take(TCC_MAX_RUN_LENGTH)
+            .take_while(|&&s| s == symbol)
+            .count();
+        let fits_one_bit_vector = !remaining
+            .iter()
+            .take(TCC_ONE_BIT_VECTOR_CAPACITY)
+            .any(|&s| s == TCC_SYMBOL_LARGE_DELTA);
+
+        let (chunk, consumed) = if run_length >= TCC_ONE_BIT_VECTOR_CAPACITY {
+            (run_length_chunk(symbol, run_length), run_length)
+        } else if fits_one_bit_vector {
+            let symbols = &remaining[..remaining.len().min(TCC_ONE_BIT_VECTOR_CAPACITY)];
+            (one_bit_vector_chunk(symbols), symbols.len())
+        } else if run_length >= TCC_TWO_BIT_VECTOR_CAPACITY {
+            (run_length_chunk(symbol, run_length), run_length)
+        } else {
+            let symbols = &remaining[..remaining.len().min(TCC_TWO_BIT_VECTOR_CAPACITY)];
+            (two_bit_vector_chunk(symbols), symbols.len())
+        };
+        buf.extend_from_slice(&chunk.to_be_bytes());
+        remaining = &remaining[consumed..];
+    }
+}
+
+//  0                   1
+//  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |T| S |       Run Length        |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+fn run_length_chunk(symbol: u8, run_length: usize) -> u16 {
+    ((symbol as u16) << 13) | run_length as u16
+}
+
+//  0                   1
+//  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |T|S|       symbol list         |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+fn one_bit_vector_chunk(symbols: &[u8]) -> u16 {
+    symbols
+        .iter()
+        .enumerate()
+        .fold(0b1000_0000_0000_0000, |chunk, (i, &symbol)| {
+            chunk | ((symbol as u16) << (13 - i))
+        })
+}
+
+fn two_bit_vector_chunk(symbols: &[u8]) -> u16 {
+    symbols
+        .iter()
+        .enumerate()
+        .fold(0b1100_0000_0000_0000, |chunk, (i, &symbol)| {
+            chunk | ((symbol as u16) << (2 * (6 - i)))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use rtp_parse::rtcp::rtcp_sdes::{RtcpSdesPacket, SdesChunk};
+
+    use super::*;
+
+    fn header(packet_type: u8) -> RtcpHeader {
+        new_rtcp_header(0, packet_type)
+    }
+
+    #[test]
+    fn test_write_rr() {
+        let rr = SomeRtcpPacket::RtcpRrPacket(RtcpRrPacket {
+            header: header(RTCP_PT_RR),
+            sender_ssrc: 0x01020304,
+            report_blocks: vec![RtcpReportBlock {
+                ssrc: 0x0A0B0C0D,
+                fraction_lost: 0x10,
+                cumulative_lost: 0x000203,
+                extended_highest_seq_num: 0x00011234,
+                interarrival_jitter: 0x20,
+                last_sr_timestamp: 0xAABBCCDD,
+                delay_since_last_sr: 0x00010000,
+            }],
+        });
+
+        #[rustfmt::skip]
+        let expected: Vec<u8> = vec![
+            0x81, 0xC9, 0x00, 0x07,
+            0x01, 0x02, 0x03, 0x04,
+            0x0A, 0x0B, 0x0C, 0x0D,
+            0x10, 0x00, 0x02, 0x03,
+            0x00, 0x01, 0x12, 0x34,
+            0x00, 0x00, 0x00, 0x20,
+            0xAA, 0xBB, 0xCC, 0xDD,
+            0x00, 0x01, 0x00, 0x00,
+        ];
+        assert_eq!(write_rtcp_packet(&rr).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_write_compound_rr_sdes() {
+        let compound = SomeRtcpPacket::CompoundRtcpPacket(vec![
+            SomeRtcpPacket::RtcpRrPacket(RtcpRrPacket {
+                header: header(RTCP_PT_RR),
+                sender_ssrc: 0x01020304,
+                report_blocks: vec![],
+            }),
+            SomeRtcpPacket::RtcpSdesPacket(RtcpSdesPacket {
+                header: header(RTCP_PT_SDES),
+                chunks: vec![SdesChunk {
+                    ssrc: 0x01020304,
+                    sdes_items: vec![SdesItem::Cname(String::from("abcd"))],
+                }],
+            }),
+        ]);
+
+        #[rustfmt::skip]
+        let expected: Vec<u8> = vec![
+            // RR
+            0x80, 0xC9, 0x00, 0x01,
+            0x01, 0x02, 0x03, 0x04,
+            // SDES
+            0x81, 0xCA, 0x00, 0x03,
+            0x01, 0x02, 0x03, 0x04,
+            0x01, 0x04, b'a', b'b',
+            b'c', b'd', 0x00, 0x00,
+        ];
+        assert_eq!(write_rtcp_packet(&compound).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_tcc_status_chunks() {
+        // A long run of small deltas gets a run length chunk
+        let mut buf = Vec::new();
+        write_tcc_status_chunks(&mut buf, &[TCC_SYMBOL_SMALL_DELTA; 20]);
+        assert_eq!(buf, vec![0x20, 0x14]);
+
+        // Mixed received/not received fits in a 1 bit vector
+        let mut buf = Vec::new();
+        write_tcc_status_chunks(&mut buf, &[1, 1, 0, 1]);
+        assert_eq!(buf, vec![0xB4, 0x00]);
+
+        // A large delta forces a 2 bit vector
+        let mut buf = Vec::new();
+        write_tcc_status_chunks(&mut buf, &[1, 2]);
+        assert_eq!(buf, vec![0xD8, 0x00]);
+
+        // A run of 7 large deltas followed by something else
+        let mut buf = Vec::new();
+        write_tcc_status_chunks(&mut buf, &[2, 2, 2, 2, 2, 2, 2, 1]);
+        assert_eq!(buf, vec![0x40, 0x07, 0xA0, 0x00]);
+    }
+
+    #[test]
+    fn test_write_nack() {
+        let nack = SomeRtcpPacket::RtcpFbNackPacket(RtcpFbNackPacket {
+            header: new_rtcp_header(FMT_NACK, RTCP_PT_RTPFB),
+            fb_header: RtcpFbHeader {
+                sender_ssrc: 0x01020304,
+                media_source_ssrc: 0x05060708,
+            },
+            missing_seq_nums: vec![65530, 65531, 65535, 9, 100],
+        });
+
+        #[rustfmt::skip]
+        let expected: Vec<u8> = vec![
+            0x81, 0xCD, 0x00, 0x04,
+            0x01, 0x02, 0x03, 0x04,
+            0x05, 0x06, 0x07, 0x08,
+            // 65530, plus 65531, 65535 and 9 (across the rollover)
+            0xFF, 0xFA, 0x40, 0x11,
+            0x00, 0x64, 0x00, 0x00,
+        ];
+        assert_eq!(write_rtcp_packet(&nack).unwrap(), expected);
+    }
+}
@@ -0,0 +1,220 @@
+use anyhow::{bail, Result};
+use rtp_parse::rtcp::rtcp_packet::SomeRtcpPacket;
+
+use crate::rtcp_writer::{new_rtcp_header, RTCP_PT_XR};
+
+// https://datatracker.ietf.org/doc/html/rfc3611#section-2
+//  0                   1                   2                   3
+//  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |V=2|P|reserved |   PT=XR=207   |             length            |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |                              SSRC                             |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// :                         report blocks                         :
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//
+// Each report block:
+//  0                   1                   2                   3
+//  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |      BT       | type-specific |         block length          |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// :             type-specific block contents                      :
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+const BLOCK_TYPE_RRTR: u8 = 4;
+const BLOCK_TYPE_DLRR: u8 = 5;
+
+/// https://datatracker.ietf.org/doc/html/rfc3611#section-4.5
+#[derive(Clone, Debug, PartialEq)]
+pub struct DlrrSubBlock {
+    pub ssrc: u32,
+    /// The middle 32 bits of the NTP timestamp from the RRTR being responded to
+    pub last_rr: u32,
+    /// The delay since the RRTR was received, in units of 1/65536 seconds
+    pub delay_since_last_rr: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum XrReportBlock {
+    /// https://datatracker.ietf.org/doc/html/rfc3611#section-4.4
+    ReceiverReferenceTime {
+        ntp_timestamp: u64,
+    },
+    Dlrr(Vec<DlrrSubBlock>),
+    Unknown {
+        block_type: u8,
+        data: Vec<u8>,
+    },
+}
+
+/// An RTCP extended report (RFC 3611).  The rtcp parser doesn't know about these, so they're
+/// parsed from the payload of an [`SomeRtcpPacket::UnknownRtcpPacket`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct RtcpXrPacket {
+    pub sender_ssrc: u32,
+    pub report_blocks: Vec<XrReportBlock>,
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+impl RtcpXrPacket {
+    /// Parse an XR packet from its payload, i.e. everything after the RTCP header.
+    pub fn parse(payload: &[u8]) -> Result<Self> {
+        if payload.len() < 4 {
+            bail!("xr packet too short: {} bytes", payload.len());
+        }
+        let sender_ssrc = read_u32(payload, 0);
+        let mut report_blocks = Vec::new();
+        let mut offset = 4;
+        while offset + 4 <= payload.len() {
+            let block_type = payload[offset];
+            let block_length = u16::from_be_bytes([payload[offset + 2], payload[offset + 3]]);
+            let data_start = offset + 4;
+            let data_end = data_start + block_length as usize * 4;
+            if data_end > payload.len() {
+                bail!("xr block length {block_length} runs past the end of the packet");
+            }
+            let data = &payload[data_start..data_end];
+            let block = match block_type {
+                BLOCK_TYPE_RRTR if data.len() == 8 => XrReportBlock::ReceiverReferenceTime {
+                    ntp_timestamp: ((read_u32(data, 0) as u64) << 32) | read_u32(data, 4) as u64,
+                },
+                BLOCK_TYPE_DLRR if data.chunks_exact(12).remainder().is_empty() => {
+                    XrReportBlock::Dlrr(
+                        data.chunks_exact(12)
+                            .map(|sub_block| DlrrSubBlock {
+                                ssrc: read_u32(sub_block, 0),
+                                last_rr: read_u32(sub_block, 4),
+                                delay_since_last_rr: read_u32(sub_block, 8),
+                            })
+                            .collect(),
+                    )
+                }
+                _ => XrReportBlock::Unknown {
+                    block_type,
+                    data: data.to_vec(),
+                },
+            };
+            report_blocks.push(block);
+            offset = data_end;
+        }
+
+        Ok(Self {
+            sender_ssrc,
+            report_blocks,
+        })
+    }
+
+    /// Convert this packet into a [`SomeRtcpPacket`] so that it can be written out alongside
+    /// other RTCP.
+    pub fn to_rtcp_packet(&self) -> SomeRtcpPacket {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&self.sender_ssrc.to_be_bytes());
+        for block in &self.report_blocks {
+            let (block_type, data) = match block {
+                XrReportBlock::ReceiverReferenceTime { ntp_timestamp } => {
+                    (BLOCK_TYPE_RRTR, ntp_timestamp.to_be_bytes().to_vec())
+                }
+                XrReportBlock::Dlrr(sub_blocks) => (
+                    BLOCK_TYPE_DLRR,
+                    sub_blocks
+                        .iter()
+                        .flat_map(|sub_block| {
+                            [
+                                sub_block.ssrc,
+                                sub_block.last_rr,
+                                sub_block.delay_since_last_rr,
+                            ]
+                        })
+                        .flat_map(u32::to_be_bytes)
+                        .collect(),
+                ),
+                XrReportBlock::Unknown { block_type, data } => (*block_type, data.clone()),
+            };
+            payload.push(block_type);
+            payload.push(0);
+            payload.extend_from_slice(&((data.len() / 4) as u16).to_be_bytes());
+            payload.extend_from_slice(&data);
+        }
+
+        SomeRtcpPacket::UnknownRtcpPacket {
+            header: new_rtcp_header(0, RTCP_PT_XR),
+            payload,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_xr() {
+        #[rustfmt::skip]
+        let payload: Vec<u8> = vec![
+            // Sender ssrc
+            0x00, 0x00, 0x04, 0xD2,
+            // RRTR
+            0x04, 0x00, 0x00, 0x02,
+            0x11, 0x22, 0x33, 0x44,
+            0x55, 0x66, 0x77, 0x88,
+            // DLRR with one sub block
+            0x05, 0x00, 0x00, 0x03,
+            0x00, 0x00, 0x16, 0x2E,
+            0x33, 0x44, 0x55, 0x66,
+            0x00, 0x01, 0x00, 0x00,
+            // Unknown
+            0x07, 0x00, 0x00, 0x01,
+            0x01, 0x02, 0x03, 0x04,
+        ];
+
+        let xr = RtcpXrPacket::parse(&payload).unwrap();
+        assert_eq!(
+            xr,
+            RtcpXrPacket {
+                sender_ssrc: 1234,
+                report_blocks: vec![
+                    XrReportBlock::ReceiverReferenceTime {
+                        ntp_timestamp: 0x1122334455667788
+                    },
+                    XrReportBlock::Dlrr(vec![DlrrSubBlock {
+                        ssrc: 5678,
+                        last_rr: 0x33445566,
+                        delay_since_last_rr: 0x00010000,
+                    }]),
+                    XrReportBlock::Unknown {
+                        block_type: 7,
+                        data: vec![1, 2, 3, 4]
+                    },
+                ]
+            }
+        );
+
+        match xr.to_rtcp_packet() {
+            SomeRtcpPacket::UnknownRtcpPacket {
+                payload: written, ..
+            } => assert_eq!(written, payload),
+            p => panic!("unexpected packet {p:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_xr_truncated() {
+        #[rustfmt::skip]
+        let payload: Vec<u8> = vec![
+            0x00, 0x00, 0x04, 0xD2,
+            0x04, 0x00, 0x00, 0x02,
+            0x11, 0x22, 0x33, 0x44,
+        ];
+
+        assert!(RtcpXrPacket::parse(&payload).is_err());
+    }
+}
@@ -1,8 +1,9 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
 use data_pipeline_rs::data_handler::{DataTransformer, SomeDataHandler};
 use rtp_parse::rtp::rtp_packet::read_rtp_packet;
 
 use crate::{
+    error::PipelineError,
     packet_info::{PacketInfo, SomePacket},
     stream_information_store::PayloadTypes,
     util::LiveStateReader,
@@ -28,19 +29,25 @@ impl DataTransformer<PacketInfo> for RtpParser {
     fn transform(&mut self, mut data: PacketInfo) -> Result<PacketInfo> {
         match data.packet {
             SomePacket::UnparsedPacket(packet_data) => {
-                let rtp_packet = read_rtp_packet(packet_data).context("rtp parse")?;
+                let rtp_packet = read_rtp_packet(packet_data)
+                    .map_err(|e| PipelineError::parse_failure("rtp packet", e))?;
                 // println!("parsed rtp packet: {rtp_packet:?}");
-                match self.payload_types.value().get(&rtp_packet.payload_type()) {
+                match self
+                    .payload_types
+                    .value()
+                    .media_type(&rtp_packet.payload_type())
+                {
                     Some(MediaType::Audio) => data.packet = SomePacket::AudioRtpPacket(rtp_packet),
                     Some(MediaType::Video) => data.packet = SomePacket::VideoRtpPacket(rtp_packet),
-                    None => panic!(
-                        "Unable to find media type for payload type {}",
-                        rtp_packet.payload_type()
-                    ),
+                    None => {
+                        return Err(
+                            PipelineError::UnknownPayloadType(rtp_packet.payload_type()).into()
+                        )
+                    }
                 }
                 Ok(data)
             }
-            _ => panic!("RTP parser got unexpected packet type: {:x?}", data.packet),
+            ref packet => Err(PipelineError::unexpected_packet_type("RtpParser", packet).into()),
         }
     }
 }
@@ -50,3 +57,35 @@ impl From<RtpParser> for SomeDataHandler<PacketInfo> {
         SomeDataHandler::Transformer(Box::new(val))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use bit_cursor::nsw_types::u7;
+
+    use crate::stream_information_store::StreamInformationStore;
+
+    use super::*;
+
+    #[test]
+    fn test_unknown_payload_type() {
+        let store = StreamInformationStore::new();
+        let mut parser = RtpParser::new(store.subscribe_to_pt_changes());
+
+        #[rustfmt::skip]
+        let packet: Vec<u8> = vec![
+            0x80, 0x60, 0x00, 0x0A,
+            0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x04, 0xD2,
+            0xAA, 0xBB,
+        ];
+        let error = parser
+            .transform(PacketInfo::new_unparsed(packet, Instant::now()))
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<PipelineError>(),
+            Some(PipelineError::UnknownPayloadType(pt)) if *pt == u7::new(96)
+        ));
+    }
+}
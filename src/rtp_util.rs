@@ -0,0 +1,282 @@
+use anyhow::{bail, Result};
+use bit_cursor::nsw_types::u7;
+
+// https://datatracker.ietf.org/doc/html/rfc3550#section-5.1
+//  0                   1                   2                   3
+//  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |V=2|P|X|  CC   |M|     PT      |       sequence number         |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |                           timestamp                           |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |           synchronization source (SSRC) identifier            |
+// +=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+
+// |            contributing source (CSRC) identifiers             |
+// |                             ....                              |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+const FIXED_HEADER_LENGTH: usize = 12;
+
+// https://datatracker.ietf.org/doc/html/rfc8285#section-4.2
+const ONE_BYTE_EXTENSIONS_PROFILE: u16 = 0xBEDE;
+/// The id used to stop parsing one-byte extensions
+const ONE_BYTE_EXTENSIONS_RESERVED_ID: u8 = 15;
+// https://datatracker.ietf.org/doc/html/rfc8285#section-4.3.  The low 4 bits of the profile are
+// application specific.
+const TWO_BYTE_EXTENSIONS_PROFILE: u16 = 0x1000;
+const TWO_BYTE_EXTENSIONS_PROFILE_MASK: u16 = 0xFFF0;
+
+pub fn payload_type(buf: &[u8]) -> u7 {
+    u7::new(buf[1] & 0b0111_1111)
+}
+
+pub fn set_payload_type(buf: &mut [u8], payload_type: u7) {
+    buf[1] = (buf[1] & 0b1000_0000) | u8::from(payload_type);
+}
+
+pub fn seq_num(buf: &[u8]) -> u16 {
+    u16::from_be_bytes([buf[2], buf[3]])
+}
+
+pub fn timestamp(buf: &[u8]) -> u32 {
+    u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]])
+}
+
+pub fn set_seq_num(buf: &mut [u8], seq_num: u16) {
+    buf[2..4].copy_from_slice(&seq_num.to_be_bytes());
+}
+
+pub fn set_ssrc(buf: &mut [u8], ssrc: u32) {
+    buf[8..12].copy_from_slice(&ssrc.to_be_bytes());
+}
+
+/// Get the number of padding bytes at the end of the given RTP packet
+pub fn padding_length(buf: &[u8]) -> usize {
+    let has_padding = buf[0] & 0b0010_0000 != 0;
+    match buf.last() {
+        Some(&padding_length) if has_padding => padding_length as usize,
+        _ => 0,
+    }
+}
+
+/// Get the length of the header of the given RTP packet, including any CSRCs and header
+/// extensions.  This is the offset at which the payload starts.
+pub fn header_length(buf: &[u8]) -> Result<usize> {
+    if buf.len() < FIXED_HEADER_LENGTH {
+        bail!(
+            "buffer too short to contain an rtp header: {} bytes",
+            buf.len()
+        );
+    }
+    let csrc_count = (buf[0] & 0b0000_1111) as usize;
+    let has_extensions = buf[0] & 0b0001_0000 != 0;
+    let mut length = FIXED_HEADER_LENGTH + csrc_count * 4;
+    if has_extensions {
+        if buf.len() < length + 4 {
+            bail!("buffer too short to contain header extensions");
+        }
+        let extensions_length_words = u16::from_be_bytes([buf[length + 2], buf[length + 3]]);
+        length += 4 + extensions_length_words as usize * 4;
+    }
+    if buf.len() < length {
+        bail!(
+            "buffer too short for header: {} bytes, header is {length} bytes",
+            buf.len()
+        );
+    }
+    Ok(length)
+}
+
+/// Get the data of the RFC 8285 header extension with the given id from the given RTP packet.
+/// This is for handlers which need an extension before the packet has been parsed.
+pub fn get_extension_by_id(buf: &[u8], id: u8) -> Option<&[u8]> {
+    let has_extensions = buf.first()? & 0b0001_0000 != 0;
+    if !has_extensions {
+        return None;
+    }
+    let header_length = header_length(buf).ok()?;
+    let extensions_start = FIXED_HEADER_LENGTH + (buf[0] & 0b0000_1111) as usize * 4;
+    let profile = u16::from_be_bytes([buf[extensions_start], buf[extensions_start + 1]]);
+    let one_byte = if profile == ONE_BYTE_EXTENSIONS_PROFILE {
+        true
+    } else if profile & TWO_BYTE_EXTENSIONS_PROFILE_MASK == TWO_BYTE_EXTENSIONS_PROFILE {
+        false
+    } else {
+        return None;
+    };
+
+    let mut extensions = &buf[extensions_start + 4..header_length];
+    while let Some(&first) = extensions.first() {
+        // Padding
+        if first == 0 {
+            extensions = &extensions[1..];
+            continue;
+        }
+        let (ext_id, ext_header_length, data_length) = if one_byte {
+            let ext_id = first >> 4;
+            if ext_id == ONE_BYTE_EXTENSIONS_RESERVED_ID {
+                return None;
+            }
+            (ext_id, 1, (first & 0b0000_1111) as usize + 1)
+        } else {
+            (first, 2, *extensions.get(1)? as usize)
+        };
+        let data = extensions.get(ext_header_length..ext_header_length + data_length)?;
+        if ext_id == id {
+            return Some(data);
+        }
+        extensions = &extensions[ext_header_length + data_length..];
+    }
+    None
+}
+
+/// Builds RTP packets for tests, with a fixed header and no CSRCs or header extensions
+#[cfg(test)]
+pub struct RtpPacketBuilder {
+    payload_type: u8,
+    seq_num: u16,
+    timestamp: u32,
+    ssrc: u32,
+    payload: Vec<u8>,
+}
+
+#[cfg(test)]
+impl Default for RtpPacketBuilder {
+    fn default() -> Self {
+        Self {
+            payload_type: 96,
+            seq_num: 1,
+            timestamp: 0,
+            ssrc: 1234,
+            payload: vec![0xAA, 0xBB],
+        }
+    }
+}
+
+#[cfg(test)]
+impl RtpPacketBuilder {
+    pub fn payload_type(mut self, payload_type: u8) -> Self {
+        self.payload_type = payload_type;
+        self
+    }
+
+    pub fn seq_num(mut self, seq_num: u16) -> Self {
+        self.seq_num = seq_num;
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: u32) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn ssrc(mut self, ssrc: u32) -> Self {
+        self.ssrc = ssrc;
+        self
+    }
+
+    pub fn payload(mut self, payload: &[u8]) -> Self {
+        self.payload = payload.to_vec();
+        self
+    }
+
+    pub fn build(self) -> Vec<u8> {
+        let mut packet = vec![0x80, self.payload_type];
+        packet.extend_from_slice(&self.seq_num.to_be_bytes());
+        packet.extend_from_slice(&self.timestamp.to_be_bytes());
+        packet.extend_from_slice(&self.ssrc.to_be_bytes());
+        packet.extend_from_slice(&self.payload);
+        packet
+    }
+
+    pub fn build_parsed(self) -> rtp_parse::rtp::rtp_packet::RtpPacket {
+        rtp_parse::rtp::rtp_packet::read_rtp_packet(self.build()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_fields() {
+        #[rustfmt::skip]
+        let mut packet: Vec<u8> = vec![
+            0x91, 0xEF, 0x43, 0xD7,
+            0xCF, 0x6F, 0xDE, 0x8F,
+            0x56, 0x29, 0x97, 0x7A,
+            // csrc
+            0x00, 0x00, 0x00, 0x01,
+            // one extension
+            0xBE, 0xDE, 0x00, 0x01,
+            0x10, 0xFF, 0x00, 0x00,
+            // payload
+            0x42,
+        ];
+
+        assert_eq!(header_length(&packet).unwrap(), 24);
+        assert_eq!(payload_type(&packet), u7::new(0x6F));
+        assert_eq!(seq_num(&packet), 0x43D7);
+
+        set_payload_type(&mut packet, u7::new(96));
+        set_seq_num(&mut packet, 1);
+        set_ssrc(&mut packet, 1234);
+        // The marker bit is preserved
+        assert_eq!(packet[1], 0x80 | 96);
+        assert_eq!(seq_num(&packet), 1);
+        assert_eq!(packet[8..12], 1234u32.to_be_bytes());
+        assert!(header_length(&packet[..20]).is_err());
+    }
+
+    #[test]
+    fn test_packet_builder() {
+        let packet = RtpPacketBuilder::default()
+            .payload_type(111)
+            .seq_num(10)
+            .timestamp(960)
+            .ssrc(5678)
+            .payload(&[0x42])
+            .build();
+        assert_eq!(header_length(&packet).unwrap(), FIXED_HEADER_LENGTH);
+        assert_eq!(payload_type(&packet), u7::new(111));
+        assert_eq!(seq_num(&packet), 10);
+        assert_eq!(timestamp(&packet), 960);
+        assert_eq!(packet[8..12], 5678u32.to_be_bytes());
+        assert_eq!(packet[12..], [0x42]);
+    }
+
+    #[test]
+    fn test_get_extension_by_id() {
+        #[rustfmt::skip]
+        let packet: Vec<u8> = vec![
+            0x90, 0x60, 0x00, 0x01,
+            0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x04, 0xD2,
+            // one-byte extensions: id 1 with 1 byte, padding, id 2 with 3 bytes
+            0xBE, 0xDE, 0x00, 0x02,
+            0x10, 0xAA, 0x00, 0x22,
+            0xBB, 0xCC, 0xDD, 0x00,
+            0x42,
+        ];
+        assert_eq!(get_extension_by_id(&packet, 1), Some(&[0xAA][..]));
+        assert_eq!(
+            get_extension_by_id(&packet, 2),
+            Some(&[0xBB, 0xCC, 0xDD][..])
+        );
+        assert_eq!(get_extension_by_id(&packet, 3), None);
+
+        #[rustfmt::skip]
+        let packet: Vec<u8> = vec![
+            0x90, 0x60, 0x00, 0x01,
+            0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x04, 0xD2,
+            // two-byte extensions: id 20 with 2 bytes, id 21 with no data
+            0x10, 0x00, 0x00, 0x02,
+            0x14, 0x02, 0xAA, 0xBB,
+            0x15, 0x00, 0x00, 0x00,
+            0x42,
+        ];
+        assert_eq!(get_extension_by_id(&packet, 20), Some(&[0xAA, 0xBB][..]));
+        assert_eq!(get_extension_by_id(&packet, 21), Some(&[][..]));
+        assert_eq!(get_extension_by_id(&packet[..20], 20), None);
+    }
+}
@@ -0,0 +1,307 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+
+use crate::{
+    clock::compact_ntp,
+    rtcp_termination::RtcpEvent,
+    util::{LiveStateReader, LiveStateWriter, SharedData},
+};
+
+/// How many sent reports we remember.  Reports are sent every few seconds at most, and the remote
+/// side should be responding to one of the last couple, so this is plenty.
+const REPORT_HISTORY_SIZE: usize = 32;
+
+/// The reports we've sent recently which the remote side will echo back to us (sender reports via
+/// the LSR field of their report blocks and XR receiver reference times via DLRR), keyed by the
+/// compact NTP timestamp they carried, so that we know when the echoed report was sent.
+#[derive(Default)]
+pub struct ReportHistory {
+    reports: VecDeque<(u32, Instant)>,
+}
+
+impl ReportHistory {
+    pub fn report_sent(&mut self, ntp_timestamp: u64, sent_time: Instant) {
+        if self.reports.len() == REPORT_HISTORY_SIZE {
+            self.reports.pop_front();
+        }
+        self.reports
+            .push_back((compact_ntp(ntp_timestamp), sent_time));
+    }
+
+    pub fn sent_time(&self, compact_ntp_timestamp: u32) -> Option<Instant> {
+        self.reports
+            .iter()
+            .rev()
+            .find(|(ntp, _)| *ntp == compact_ntp_timestamp)
+            .map(|(_, sent_time)| *sent_time)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RttStats {
+    pub latest: Duration,
+    /// An exponentially weighted moving average of the samples, as in
+    /// https://datatracker.ietf.org/doc/html/rfc6298#section-2
+    pub smoothed: Duration,
+    pub min: Duration,
+}
+
+impl RttStats {
+    fn new(sample: Duration) -> Self {
+        Self {
+            latest: sample,
+            smoothed: sample,
+            min: sample,
+        }
+    }
+
+    fn update(&mut self, sample: Duration) {
+        self.latest = sample;
+        self.smoothed = (self.smoothed * 7 + sample) / 8;
+        self.min = self.min.min(sample);
+    }
+}
+
+pub type RttSnapshot = HashMap<u32, RttStats>;
+
+/// Estimates the round trip time to the remote side from the [`RtcpEvent`]s published by
+/// [`crate::rtcp_termination::RtcpTermination`]: report blocks which refer to sender reports we
+/// sent, and XR DLRR blocks which refer to XR receiver reference times we sent.  Estimates are
+/// kept per remote ssrc and are available via [`RttEstimator::reader`].
+/// [`RttEstimator::rtt_reader`] gives the smoothed rtt of whichever ssrc was most recently
+/// updated, for things which only care about a single rtt value (like
+/// [`crate::nack_generator::NackGenerator`]).
+pub struct RttEstimator {
+    sender_report_history: SharedData<ReportHistory>,
+    rrtr_history: SharedData<ReportHistory>,
+    rtts: LiveStateWriter<RttSnapshot>,
+    rtt: LiveStateWriter<Duration>,
+    lagged_events: LiveStateWriter<u64>,
+}
+
+impl RttEstimator {
+    pub fn new(
+        sender_report_history: SharedData<ReportHistory>,
+        rrtr_history: SharedData<ReportHistory>,
+    ) -> Self {
+        Self {
+            sender_report_history,
+            rrtr_history,
+            rtts: LiveStateWriter::new(HashMap::new()),
+            rtt: LiveStateWriter::new(Duration::ZERO),
+            lagged_events: LiveStateWriter::new(0),
+        }
+    }
+
+    pub fn reader(&self) -> LiveStateReader<RttSnapshot> {
+        self.rtts.reader()
+    }
+
+    pub fn rtt_reader(&self) -> LiveStateReader<Duration> {
+        self.rtt.reader()
+    }
+
+    /// How many [`RtcpEvent`]s [`RttEstimator::run`] has missed by falling behind
+    pub fn lagged_events(&self) -> LiveStateReader<u64> {
+        self.lagged_events.reader()
+    }
+
+    /// Handle [`RtcpEvent`]s until the sender side of `events` is closed.
+    pub async fn run(mut self, mut events: Receiver<RtcpEvent>) {
+        loop {
+            match events.recv().await {
+                Ok(event) => self.handle_event(&event),
+                Err(RecvError::Lagged(num_missed)) => {
+                    self.lagged_events.modify(|lagged| *lagged += num_missed)
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &RtcpEvent) {
+        match event {
+            RtcpEvent::ReportBlocks {
+                sender_ssrc,
+                report_blocks,
+                received_time,
+            } => {
+                for block in report_blocks {
+                    let sample = rtt_sample(
+                        &self.sender_report_history.read(),
+                        block.last_sr_timestamp,
+                        block.delay_since_last_sr,
+                        *received_time,
+                    );
+                    if let Some(sample) = sample {
+                        self.add_sample(*sender_ssrc, sample);
+                    }
+                }
+            }
+            RtcpEvent::Dlrr {
+                sender_ssrc,
+                sub_blocks,
+                received_time,
+            } => {
+                for sub_block in sub_blocks {
+                    let sample = rtt_sample(
+                        &self.rrtr_history.read(),
+                        sub_block.last_rr,
+                        sub_block.delay_since_last_rr,
+                        *received_time,
+                    );
+                    if let Some(sample) = sample {
+                        self.add_sample(*sender_ssrc, sample);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn add_sample(&mut self, ssrc: u32, sample: Duration) {
+        let mut smoothed = sample;
+        self.rtts.modify(|rtts| {
+            let stats = rtts
+                .entry(ssrc)
+                .and_modify(|stats| stats.update(sample))
+                .or_insert_with(|| RttStats::new(sample));
+            smoothed = stats.smoothed;
+        });
+        self.rtt.set(smoothed);
+    }
+}
+
+/// Calculate an rtt sample from a report that was echoed back to us: the time since we sent it,
+/// minus the time the remote side held on to it.
+///
+/// * `last_report`: The compact NTP timestamp of the report we sent.  Zero means the remote side
+///   hasn't received one yet.
+/// * `delay_since_last_report`: How long the remote side held on to the report, in units of
+///   1/65536 seconds
+fn rtt_sample(
+    history: &ReportHistory,
+    last_report: u32,
+    delay_since_last_report: u32,
+    received_time: Instant,
+) -> Option<Duration> {
+    if last_report == 0 {
+        return None;
+    }
+    let sent_time = history.sent_time(last_report)?;
+    let delay = Duration::from_secs_f64(delay_since_last_report as f64 / 65536.0);
+    received_time
+        .checked_duration_since(sent_time)?
+        .checked_sub(delay)
+}
+
+#[cfg(test)]
+mod tests {
+    use rtp_parse::rtcp::rtcp_report_block::RtcpReportBlock;
+
+    use crate::rtcp_xr::DlrrSubBlock;
+
+    use super::*;
+
+    fn report_block(last_sr_timestamp: u32, delay_since_last_sr: u32) -> RtcpReportBlock {
+        RtcpReportBlock {
+            ssrc: 1234,
+            fraction_lost: 0,
+            cumulative_lost: 0,
+            extended_highest_seq_num: 0,
+            interarrival_jitter: 0,
+            last_sr_timestamp,
+            delay_since_last_sr,
+        }
+    }
+
+    #[test]
+    fn test_rtt_from_report_blocks() {
+        let sr_history = SharedData::new(ReportHistory::default());
+        let mut estimator = RttEstimator::new(sr_history.clone(), SharedData::default());
+        let rtts = estimator.reader();
+        let rtt = estimator.rtt_reader();
+        let start = Instant::now();
+        sr_history.write().report_sent(0x1122334455667788, start);
+
+        // Received 300ms after the sr was sent, 100ms of which it was held by the remote side
+        estimator.handle_event(&RtcpEvent::ReportBlocks {
+            sender_ssrc: 5678,
+            report_blocks: vec![report_block(0x33445566, 6554)],
+            received_time: start + Duration::from_millis(300),
+        });
+        let stats = rtts.value().get(&5678).copied().unwrap();
+        assert!(stats.latest.abs_diff(Duration::from_millis(200)) < Duration::from_millis(1));
+        assert_eq!(stats.smoothed, stats.latest);
+        assert_eq!(*rtt.value(), stats.smoothed);
+
+        // A larger sample moves the smoothed value an eighth of the way, and leaves the min
+        estimator.handle_event(&RtcpEvent::ReportBlocks {
+            sender_ssrc: 5678,
+            report_blocks: vec![report_block(0x33445566, 0)],
+            received_time: start + Duration::from_millis(1000),
+        });
+        let stats = rtts.value().get(&5678).copied().unwrap();
+        assert_eq!(stats.latest, Duration::from_millis(1000));
+        assert!(stats.smoothed.abs_diff(Duration::from_millis(300)) < Duration::from_millis(1));
+        assert!(stats.min.abs_diff(Duration::from_millis(200)) < Duration::from_millis(1));
+
+        // Reports which don't refer to an sr we know about are ignored
+        estimator.handle_event(&RtcpEvent::ReportBlocks {
+            sender_ssrc: 5678,
+            report_blocks: vec![report_block(0, 0), report_block(0x01020304, 0)],
+            received_time: start + Duration::from_millis(2000),
+        });
+        assert_eq!(
+            rtts.value().get(&5678).unwrap().latest,
+            Duration::from_millis(1000)
+        );
+    }
+
+    #[test]
+    fn test_rtt_from_dlrr() {
+        let rrtr_history = SharedData::new(ReportHistory::default());
+        let mut estimator = RttEstimator::new(SharedData::default(), rrtr_history.clone());
+        let rtts = estimator.reader();
+        let start = Instant::now();
+        rrtr_history.write().report_sent(0x1122334455667788, start);
+
+        estimator.handle_event(&RtcpEvent::Dlrr {
+            sender_ssrc: 5678,
+            sub_blocks: vec![DlrrSubBlock {
+                ssrc: 1234,
+                last_rr: 0x33445566,
+                delay_since_last_rr: 0x8000,
+            }],
+            received_time: start + Duration::from_millis(600),
+        });
+        assert_eq!(
+            rtts.value().get(&5678).unwrap().latest,
+            Duration::from_millis(100)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_counts_lagged_events() {
+        let estimator = RttEstimator::new(SharedData::default(), SharedData::default());
+        let lagged_events = estimator.lagged_events();
+        let (events_tx, events_rx) = tokio::sync::broadcast::channel(1);
+
+        for _ in 0..3 {
+            events_tx
+                .send(RtcpEvent::ReportBlocks {
+                    sender_ssrc: 5678,
+                    report_blocks: vec![report_block(0, 0)],
+                    received_time: Instant::now(),
+                })
+                .unwrap();
+        }
+        drop(events_tx);
+        estimator.run(events_rx).await;
+        assert_eq!(*lagged_events.value(), 2);
+    }
+}
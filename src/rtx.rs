@@ -0,0 +1,146 @@
+use anyhow::anyhow;
+use bit_cursor::nsw_types::u7;
+
+use crate::{error::PipelineError, rtp_util};
+
+// https://datatracker.ietf.org/doc/html/rfc4588#section-4
+//  0                   1                   2                   3
+//  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |                         RTP Header                            |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |            OSN                |                               |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+                               |
+// |                  Original RTP Packet Payload                  |
+// |                                                               |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+const OSN_LENGTH: usize = 2;
+
+/// Encapsulate the given serialized RTP packet as an RTX packet: the original sequence number is
+/// prepended to the payload and the header is rewritten to use the RTX stream's ssrc, payload type
+/// and sequence number.
+pub fn encapsulate(
+    packet: &[u8],
+    rtx_ssrc: u32,
+    rtx_pt: u7,
+    rtx_seq_num: u16,
+) -> Result<Vec<u8>, PipelineError> {
+    let header_length = header_length(packet)?;
+    let original_seq_num = rtp_util::seq_num(packet);
+
+    let mut rtx_packet = Vec::with_capacity(packet.len() + OSN_LENGTH);
+    rtx_packet.extend_from_slice(&packet[..header_length]);
+    rtx_packet.extend_from_slice(&original_seq_num.to_be_bytes());
+    rtx_packet.extend_from_slice(&packet[header_length..]);
+
+    rtp_util::set_ssrc(&mut rtx_packet, rtx_ssrc);
+    rtp_util::set_payload_type(&mut rtx_packet, rtx_pt);
+    rtp_util::set_seq_num(&mut rtx_packet, rtx_seq_num);
+
+    Ok(rtx_packet)
+}
+
+/// Restore the original packet from the given serialized RTX packet: the original sequence number
+/// is removed from the payload and the header is rewritten to use the primary stream's ssrc,
+/// payload type and the original sequence number.
+pub fn decapsulate(
+    rtx_packet: &[u8],
+    primary_ssrc: u32,
+    primary_pt: u7,
+) -> Result<Vec<u8>, PipelineError> {
+    let header_length = header_length(rtx_packet)?;
+    let padding_length = rtp_util::padding_length(rtx_packet);
+    if rtx_packet.len() < header_length + OSN_LENGTH + padding_length {
+        return Err(PipelineError::parse_failure(
+            "rtx packet",
+            anyhow!("no original sequence number"),
+        ));
+    }
+    let original_seq_num =
+        u16::from_be_bytes([rtx_packet[header_length], rtx_packet[header_length + 1]]);
+
+    let mut packet = Vec::with_capacity(rtx_packet.len() - OSN_LENGTH);
+    packet.extend_from_slice(&rtx_packet[..header_length]);
+    packet.extend_from_slice(&rtx_packet[header_length + OSN_LENGTH..]);
+
+    rtp_util::set_ssrc(&mut packet, primary_ssrc);
+    rtp_util::set_payload_type(&mut packet, primary_pt);
+    rtp_util::set_seq_num(&mut packet, original_seq_num);
+
+    Ok(packet)
+}
+
+/// Whether the given serialized RTX packet carries nothing but padding (e.g. a bandwidth probe),
+/// in which case there's no original packet to restore.
+pub fn is_padding_only(rtx_packet: &[u8]) -> Result<bool, PipelineError> {
+    let header_length = header_length(rtx_packet)?;
+    Ok(rtx_packet.len() <= header_length + rtp_util::padding_length(rtx_packet))
+}
+
+fn header_length(packet: &[u8]) -> Result<usize, PipelineError> {
+    rtp_util::header_length(packet).map_err(|e| PipelineError::parse_failure("rtp header", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use rtp_parse::rtp::rtp_header::RtpHeader;
+
+    use super::*;
+
+    #[test]
+    fn test_encapsulate() {
+        #[rustfmt::skip]
+        let packet: Vec<u8> = vec![
+            0x90, 0x60, 0x43, 0xD7,
+            0xCF, 0x6F, 0xDE, 0x8F,
+            0x56, 0x29, 0x97, 0x7A,
+            0xBE, 0xDE, 0x00, 0x01,
+            0x10, 0xFF, 0x00, 0x00,
+            0x01, 0x02, 0x03,
+        ];
+
+        let rtx_packet = encapsulate(&packet, 1234, u7::new(97), 1).unwrap();
+
+        assert_eq!(RtpHeader::ssrc(&rtx_packet), 1234);
+        assert_eq!(rtp_util::payload_type(&rtx_packet), u7::new(97));
+        assert_eq!(rtp_util::seq_num(&rtx_packet), 1);
+        // Timestamp and extensions are untouched
+        assert_eq!(rtx_packet[4..8], packet[4..8]);
+        assert_eq!(rtx_packet[12..20], packet[12..20]);
+        assert_eq!(rtx_packet[20..], [0x43, 0xD7, 0x01, 0x02, 0x03]);
+    }
+
+    #[test]
+    fn test_decapsulate() {
+        #[rustfmt::skip]
+        let packet: Vec<u8> = vec![
+            0x90, 0x60, 0x43, 0xD7,
+            0xCF, 0x6F, 0xDE, 0x8F,
+            0x56, 0x29, 0x97, 0x7A,
+            0xBE, 0xDE, 0x00, 0x01,
+            0x10, 0xFF, 0x00, 0x00,
+            0x01, 0x02, 0x03,
+        ];
+
+        let rtx_packet = encapsulate(&packet, 1234, u7::new(97), 1).unwrap();
+        assert_eq!(
+            decapsulate(&rtx_packet, 0x5629977a, u7::new(96)).unwrap(),
+            packet
+        );
+        assert!(!is_padding_only(&rtx_packet).unwrap());
+    }
+
+    #[test]
+    fn test_decapsulate_padding_only() {
+        #[rustfmt::skip]
+        let rtx_packet: Vec<u8> = vec![
+            0xA0, 0x61, 0x00, 0x01,
+            0xCF, 0x6F, 0xDE, 0x8F,
+            0x00, 0x00, 0x04, 0xD2,
+            0x00, 0x00, 0x00, 0x04,
+        ];
+
+        assert!(is_padding_only(&rtx_packet).unwrap());
+        assert!(decapsulate(&rtx_packet, 0x5629977a, u7::new(96)).is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use anyhow::Result;
+use data_pipeline_rs::data_handler::{DataTransformer, SomeDataHandler};
+use rtp_parse::rtp::rtp_header::RtpHeader;
+
+use crate::{
+    error::PipelineError,
+    packet_info::{PacketInfo, SomePacket},
+    rtp_util, rtx,
+    stream_information_store::RtxInfo,
+    util::LiveStateReader,
+};
+
+/// Restores RTX (RFC 4588) retransmissions to the original packet on the primary stream, so the
+/// rest of the pipeline doesn't need to know about RTX.  Packets which aren't RTX pass through
+/// untouched, and RTX packets which only carry padding are marked to be discarded.  This should
+/// be placed after decryption but before the [`crate::rtp_parser::RtpParser`], followed by a
+/// [`crate::discardable_discarder::DiscardableDiscarder`] since padding can't be parsed as media.
+pub struct RtxHandler {
+    rtx_info: LiveStateReader<RtxInfo>,
+}
+
+impl RtxHandler {
+    pub fn new(rtx_info: LiveStateReader<RtxInfo>) -> Self {
+        Self { rtx_info }
+    }
+}
+
+impl DataTransformer<PacketInfo> for RtxHandler {
+    fn transform(&mut self, mut data: PacketInfo) -> Result<PacketInfo> {
+        let buf = match data.packet {
+            SomePacket::UnparsedPacket(ref buf) => buf,
+            ref packet => {
+                return Err(PipelineError::unexpected_packet_type("RtxHandler", packet).into())
+            }
+        };
+        let rtx_info = self.rtx_info.value();
+        let Some(primary_pt) = rtx_info.primary_payload_type(rtp_util::payload_type(buf)) else {
+            return Ok(data);
+        };
+        if rtx::is_padding_only(buf)? {
+            drop(rtx_info);
+            data.should_discard = true;
+            return Ok(data);
+        }
+        let rtx_ssrc = RtpHeader::ssrc(buf);
+        let primary_ssrc = rtx_info
+            .primary_ssrc(rtx_ssrc)
+            .ok_or(PipelineError::UnknownRtxSsrc(rtx_ssrc))?;
+        let packet = rtx::decapsulate(buf, primary_ssrc, primary_pt)?;
+        drop(rtx_info);
+
+        data.packet = SomePacket::UnparsedPacket(packet);
+        data.is_retransmission = true;
+        Ok(data)
+    }
+}
+
+impl From<RtxHandler> for SomeDataHandler<PacketInfo> {
+    fn from(value: RtxHandler) -> Self {
+        SomeDataHandler::Transformer(Box::new(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use bit_cursor::nsw_types::u7;
+
+    use crate::stream_information_store::StreamInformationStore;
+
+    use super::*;
+
+    #[test]
+    fn test_rtx_handler() {
+        let mut store = StreamInformationStore::new();
+        store.add_rtx_payload_type(u7::new(97), u7::new(96));
+        store.add_rtx_ssrc(5678, 1234);
+        let mut handler = RtxHandler::new(store.subscribe_to_rtx_changes());
+
+        #[rustfmt::skip]
+        let packet: Vec<u8> = vec![
+            0x80, 0x60, 0x00, 0x0A,
+            0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x04, 0xD2,
+            0xAA, 0xBB,
+        ];
+        let rtx_packet = rtx::encapsulate(&packet, 5678, u7::new(97), 1).unwrap();
+
+        let result = handler
+            .transform(PacketInfo::new_unparsed(rtx_packet, Instant::now()))
+            .unwrap();
+        assert!(result.is_retransmission);
+        match result.packet {
+            SomePacket::UnparsedPacket(data) => assert_eq!(data, packet),
+            _ => panic!("wrong output"),
+        }
+
+        // Non-rtx packets are left alone
+        let result = handler
+            .transform(PacketInfo::new_unparsed(packet.clone(), Instant::now()))
+            .unwrap();
+        assert!(!result.is_retransmission);
+        match result.packet {
+            SomePacket::UnparsedPacket(data) => assert_eq!(data, packet),
+            _ => panic!("wrong output"),
+        }
+
+        // Padding only packets are passed on to be discarded
+        #[rustfmt::skip]
+        let padding: Vec<u8> = vec![
+            0xA0, 0x61, 0x00, 0x0B,
+            0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x16, 0x2E,
+            0x00, 0x00, 0x00, 0x04,
+        ];
+        let result = handler
+            .transform(PacketInfo::new_unparsed(padding, Instant::now()))
+            .unwrap();
+        assert!(result.should_discard);
+
+        // Rtx packets on an unknown ssrc are rejected
+        let rtx_packet = rtx::encapsulate(&packet, 9012, u7::new(97), 2).unwrap();
+        let error = handler
+            .transform(PacketInfo::new_unparsed(rtx_packet, Instant::now()))
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<PipelineError>(),
+            Some(PipelineError::UnknownRtxSsrc(9012))
+        ));
+    }
+}
@@ -0,0 +1,684 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Context, Result};
+use bit_cursor::nsw_types::u7;
+
+use crate::{
+    rtp_parser::MediaType,
+    stream_information_store::{
+        NegotiatedSession, PayloadType, RtcpFeedback, StreamDirection, StreamInfo,
+        StreamInformationStore,
+    },
+};
+
+/// `a=rtpmap:<payload type> <encoding name>/<clock rate>[/<channels>]`
+/// https://datatracker.ietf.org/doc/html/rfc8866#section-6.6
+#[derive(Clone, Debug, PartialEq)]
+pub struct RtpMap {
+    pub pt: u7,
+    pub encoding_name: String,
+    pub clock_rate: u32,
+    pub channels: Option<u16>,
+}
+
+/// `a=extmap:<id>[/<direction>] <uri>`
+/// https://datatracker.ietf.org/doc/html/rfc8285#section-8
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExtMap {
+    pub id: u8,
+    pub uri: String,
+}
+
+/// `a=ssrc-group:<semantics> <ssrc> ...`, e.g. FID for an RTX pairing or SIM for simulcast
+/// https://datatracker.ietf.org/doc/html/rfc5576#section-4.2
+#[derive(Clone, Debug, PartialEq)]
+pub struct SsrcGroup {
+    pub semantics: String,
+    pub ssrcs: Vec<u32>,
+}
+
+/// `a=rtcp-fb:<payload type or *> <type> [<parameter>]`, e.g. `nack pli` or `transport-cc`
+/// https://datatracker.ietf.org/doc/html/rfc4585#section-4.2
+#[derive(Clone, Debug, PartialEq)]
+pub struct RtcpFeedbackAttribute {
+    /// The payload type this applies to, or None if it applies to all of them
+    pub pt: Option<u7>,
+    pub feedback: RtcpFeedback,
+}
+
+/// `a=crypto:<tag> <crypto suite> <key params> [<session params>]`
+/// https://datatracker.ietf.org/doc/html/rfc4568#section-9.1
+#[derive(Clone, Debug, PartialEq)]
+pub struct Crypto {
+    pub tag: u32,
+    pub suite: String,
+    pub key_params: String,
+    pub session_params: Vec<String>,
+}
+
+/// The parts of a single `m=` section that we care about
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MediaDescription {
+    /// None for media other than audio and video (e.g. a data channel)
+    pub media_type: Option<MediaType>,
+    pub mid: Option<String>,
+    pub rtp_maps: Vec<RtpMap>,
+    /// The `a=fmtp` parameters for each payload type.  Parameters without a value (e.g. the
+    /// `0-15` of telephone-event) map to an empty string.
+    pub fmtp: HashMap<u7, HashMap<String, String>>,
+    pub header_extensions: Vec<ExtMap>,
+    /// The `a=ssrc:<ssrc> <attribute>[:<value>]` attributes for each ssrc
+    pub ssrcs: HashMap<u32, HashMap<String, String>>,
+    pub ssrc_groups: Vec<SsrcGroup>,
+    pub rtcp_feedback: Vec<RtcpFeedbackAttribute>,
+    pub crypto: Vec<Crypto>,
+}
+
+/// A parsed SDP offer or answer.  Only the attributes needed to set up the pipeline are parsed,
+/// everything else is ignored.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SessionDescription {
+    /// Header extensions given at the session level, which apply to every media section
+    pub header_extensions: Vec<ExtMap>,
+    pub media: Vec<MediaDescription>,
+}
+
+fn parse_pt(value: &str) -> Result<u7> {
+    let pt = value
+        .parse::<u8>()
+        .with_context(|| format!("invalid payload type {value}"))?;
+    if pt > 127 {
+        bail!("payload type {pt} out of range");
+    }
+    Ok(u7::new(pt))
+}
+
+fn parse_rtp_map(value: &str) -> Result<RtpMap> {
+    let (pt, encoding) = value
+        .split_once(' ')
+        .ok_or_else(|| anyhow!("invalid rtpmap {value}"))?;
+    let mut parts = encoding.split('/');
+    let encoding_name = parts.next().unwrap_or_default().to_owned();
+    let clock_rate = parts
+        .next()
+        .ok_or_else(|| anyhow!("rtpmap {value} has no clock rate"))?
+        .parse()
+        .with_context(|| format!("invalid clock rate in rtpmap {value}"))?;
+    let channels = parts
+        .next()
+        .map(str::parse)
+        .transpose()
+        .with_context(|| format!("invalid channels in rtpmap {value}"))?;
+    Ok(RtpMap {
+        pt: parse_pt(pt)?,
+        encoding_name,
+        clock_rate,
+        channels,
+    })
+}
+
+fn parse_fmtp(value: &str) -> Result<(u7, HashMap<String, String>)> {
+    let (pt, params) = value
+        .split_once(' ')
+        .ok_or_else(|| anyhow!("invalid fmtp {value}"))?;
+    let params = params
+        .split(';')
+        .map(str::trim)
+        .filter(|param| !param.is_empty())
+        .map(|param| match param.split_once('=') {
+            Some((name, value)) => (name.to_owned(), value.to_owned()),
+            None => (param.to_owned(), String::new()),
+        })
+        .collect();
+    Ok((parse_pt(pt)?, params))
+}
+
+fn parse_ext_map(value: &str) -> Result<ExtMap> {
+    let mut parts = value.split_whitespace();
+    let id = parts.next().unwrap_or_default();
+    // The direction is ignored
+    let id = id.split('/').next().unwrap_or_default();
+    let uri = parts
+        .next()
+        .ok_or_else(|| anyhow!("extmap {value} has no uri"))?;
+    Ok(ExtMap {
+        id: id
+            .parse()
+            .with_context(|| format!("invalid extmap id in {value}"))?,
+        uri: uri.to_owned(),
+    })
+}
+
+fn parse_ssrc(value: &str) -> Result<(u32, String, String)> {
+    let (ssrc, attribute) = value
+        .split_once(' ')
+        .ok_or_else(|| anyhow!("invalid ssrc attribute {value}"))?;
+    let (name, attribute_value) = attribute.split_once(':').unwrap_or((attribute, ""));
+    Ok((
+        ssrc.parse()
+            .with_context(|| format!("invalid ssrc in {value}"))?,
+        name.to_owned(),
+        attribute_value.to_owned(),
+    ))
+}
+
+fn parse_ssrc_group(value: &str) -> Result<SsrcGroup> {
+    let mut parts = value.split_whitespace();
+    let semantics = parts.next().unwrap_or_default().to_owned();
+    let ssrcs = parts
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("invalid ssrc in ssrc-group {value}"))?;
+    Ok(SsrcGroup { semantics, ssrcs })
+}
+
+fn parse_rtcp_feedback(value: &str) -> Result<RtcpFeedbackAttribute> {
+    let mut parts = value.split_whitespace();
+    let pt = match parts.next() {
+        Some("*") => None,
+        Some(pt) => Some(parse_pt(pt)?),
+        None => bail!("empty rtcp-fb"),
+    };
+    let feedback_type = parts
+        .next()
+        .ok_or_else(|| anyhow!("rtcp-fb {value} has no type"))?
+        .to_owned();
+    let parameter = parts.next().map(str::to_owned);
+    Ok(RtcpFeedbackAttribute {
+        pt,
+        feedback: RtcpFeedback {
+            feedback_type,
+            parameter,
+        },
+    })
+}
+
+fn parse_crypto(value: &str) -> Result<Crypto> {
+    let mut parts = value.split_whitespace();
+    let tag = parts
+        .next()
+        .unwrap_or_default()
+        .parse()
+        .with_context(|| format!("invalid crypto tag in {value}"))?;
+    let suite = parts
+        .next()
+        .ok_or_else(|| anyhow!("crypto {value} has no suite"))?
+        .to_owned();
+    let key_params = parts
+        .next()
+        .ok_or_else(|| anyhow!("crypto {value} has no key params"))?
+        .to_owned();
+    Ok(Crypto {
+        tag,
+        suite,
+        key_params,
+        session_params: parts.map(str::to_owned).collect(),
+    })
+}
+
+impl MediaDescription {
+    /// Everything signaled for the payload type described by `rtp_map`
+    pub fn payload_type(&self, media_type: MediaType, rtp_map: &RtpMap) -> PayloadType {
+        PayloadType {
+            pt: rtp_map.pt,
+            media_type,
+            codec: rtp_map.encoding_name.clone(),
+            clock_rate: rtp_map.clock_rate,
+            channels: rtp_map.channels,
+            parameters: self.fmtp.get(&rtp_map.pt).cloned().unwrap_or_default(),
+            rtcp_feedback: self
+                .rtcp_feedback
+                .iter()
+                .filter(|attribute| attribute.pt.is_none() || attribute.pt == Some(rtp_map.pt))
+                .map(|attribute| attribute.feedback.clone())
+                .collect(),
+        }
+    }
+
+    fn parse_attribute(&mut self, name: &str, value: &str) -> Result<()> {
+        match name {
+            "mid" => self.mid = Some(value.to_owned()),
+            "rtpmap" => self.rtp_maps.push(parse_rtp_map(value)?),
+            "fmtp" => {
+                let (pt, params) = parse_fmtp(value)?;
+                self.fmtp.insert(pt, params);
+            }
+            "extmap" => self.header_extensions.push(parse_ext_map(value)?),
+            "ssrc" => {
+                let (ssrc, name, value) = parse_ssrc(value)?;
+                self.ssrcs.entry(ssrc).or_default().insert(name, value);
+            }
+            "ssrc-group" => self.ssrc_groups.push(parse_ssrc_group(value)?),
+            "rtcp-fb" => self.rtcp_feedback.push(parse_rtcp_feedback(value)?),
+            "crypto" => self.crypto.push(parse_crypto(value)?),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl SessionDescription {
+    pub fn parse(sdp: &str) -> Result<Self> {
+        let mut description = SessionDescription::default();
+        for line in sdp.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let (line_type, value) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("invalid sdp line {line}"))?;
+            match line_type {
+                "m" => description.media.push(MediaDescription {
+                    media_type: match value.split(' ').next() {
+                        Some("audio") => Some(MediaType::Audio),
+                        Some("video") => Some(MediaType::Video),
+                        _ => None,
+                    },
+                    ..Default::default()
+                }),
+                "a" => {
+                    let (name, value) = value.split_once(':').unwrap_or((value, ""));
+                    match description.media.last_mut() {
+                        Some(media) => media.parse_attribute(name, value)?,
+                        None if name == "extmap" => {
+                            description.header_extensions.push(parse_ext_map(value)?)
+                        }
+                        None => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(description)
+    }
+
+    /// The payload types (including their fmtp parameters and feedback types), header extensions,
+    /// rtx pairings (from `apt` and FID groups), streams and simulcast groups described here.
+    /// The streams declared in a description are the ones its author sends, so they're treated
+    /// as remote streams.  Crypto isn't included, so it's only available from the parsed
+    /// description.
+    pub fn negotiated_session(&self) -> NegotiatedSession {
+        let mut session = NegotiatedSession {
+            header_extensions: self
+                .header_extensions
+                .iter()
+                .map(|ext| (ext.uri.clone(), ext.id))
+                .collect(),
+            ..Default::default()
+        };
+        for media in &self.media {
+            let Some(ref media_type) = media.media_type else {
+                continue;
+            };
+            for rtp_map in &media.rtp_maps {
+                let payload_type = media.payload_type(media_type.clone(), rtp_map);
+                if payload_type.is_rtx() {
+                    if let Some(primary_pt) = payload_type.associated_payload_type() {
+                        session.rtx_payload_types.insert(rtp_map.pt, primary_pt);
+                    }
+                }
+                session.payload_types.push(payload_type);
+            }
+            session.header_extensions.extend(
+                media
+                    .header_extensions
+                    .iter()
+                    .map(|ext| (ext.uri.clone(), ext.id)),
+            );
+            let mut rtx_ssrcs = HashMap::new();
+            for group in &media.ssrc_groups {
+                match (group.semantics.as_str(), &group.ssrcs[..]) {
+                    ("FID", [primary_ssrc, rtx_ssrc]) => {
+                        session.rtx_ssrcs.insert(*rtx_ssrc, *primary_ssrc);
+                        rtx_ssrcs.insert(*primary_ssrc, *rtx_ssrc);
+                    }
+                    ("SIM", layers) => session.simulcast_groups.push(layers.to_vec()),
+                    _ => {}
+                }
+            }
+            session
+                .streams
+                .extend(media.ssrcs.iter().map(|(ssrc, attributes)| StreamInfo {
+                    media_type: Some(media_type.clone()),
+                    mid: media.mid.clone(),
+                    cname: attributes.get("cname").cloned(),
+                    rtx_ssrc: rtx_ssrcs.get(ssrc).copied(),
+                    ..StreamInfo::new(*ssrc, StreamDirection::Remote)
+                }));
+        }
+        session
+    }
+
+    /// Replace the contents of `store` with the [`negotiated session`](Self::negotiated_session)
+    /// described here, e.g. after an offer/answer exchange or a renegotiation.
+    pub fn apply_to(&self, store: &mut StreamInformationStore) {
+        store.apply_negotiated_session(self.negotiated_session());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An offer from Chrome, trimmed to one audio and one video codec (plus rtx)
+    const CHROME_OFFER: &str = "v=0\r
+o=- 4611731400430051336 2 IN IP4 127.0.0.1\r
+s=-\r
+t=0 0\r
+a=group:BUNDLE 0 1\r
+a=extmap-allow-mixed\r
+a=msid-semantic: WMS stream\r
+m=audio 9 UDP/TLS/RTP/SAVPF 111 63\r
+c=IN IP4 0.0.0.0\r
+a=rtcp:9 IN IP4 0.0.0.0\r
+a=ice-ufrag:Jm0v\r
+a=ice-pwd:Sx3Rkb2fHxAGoMu5xwvUW4rW\r
+a=ice-options:trickle\r
+a=fingerprint:sha-256 1B:2C:3D:4E:5F:60:71:82:93:A4:B5:C6:D7:E8:F9:0A:1B:2C:3D:4E:5F:60:71:82:93:A4:B5:C6:D7:E8:F9:0A\r
+a=setup:actpass\r
+a=mid:0\r
+a=extmap:1 urn:ietf:params:rtp-hdrext:ssrc-audio-level\r
+a=extmap:2 http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time\r
+a=extmap:3 http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01\r
+a=extmap:4 urn:ietf:params:rtp-hdrext:sdes:mid\r
+a=sendrecv\r
+a=msid:stream audio-track\r
+a=rtcp-mux\r
+a=rtpmap:111 opus/48000/2\r
+a=rtcp-fb:111 transport-cc\r
+a=fmtp:111 minptime=10;useinbandfec=1\r
+a=rtpmap:63 red/48000/2\r
+a=fmtp:63 111/111\r
+a=ssrc:3570614608 cname:4TOk42mSjXCkVIa6\r
+a=ssrc:3570614608 msid:stream audio-track\r
+m=video 9 UDP/TLS/RTP/SAVPF 96 97\r
+c=IN IP4 0.0.0.0\r
+a=rtcp:9 IN IP4 0.0.0.0\r
+a=ice-ufrag:Jm0v\r
+a=ice-pwd:Sx3Rkb2fHxAGoMu5xwvUW4rW\r
+a=ice-options:trickle\r
+a=fingerprint:sha-256 1B:2C:3D:4E:5F:60:71:82:93:A4:B5:C6:D7:E8:F9:0A:1B:2C:3D:4E:5F:60:71:82:93:A4:B5:C6:D7:E8:F9:0A\r
+a=setup:actpass\r
+a=mid:1\r
+a=extmap:14 urn:ietf:params:rtp-hdrext:toffset\r
+a=extmap:2 http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time\r
+a=extmap:3 http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01\r
+a=extmap:4 urn:ietf:params:rtp-hdrext:sdes:mid\r
+a=extmap:10 urn:ietf:params:rtp-hdrext:sdes:rtp-stream-id\r
+a=extmap:11 urn:ietf:params:rtp-hdrext:sdes:repaired-rtp-stream-id\r
+a=sendrecv\r
+a=msid:stream video-track\r
+a=rtcp-mux\r
+a=rtcp-rsize\r
+a=rtpmap:96 VP8/90000\r
+a=rtcp-fb:96 goog-remb\r
+a=rtcp-fb:96 transport-cc\r
+a=rtcp-fb:96 ccm fir\r
+a=rtcp-fb:96 nack\r
+a=rtcp-fb:96 nack pli\r
+a=rtpmap:97 rtx/90000\r
+a=fmtp:97 apt=96\r
+a=ssrc-group:FID 2231627014 632943048\r
+a=ssrc:2231627014 cname:4TOk42mSjXCkVIa6\r
+a=ssrc:2231627014 msid:stream video-track\r
+a=ssrc:632943048 cname:4TOk42mSjXCkVIa6\r
+a=ssrc:632943048 msid:stream video-track\r
+";
+
+    // An offer from Firefox, trimmed to one audio and one video codec (plus rtx)
+    const FIREFOX_OFFER: &str = "v=0\r
+o=mozilla...THIS_IS_SDPARTA-99.0 7950476328283735612 0 IN IP4 0.0.0.0\r
+s=-\r
+t=0 0\r
+a=fingerprint:sha-256 5A:87:1B:3C:9D:E2:40:6F:11:22:33:44:55:66:77:88:99:AA:BB:CC:DD:EE:FF:00:11:22:33:44:55:66:77:88\r
+a=group:BUNDLE 0 1\r
+a=ice-options:trickle\r
+a=msid-semantic:WMS *\r
+m=audio 9 UDP/TLS/RTP/SAVPF 109 101\r
+c=IN IP4 0.0.0.0\r
+a=sendrecv\r
+a=extmap:1 urn:ietf:params:rtp-hdrext:ssrc-audio-level\r
+a=extmap:2/recvonly urn:ietf:params:rtp-hdrext:csrc-audio-level\r
+a=extmap:3 urn:ietf:params:rtp-hdrext:sdes:mid\r
+a=fmtp:109 maxplaybackrate=48000;stereo=1;useinbandfec=1\r
+a=fmtp:101 0-15\r
+a=ice-pwd:1d2a0e5c9b2f5f4c0c4f1e3a6b7c8d9e\r
+a=ice-ufrag:4b5c6d7e\r
+a=mid:0\r
+a=msid:{8f1e3a6b-7c8d-4e9f-a0b1-c2d3e4f5a6b7} {1a2b3c4d-5e6f-4a7b-8c9d-0e1f2a3b4c5d}\r
+a=rtcp-mux\r
+a=rtpmap:109 opus/48000/2\r
+a=rtpmap:101 telephone-event/8000/1\r
+a=setup:actpass\r
+a=ssrc:2655508255 cname:{5d8b2a3e-9c1f-4e7a-b6d0-3f2e1a9c8b7d}\r
+m=video 9 UDP/TLS/RTP/SAVPF 120 124\r
+c=IN IP4 0.0.0.0\r
+a=sendrecv\r
+a=extmap:3 urn:ietf:params:rtp-hdrext:sdes:mid\r
+a=extmap:4 http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time\r
+a=extmap:5 urn:ietf:params:rtp-hdrext:toffset\r
+a=extmap:6/recvonly http://www.webrtc.org/experiments/rtp-hdrext/playout-delay\r
+a=extmap:7 http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01\r
+a=fmtp:120 max-fs=12288;max-fr=60\r
+a=fmtp:124 apt=120\r
+a=ice-pwd:1d2a0e5c9b2f5f4c0c4f1e3a6b7c8d9e\r
+a=ice-ufrag:4b5c6d7e\r
+a=mid:1\r
+a=msid:{8f1e3a6b-7c8d-4e9f-a0b1-c2d3e4f5a6b7} {9e8d7c6b-5a4f-4e3d-2c1b-0a9f8e7d6c5b}\r
+a=rtcp-fb:120 nack\r
+a=rtcp-fb:120 nack pli\r
+a=rtcp-fb:120 ccm fir\r
+a=rtcp-fb:120 goog-remb\r
+a=rtcp-fb:120 transport-cc\r
+a=rtcp-mux\r
+a=rtcp-rsize\r
+a=rtpmap:120 VP8/90000\r
+a=rtpmap:124 rtx/90000\r
+a=setup:actpass\r
+a=ssrc:1718253476 cname:{5d8b2a3e-9c1f-4e7a-b6d0-3f2e1a9c8b7d}\r
+a=ssrc:3264717381 cname:{5d8b2a3e-9c1f-4e7a-b6d0-3f2e1a9c8b7d}\r
+a=ssrc-group:FID 1718253476 3264717381\r
+";
+
+    #[test]
+    fn test_parse_chrome_offer() {
+        let description = SessionDescription::parse(CHROME_OFFER).unwrap();
+        assert_eq!(description.media.len(), 2);
+
+        let audio = &description.media[0];
+        assert_eq!(audio.media_type, Some(MediaType::Audio));
+        assert_eq!(audio.mid.as_deref(), Some("0"));
+        assert_eq!(
+            audio.rtp_maps[0],
+            RtpMap {
+                pt: u7::new(111),
+                encoding_name: String::from("opus"),
+                clock_rate: 48000,
+                channels: Some(2),
+            }
+        );
+        assert_eq!(audio.fmtp[&u7::new(111)]["useinbandfec"], "1");
+        assert_eq!(
+            audio.ssrcs[&3570614608]["cname"],
+            String::from("4TOk42mSjXCkVIa6")
+        );
+
+        let video = &description.media[1];
+        assert_eq!(video.media_type, Some(MediaType::Video));
+        assert_eq!(video.rtp_maps.len(), 2);
+        assert_eq!(video.rtp_maps[1].channels, None);
+        assert_eq!(video.header_extensions.len(), 6);
+        assert_eq!(
+            video.ssrc_groups,
+            vec![SsrcGroup {
+                semantics: String::from("FID"),
+                ssrcs: vec![2231627014, 632943048],
+            }]
+        );
+        assert_eq!(video.rtcp_feedback.len(), 5);
+        assert_eq!(
+            video.rtcp_feedback[4],
+            RtcpFeedbackAttribute {
+                pt: Some(u7::new(96)),
+                feedback: RtcpFeedback::new("nack", Some("pli")),
+            }
+        );
+    }
+
+    #[test]
+    fn test_apply_chrome_offer() {
+        let mut store = StreamInformationStore::new();
+        let tcc_id = store.subscribe_to_header_extension_id_change(String::from(
+            "http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01",
+        ));
+        SessionDescription::parse(CHROME_OFFER)
+            .unwrap()
+            .apply_to(&mut store);
+
+        let pts = store.subscribe_to_pt_changes();
+        let opus = pts.value().get(&u7::new(111)).cloned().unwrap();
+        assert_eq!(opus.media_type, MediaType::Audio);
+        assert_eq!(opus.codec, "opus");
+        assert_eq!(opus.clock_rate, 48000);
+        assert_eq!(opus.channels, Some(2));
+        assert_eq!(opus.parameters["minptime"], "10");
+        assert!(opus.supports_feedback("transport-cc", None));
+        let vp8 = pts.value().get(&u7::new(96)).cloned().unwrap();
+        assert_eq!(vp8.media_type, MediaType::Video);
+        assert_eq!(vp8.rtcp_feedback.len(), 5);
+        assert!(vp8.supports_feedback("nack", Some("pli")));
+        assert_eq!(*tcc_id.value(), Some(3));
+        let header_extensions = store.subscribe_to_header_extension_id_changes();
+        assert_eq!(
+            header_extensions
+                .value()
+                .get(&String::from("urn:ietf:params:rtp-hdrext:ssrc-audio-level")),
+            Some(&1)
+        );
+        let rtx = store.subscribe_to_rtx_changes();
+        assert_eq!(
+            rtx.value().primary_payload_type(u7::new(97)),
+            Some(u7::new(96))
+        );
+        assert_eq!(rtx.value().primary_ssrc(632943048), Some(2231627014));
+        let streams = store.subscribe_to_stream_changes();
+        let audio = streams.value().get(3570614608).cloned().unwrap();
+        assert_eq!(audio.direction, StreamDirection::Remote);
+        assert_eq!(audio.media_type, Some(MediaType::Audio));
+        assert_eq!(audio.mid.as_deref(), Some("0"));
+        assert_eq!(audio.cname.as_deref(), Some("4TOk42mSjXCkVIa6"));
+        assert_eq!(streams.value().by_mid("1").count(), 2);
+        assert_eq!(
+            streams.value().get(2231627014).unwrap().rtx_ssrc,
+            Some(632943048)
+        );
+        assert_eq!(streams.value().primary_ssrc(632943048), Some(2231627014));
+    }
+
+    #[test]
+    fn test_simulcast_groups() {
+        let sdp = "v=0\r
+m=video 9 UDP/TLS/RTP/SAVPF 96 97\r
+a=mid:1\r
+a=rtpmap:96 VP8/90000\r
+a=rtpmap:97 rtx/90000\r
+a=fmtp:97 apt=96\r
+a=ssrc-group:SIM 1 2 3\r
+a=ssrc-group:FID 1 11\r
+a=ssrc-group:FID 2 12\r
+a=ssrc-group:FID 3 13\r
+a=ssrc:1 cname:cname\r
+a=ssrc:2 cname:cname\r
+a=ssrc:3 cname:cname\r
+a=ssrc:11 cname:cname\r
+a=ssrc:12 cname:cname\r
+a=ssrc:13 cname:cname\r
+";
+        let mut store = StreamInformationStore::new();
+        SessionDescription::parse(sdp).unwrap().apply_to(&mut store);
+
+        let streams = store.subscribe_to_stream_changes();
+        assert_eq!(streams.value().iter().count(), 6);
+        assert_eq!(streams.value().simulcast_layers(2), Some(&[1, 2, 3][..]));
+        assert_eq!(streams.value().simulcast_layer(3), Some(2));
+        assert_eq!(streams.value().primary_ssrc(12), Some(2));
+        let rtx = store.subscribe_to_rtx_changes();
+        assert_eq!(rtx.value().rtx_ssrc(3), Some(13));
+    }
+
+    #[test]
+    fn test_firefox_offer() {
+        let description = SessionDescription::parse(FIREFOX_OFFER).unwrap();
+        let audio = &description.media[0];
+        assert_eq!(audio.rtp_maps[1].encoding_name, "telephone-event");
+        assert_eq!(audio.rtp_maps[1].clock_rate, 8000);
+        assert_eq!(audio.fmtp[&u7::new(101)]["0-15"], "");
+        // The direction is dropped
+        assert_eq!(
+            audio.header_extensions[1],
+            ExtMap {
+                id: 2,
+                uri: String::from("urn:ietf:params:rtp-hdrext:csrc-audio-level"),
+            }
+        );
+
+        let mut store = StreamInformationStore::new();
+        description.apply_to(&mut store);
+        let pts = store.subscribe_to_pt_changes();
+        assert_eq!(
+            pts.value().media_type(&u7::new(109)),
+            Some(&MediaType::Audio)
+        );
+        assert_eq!(pts.value().clock_rate(&u7::new(101)), Some(8000));
+        assert_eq!(
+            pts.value().media_type(&u7::new(120)),
+            Some(&MediaType::Video)
+        );
+        assert_eq!(
+            pts.value()
+                .get(&u7::new(124))
+                .unwrap()
+                .associated_payload_type(),
+            Some(u7::new(120))
+        );
+        let rtx = store.subscribe_to_rtx_changes();
+        assert_eq!(
+            rtx.value().rtx_payload_type(u7::new(120)),
+            Some(u7::new(124))
+        );
+        assert_eq!(rtx.value().rtx_ssrc(1718253476), Some(3264717381));
+        let header_extensions = store.subscribe_to_header_extension_id_changes();
+        assert_eq!(
+            header_extensions.value().get(&String::from(
+                "http://www.webrtc.org/experiments/rtp-hdrext/playout-delay"
+            )),
+            Some(&6)
+        );
+    }
+
+    #[test]
+    fn test_crypto_and_session_extmap() {
+        let sdp = "v=0
+a=extmap:5 urn:ietf:params:rtp-hdrext:sdes:mid
+m=audio 9 RTP/SAVPF 0
+a=rtpmap:0 PCMU/8000
+a=crypto:1 AES_CM_128_HMAC_SHA1_80 inline:WVNfX19zZW1jdGwgKCkgewkyMjA7fQp9CnVubGVz|2^20|1:4 KDR=1
+a=rtcp-fb:* nack
+";
+        let description = SessionDescription::parse(sdp).unwrap();
+        assert_eq!(description.header_extensions[0].id, 5);
+        assert_eq!(
+            description.media[0].crypto,
+            vec![Crypto {
+                tag: 1,
+                suite: String::from("AES_CM_128_HMAC_SHA1_80"),
+                key_params: String::from(
+                    "inline:WVNfX19zZW1jdGwgKCkgewkyMjA7fQp9CnVubGVz|2^20|1:4"
+                ),
+                session_params: vec![String::from("KDR=1")],
+            }]
+        );
+        assert_eq!(description.media[0].rtcp_feedback[0].pt, None);
+
+        assert!(
+            SessionDescription::parse("m=audio 9 RTP/SAVPF 0\na=rtpmap:200 PCMU/8000").is_err()
+        );
+    }
+}
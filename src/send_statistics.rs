@@ -0,0 +1,203 @@
+use std::{collections::HashMap, time::Instant};
+
+use data_pipeline_rs::data_handler::{DataObserver, SomeDataHandler};
+use rtp_parse::rtp::rtp_header::RtpHeader;
+
+use crate::{
+    error::{DroppedPackets, PipelineError},
+    packet_info::{PacketInfo, SomePacket},
+    rtp_util,
+    stream_information_store::PayloadTypes,
+    util::{LiveStateReader, SharedData},
+};
+
+/// What we've sent on a single ssrc, as needed for the sender info in an RTCP sender report.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamSendStatistics {
+    pub packet_count: u32,
+    /// The number of payload octets sent (i.e. not including the header or padding)
+    pub octet_count: u32,
+    pub last_rtp_timestamp: u32,
+    pub last_packet_time: Instant,
+    pub clock_rate: u32,
+}
+
+impl StreamSendStatistics {
+    /// Extrapolate the RTP timestamp corresponding to `now` from the last packet that was sent
+    pub fn rtp_timestamp_at(&self, now: Instant) -> u32 {
+        let elapsed = now.saturating_duration_since(self.last_packet_time);
+        let elapsed_ticks = (elapsed.as_secs_f64() * self.clock_rate as f64) as u32;
+        self.last_rtp_timestamp.wrapping_add(elapsed_ticks)
+    }
+}
+
+#[derive(Default)]
+pub struct SendStatistics {
+    streams: HashMap<u32, StreamSendStatistics>,
+}
+
+impl SendStatistics {
+    pub fn packet_sent(
+        &mut self,
+        ssrc: u32,
+        rtp_timestamp: u32,
+        payload_length: usize,
+        clock_rate: u32,
+        now: Instant,
+    ) {
+        let stream = self
+            .streams
+            .entry(ssrc)
+            .or_insert_with(|| StreamSendStatistics {
+                packet_count: 0,
+                octet_count: 0,
+                last_rtp_timestamp: rtp_timestamp,
+                last_packet_time: now,
+                clock_rate,
+            });
+        // The counts wrap around, as described in
+        // https://datatracker.ietf.org/doc/html/rfc3550#section-6.4.1
+        stream.packet_count = stream.packet_count.wrapping_add(1);
+        stream.octet_count = stream.octet_count.wrapping_add(payload_length as u32);
+        stream.last_rtp_timestamp = rtp_timestamp;
+        stream.last_packet_time = now;
+        stream.clock_rate = clock_rate;
+    }
+
+    pub fn get(&self, ssrc: u32) -> Option<&StreamSendStatistics> {
+        self.streams.get(&ssrc)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&u32, &StreamSendStatistics)> {
+        self.streams.iter()
+    }
+
+    pub fn remove_ssrc(&mut self, ssrc: u32) {
+        self.streams.remove(&ssrc);
+    }
+}
+
+/// Records every RTP packet going out on the send pipeline in [`SendStatistics`].  This should
+/// be placed before encryption.
+pub struct SendStatisticsTracker {
+    stats: SharedData<SendStatistics>,
+    payload_types: LiveStateReader<PayloadTypes>,
+    dropped_packets: SharedData<DroppedPackets>,
+}
+
+impl SendStatisticsTracker {
+    pub fn new(
+        stats: SharedData<SendStatistics>,
+        payload_types: LiveStateReader<PayloadTypes>,
+        dropped_packets: SharedData<DroppedPackets>,
+    ) -> Self {
+        Self {
+            stats,
+            payload_types,
+            dropped_packets,
+        }
+    }
+}
+
+impl DataObserver<PacketInfo> for SendStatisticsTracker {
+    fn observe(&mut self, data: &PacketInfo) {
+        let buf = match data.packet {
+            SomePacket::UnparsedPacket(ref buf) => buf.as_slice(),
+            SomePacket::RtpPacket(ref rtp)
+            | SomePacket::AudioRtpPacket(ref rtp)
+            | SomePacket::VideoRtpPacket(ref rtp) => rtp.buf(),
+            ref packet => {
+                self.dropped_packets
+                    .write()
+                    .record(&PipelineError::unexpected_packet_type(
+                        "SendStatisticsTracker",
+                        packet,
+                    ));
+                return;
+            }
+        };
+        let header_length = match rtp_util::header_length(buf) {
+            Ok(header_length) => header_length,
+            Err(e) => {
+                self.dropped_packets
+                    .write()
+                    .record(&PipelineError::parse_failure("rtp header", e));
+                return;
+            }
+        };
+        let payload_length = buf
+            .len()
+            .saturating_sub(header_length + rtp_util::padding_length(buf));
+        let clock_rate = self
+            .payload_types
+            .value()
+            .clock_rate(&rtp_util::payload_type(buf))
+            .unwrap_or(0);
+        self.stats.write().packet_sent(
+            RtpHeader::ssrc(buf),
+            rtp_util::timestamp(buf),
+            payload_length,
+            clock_rate,
+            data.received_time,
+        );
+    }
+}
+
+impl From<SendStatisticsTracker> for SomeDataHandler<PacketInfo> {
+    fn from(value: SendStatisticsTracker) -> Self {
+        SomeDataHandler::Observer(Box::new(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bit_cursor::nsw_types::u7;
+
+    use crate::{
+        rtp_parser::MediaType,
+        stream_information_store::{PayloadType, StreamInformationStore},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_send_statistics_tracker() {
+        let mut store = StreamInformationStore::new();
+        store.add_payload_type(PayloadType::new(
+            u7::new(96),
+            MediaType::Video,
+            "VP8",
+            90000,
+        ));
+        let stats = SharedData::new(SendStatistics::default());
+        let mut tracker = SendStatisticsTracker::new(
+            stats.clone(),
+            store.subscribe_to_pt_changes(),
+            SharedData::default(),
+        );
+        let start = Instant::now();
+
+        #[rustfmt::skip]
+        let packet: Vec<u8> = vec![
+            0x80, 0x60, 0x00, 0x0A,
+            0x00, 0x00, 0x03, 0xE8,
+            0x00, 0x00, 0x04, 0xD2,
+            0xAA, 0xBB, 0xCC,
+        ];
+        tracker.observe(&PacketInfo::new_unparsed(packet.clone(), start));
+        tracker.observe(&PacketInfo::new_unparsed(packet, start));
+
+        let stats = stats.read();
+        let stream = stats.get(1234).unwrap();
+        assert_eq!(stream.packet_count, 2);
+        assert_eq!(stream.octet_count, 6);
+        assert_eq!(stream.last_rtp_timestamp, 1000);
+        assert_eq!(stream.clock_rate, 90000);
+        assert_eq!(
+            stream.rtp_timestamp_at(start + Duration::from_millis(100)),
+            10000
+        );
+    }
+}
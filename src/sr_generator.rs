@@ -0,0 +1,363 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Instant, SystemTime},
+};
+
+use rtp_parse::rtcp::{
+    rtcp_packet::SomeRtcpPacket,
+    rtcp_report_block::RtcpReportBlock,
+    rtcp_rr::RtcpRrPacket,
+    rtcp_sdes::{RtcpSdesPacket, SdesChunk, SdesItem},
+    rtcp_sr::{RtcpSrPacket, RtcpSrSenderInfo},
+};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{
+    clock::{to_ntp_timestamp, Clock},
+    receive_statistics::ReceiveStatisticsSnapshot,
+    rr_generator::{build_cname_sdes, ReportBlockBuilder, MAX_REPORT_BLOCKS},
+    rtcp_scheduler::RtcpScheduler,
+    rtcp_termination::RtcpEvent,
+    rtcp_writer::{new_rtcp_header, write_rtcp_packet, RTCP_PT_RR, RTCP_PT_SDES, RTCP_PT_SR},
+    rtt_estimator::ReportHistory,
+    send_statistics::SendStatistics,
+    timer::{TimerHandle, TimerHandler, TimerScheduler},
+    util::{LiveStateReader, SharedData},
+};
+
+/// Periodically sends RTCP sender reports for the streams we're sending, using the counts
+/// gathered by [`crate::send_statistics::SendStatisticsTracker`].  If we're also receiving
+/// streams then `receive_stats` should be given, and the reports will carry report blocks for
+/// them.  Reports are only sent for streams which have sent packets since the previous report, so
+/// while none have (e.g. we're muted) receiver reports are sent from `sender_ssrc` instead, and an
+/// [`crate::rr_generator::RrGenerator`] isn't needed alongside this.
+pub struct SrGenerator {
+    clock: Arc<dyn Clock>,
+    sender_ssrc: u32,
+    cname: String,
+    send_stats: SharedData<SendStatistics>,
+    receive_stats: Option<LiveStateReader<ReceiveStatisticsSnapshot>>,
+    report_block_builder: ReportBlockBuilder,
+    scheduler: RtcpScheduler,
+    /// The packet count for each ssrc at the time of the previous report
+    prior_packet_counts: HashMap<u32, u32>,
+    report_history: SharedData<ReportHistory>,
+    rtcp_tx: UnboundedSender<SomeRtcpPacket>,
+}
+
+impl SrGenerator {
+    pub fn new(
+        clock: Arc<dyn Clock>,
+        sender_ssrc: u32,
+        cname: String,
+        send_stats: SharedData<SendStatistics>,
+        receive_stats: Option<LiveStateReader<ReceiveStatisticsSnapshot>>,
+        scheduler: RtcpScheduler,
+        rtcp_tx: UnboundedSender<SomeRtcpPacket>,
+    ) -> Self {
+        Self {
+            clock,
+            sender_ssrc,
+            cname,
+            send_stats,
+            receive_stats,
+            report_block_builder: ReportBlockBuilder::default(),
+            scheduler,
+            prior_packet_counts: HashMap::new(),
+            report_history: SharedData::default(),
+            rtcp_tx,
+        }
+    }
+
+    /// The sender reports we've sent, for matching against the LSR in the report blocks we
+    /// receive (see [`crate::rtt_estimator::RttEstimator`]).
+    pub fn report_history(&self) -> SharedData<ReportHistory> {
+        self.report_history.clone()
+    }
+
+    /// Have reports sent from `timers`, starting with a check straight away, until the returned
+    /// [`TimerHandle`] is dropped.  The generator is shared with the scheduler, so [`RtcpEvent`]s
+    /// should be passed to the returned generator via [`SrGenerator::handle_event`].
+    pub fn register(self, timers: &TimerScheduler) -> (SharedData<Self>, TimerHandle) {
+        let now = self.clock.now();
+        let generator = SharedData::new(self);
+        let timer = timers.register(generator.clone());
+        timer.schedule(now);
+        (generator, timer)
+    }
+
+    pub fn handle_event(&mut self, event: &RtcpEvent) {
+        if let RtcpEvent::SenderReport {
+            ssrc,
+            ntp_timestamp,
+            received_time,
+            ..
+        } = event
+        {
+            self.report_block_builder
+                .sender_report_received(*ssrc, *ntp_timestamp, *received_time);
+        }
+    }
+
+    pub fn maybe_send_report(&mut self) {
+        let now = self.clock.now();
+        let remote_senders = self
+            .receive_stats
+            .as_ref()
+            .map_or(0, |stats| stats.value().len());
+        let members = remote_senders + 1;
+        let senders = remote_senders + 1;
+        if !self.scheduler.is_due(now, members, senders, true) {
+            return;
+        }
+        let system_time = self.clock.system_time();
+        let report = self.build_report(now, system_time);
+        let size = write_rtcp_packet(&report).map_or(0, |buf| buf.len());
+        if is_sender_report(&report) {
+            self.report_history
+                .write()
+                .report_sent(to_ntp_timestamp(system_time), now);
+        }
+        let _ = self.rtcp_tx.send(report);
+        self.scheduler
+            .report_sent(now, size, members, senders, true);
+    }
+
+    /// Build a compound packet with a sender report for each stream that has sent packets since
+    /// the previous report, followed by the CNAME for each of them.  If no stream has, it holds
+    /// receiver reports and the CNAME for `sender_ssrc` instead.  `now` and `system_time` must
+    /// refer to the same instant.
+    pub fn build_report(&mut self, now: Instant, system_time: SystemTime) -> SomeRtcpPacket {
+        let ntp_timestamp = to_ntp_timestamp(system_time);
+        let mut sender_reports = Vec::new();
+        {
+            let send_stats = self.send_stats.read();
+            let mut active_streams = send_stats
+                .iter()
+                .filter(|(ssrc, stats)| {
+                    self.prior_packet_counts.get(ssrc) != Some(&stats.packet_count)
+                })
+                .collect::<Vec<_>>();
+            active_streams.sort_by_key(|(&ssrc, _)| ssrc);
+            for (&ssrc, stats) in active_streams {
+                self.prior_packet_counts.insert(ssrc, stats.packet_count);
+                sender_reports.push(RtcpSrPacket {
+                    header: new_rtcp_header(0, RTCP_PT_SR),
+                    sender_ssrc: ssrc,
+                    sender_info: RtcpSrSenderInfo {
+                        ntp_timestamp_msw: (ntp_timestamp >> 32) as u32,
+                        ntp_timestamp_lsw: ntp_timestamp as u32,
+                        rtp_timestamp: stats.rtp_timestamp_at(now),
+                        sender_packet_count: stats.packet_count,
+                        sender_octet_count: stats.octet_count,
+                    },
+                    report_blocks: Vec::new(),
+                });
+            }
+        }
+        let report_blocks = match self.receive_stats {
+            Some(ref receive_stats) => self
+                .report_block_builder
+                .build_report_blocks(&receive_stats.value(), now),
+            None => Vec::new(),
+        };
+        let Some(first_ssrc) = sender_reports.first().map(|sr| sr.sender_ssrc) else {
+            // We still need to send at least one report, even if it's empty
+            let mut packets = report_blocks
+                .chunks(MAX_REPORT_BLOCKS)
+                .map(|report_blocks| receiver_report(self.sender_ssrc, report_blocks))
+                .collect::<Vec<_>>();
+            if packets.is_empty() {
+                packets.push(receiver_report(self.sender_ssrc, &[]));
+            }
+            packets.push(build_cname_sdes(self.sender_ssrc, &self.cname));
+            return SomeRtcpPacket::CompoundRtcpPacket(packets);
+        };
+        let mut report_block_chunks = report_blocks.chunks(MAX_REPORT_BLOCKS);
+        if let Some(report_blocks) = report_block_chunks.next() {
+            sender_reports[0].report_blocks = report_blocks.to_vec();
+            sender_reports[0].header = new_rtcp_header(report_blocks.len() as u8, RTCP_PT_SR);
+        }
+
+        let sdes = SomeRtcpPacket::RtcpSdesPacket(RtcpSdesPacket {
+            header: new_rtcp_header(sender_reports.len() as u8, RTCP_PT_SDES),
+            chunks: sender_reports
+                .iter()
+                .map(|sr| SdesChunk {
+                    ssrc: sr.sender_ssrc,
+                    sdes_items: vec![SdesItem::Cname(self.cname.clone())],
+                })
+                .collect(),
+        });
+        let mut packets = sender_reports
+            .into_iter()
+            .map(SomeRtcpPacket::RtcpSrPacket)
+            .collect::<Vec<_>>();
+        // Any report blocks which didn't fit go in additional receiver reports
+        for report_blocks in report_block_chunks {
+            packets.push(receiver_report(first_ssrc, report_blocks));
+        }
+        packets.push(sdes);
+
+        SomeRtcpPacket::CompoundRtcpPacket(packets)
+    }
+}
+
+fn receiver_report(sender_ssrc: u32, report_blocks: &[RtcpReportBlock]) -> SomeRtcpPacket {
+    SomeRtcpPacket::RtcpRrPacket(RtcpRrPacket {
+        header: new_rtcp_header(report_blocks.len() as u8, RTCP_PT_RR),
+        sender_ssrc,
+        report_blocks: report_blocks.to_vec(),
+    })
+}
+
+fn is_sender_report(report: &SomeRtcpPacket) -> bool {
+    matches!(
+        report,
+        SomeRtcpPacket::CompoundRtcpPacket(packets)
+            if matches!(packets.first(), Some(SomeRtcpPacket::RtcpSrPacket(_)))
+    )
+}
+
+impl TimerHandler for SrGenerator {
+    fn on_timer(&mut self, _now: Instant) -> Option<Instant> {
+        self.maybe_send_report();
+        self.scheduler.next_report_time()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use crate::{
+        clock::{compact_ntp, ManualClock},
+        receive_statistics::StreamReceiveStatistics,
+        util::LiveStateWriter,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_sender_reports() {
+        let clock = Arc::new(ManualClock::new());
+        let send_stats = SharedData::new(SendStatistics::default());
+        let receive_stats = LiveStateWriter::new(HashMap::from([(
+            5678,
+            StreamReceiveStatistics {
+                extended_highest_seq_num: 9,
+                packets_received: 10,
+                ..Default::default()
+            },
+        )]));
+        let (tx, mut rx) = unbounded_channel();
+        let mut generator = SrGenerator::new(
+            clock.clone(),
+            42,
+            String::from("cname"),
+            send_stats.clone(),
+            Some(receive_stats.reader()),
+            RtcpScheduler::new(8000.0, Duration::from_secs(1)),
+            tx,
+        );
+
+        send_stats
+            .write()
+            .packet_sent(1234, 90000, 100, 90000, clock.now());
+        send_stats
+            .write()
+            .packet_sent(1234, 93000, 100, 90000, clock.now());
+        // Schedules the first report
+        generator.maybe_send_report();
+        assert!(rx.try_recv().is_err());
+
+        clock.advance(Duration::from_secs(2));
+        generator.maybe_send_report();
+        let Ok(SomeRtcpPacket::CompoundRtcpPacket(packets)) = rx.try_recv() else {
+            panic!("expected compound packet");
+        };
+        assert_eq!(packets.len(), 2);
+        let SomeRtcpPacket::RtcpSrPacket(ref sr) = packets[0] else {
+            panic!("expected sr");
+        };
+        assert_eq!(sr.sender_ssrc, 1234);
+        assert_eq!(
+            sr.sender_info,
+            RtcpSrSenderInfo {
+                ntp_timestamp_msw: 1_000_002 + 2_208_988_800,
+                ntp_timestamp_lsw: 0,
+                // Extrapolated 2 seconds on from the last packet
+                rtp_timestamp: 93000 + 180000,
+                sender_packet_count: 2,
+                sender_octet_count: 200,
+            }
+        );
+        assert_eq!(sr.report_blocks.len(), 1);
+        assert_eq!(sr.report_blocks[0].ssrc, 5678);
+        assert!(matches!(packets[1], SomeRtcpPacket::RtcpSdesPacket(_)));
+        assert_eq!(
+            generator
+                .report_history()
+                .read()
+                .sent_time(compact_ntp(to_ntp_timestamp(clock.system_time()))),
+            Some(clock.now())
+        );
+
+        // Nothing's been sent since the last report (e.g. we've been muted), so only a receiver
+        // report is sent
+        receive_stats.set(HashMap::from([(
+            5678,
+            StreamReceiveStatistics {
+                extended_highest_seq_num: 19,
+                packets_received: 20,
+                ..Default::default()
+            },
+        )]));
+        clock.advance(Duration::from_secs(10));
+        generator.maybe_send_report();
+        let Ok(SomeRtcpPacket::CompoundRtcpPacket(packets)) = rx.try_recv() else {
+            panic!("expected compound packet");
+        };
+        assert_eq!(packets.len(), 2);
+        let SomeRtcpPacket::RtcpRrPacket(ref rr) = packets[0] else {
+            panic!("expected rr");
+        };
+        assert_eq!(rr.sender_ssrc, 42);
+        assert_eq!(rr.report_blocks.len(), 1);
+        assert_eq!(rr.report_blocks[0].ssrc, 5678);
+        assert_eq!(packets[1], build_cname_sdes(42, "cname"));
+        assert_eq!(
+            generator
+                .report_history()
+                .read()
+                .sent_time(compact_ntp(to_ntp_timestamp(clock.system_time()))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_receiver_report_before_sending() {
+        let clock = Arc::new(ManualClock::new());
+        let (tx, _rx) = unbounded_channel();
+        let mut generator = SrGenerator::new(
+            clock.clone(),
+            42,
+            String::from("cname"),
+            SharedData::new(SendStatistics::default()),
+            None,
+            RtcpScheduler::new(8000.0, Duration::from_secs(1)),
+            tx,
+        );
+
+        assert_eq!(
+            generator.build_report(clock.now(), clock.system_time()),
+            SomeRtcpPacket::CompoundRtcpPacket(vec![
+                receiver_report(42, &[]),
+                build_cname_sdes(42, "cname"),
+            ])
+        );
+    }
+}
@@ -1,5 +1,7 @@
 pub mod srtcp_decrypt;
+pub mod srtcp_encrypt;
 pub mod srtp_decrypt;
+pub mod srtp_encrypt;
 
 pub use webrtc_srtp::{
     config::Config, config::SessionKeys, context::Context as SrtpContext,
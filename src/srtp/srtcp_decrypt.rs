@@ -1,11 +1,12 @@
-use std::collections::HashMap;
+use std::collections::{hash_map::Entry, HashMap};
 
-use anyhow::{bail, Result};
+use anyhow::Result;
 use data_pipeline_rs::data_handler::{DataTransformer, SomeDataHandler};
 use rtp_parse::rtcp::rtcp_header;
 use webrtc_srtp::{config::Config, context::Context as SrtpContext};
 
 use crate::{
+    error::PipelineError,
     packet_info::{PacketInfo, SomePacket},
     util::SharedData,
 };
@@ -21,19 +22,23 @@ pub struct SrtcpDecrypt {
 }
 
 impl SrtcpDecrypt {
-    fn get_context(&mut self, ssrc: u32) -> &mut SrtpContext {
-        self.contexts.entry(ssrc).or_insert_with(|| {
-            let config = self.config.read();
-            SrtpContext::new(
-                &config.keys.local_master_key,
-                &config.keys.local_master_salt,
-                config.profile,
-                // TODO: should pass options in here
-                None,
-                None,
-            )
-            .unwrap()
-        })
+    fn get_context(&mut self, ssrc: u32) -> Result<&mut SrtpContext> {
+        match self.contexts.entry(ssrc) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let config = self.config.read();
+                let context = SrtpContext::new(
+                    &config.keys.local_master_key,
+                    &config.keys.local_master_salt,
+                    config.profile,
+                    // TODO: should pass options in here
+                    None,
+                    None,
+                )
+                .map_err(|e| PipelineError::from_srtp(ssrc, e))?;
+                Ok(entry.insert(context))
+            }
+        }
     }
 }
 
@@ -42,22 +47,19 @@ impl DataTransformer<PacketInfo> for SrtcpDecrypt {
         match data.packet {
             SomePacket::UnparsedPacket(ref buf) => {
                 let ssrc = rtcp_header::get_sender_ssrc(buf);
-                let context = self.get_context(ssrc);
-                match context.decrypt_rtcp(buf) {
-                    Ok(bytes) => {
-                        // TODO: we should look at using 'Bytes' everywhere, most likely, but
-                        // it's also a bit annoying that webrtc-rs parses the header as part of
-                        // the decrypt, using its own types.  need to dig into what to do
-                        // there overall.
-                        data.packet = SomePacket::UnparsedPacket(bytes.to_vec());
-                    }
-                    Err(e) => {
-                        println!("Error decrypting packet: {e}");
-                        bail!("Error decrypting packet: {e}");
-                    }
-                }
+                let context = self.get_context(ssrc)?;
+                let bytes = context
+                    .decrypt_rtcp(buf)
+                    .map_err(|e| PipelineError::from_srtp(ssrc, e))?;
+                // TODO: we should look at using 'Bytes' everywhere, most likely, but
+                // it's also a bit annoying that webrtc-rs parses the header as part of
+                // the decrypt, using its own types.  need to dig into what to do
+                // there overall.
+                data.packet = SomePacket::UnparsedPacket(bytes.to_vec());
+            }
+            ref packet => {
+                return Err(PipelineError::unexpected_packet_type("SrtcpDecrypt", packet).into())
             }
-            _ => panic!("Unsupported packet type passed to srtp decrypt"),
         }
 
         Ok(data)
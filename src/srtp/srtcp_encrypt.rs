@@ -0,0 +1,154 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use anyhow::Result;
+use data_pipeline_rs::data_handler::{DataTransformer, SomeDataHandler};
+use rtp_parse::rtcp::rtcp_header;
+use webrtc_srtp::{config::Config, context::Context as SrtpContext};
+
+use crate::{
+    error::PipelineError,
+    packet_info::{PacketInfo, SomePacket},
+    rtcp_writer::write_rtcp_packet,
+    util::SharedData,
+};
+
+pub struct SrtcpEncrypt {
+    pub contexts: HashMap<u32, SrtpContext>,
+    pub config: SharedData<Config>,
+}
+
+impl SrtcpEncrypt {
+    fn get_context(&mut self, ssrc: u32) -> Result<&mut SrtpContext> {
+        match self.contexts.entry(ssrc) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let config = self.config.read();
+                let context = SrtpContext::new(
+                    &config.keys.remote_master_key,
+                    &config.keys.remote_master_salt,
+                    config.profile,
+                    // TODO: should pass options in here
+                    None,
+                    None,
+                )
+                .map_err(|e| PipelineError::from_srtp(ssrc, e))?;
+                Ok(entry.insert(context))
+            }
+        }
+    }
+}
+
+impl DataTransformer<PacketInfo> for SrtcpEncrypt {
+    fn transform(&mut self, mut data: PacketInfo) -> Result<PacketInfo> {
+        let serialized;
+        let buf = match data.packet {
+            SomePacket::UnparsedPacket(ref buf) | SomePacket::UnparsedRtcpPacket(ref buf) => {
+                buf.as_slice()
+            }
+            SomePacket::RtcpPacket(ref rtcp) => {
+                serialized = write_rtcp_packet(rtcp)?;
+                serialized.as_slice()
+            }
+            ref packet => {
+                return Err(PipelineError::unexpected_packet_type("SrtcpEncrypt", packet).into())
+            }
+        };
+        let ssrc = rtcp_header::get_sender_ssrc(buf);
+        let context = self.get_context(ssrc)?;
+        let bytes = context
+            .encrypt_rtcp(buf)
+            .map_err(|e| PipelineError::from_srtp(ssrc, e))?;
+        data.packet = SomePacket::UnparsedPacket(bytes.to_vec());
+
+        Ok(data)
+    }
+}
+
+impl From<SrtcpEncrypt> for SomeDataHandler<PacketInfo> {
+    fn from(value: SrtcpEncrypt) -> Self {
+        SomeDataHandler::Transformer(Box::new(value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use bit_cursor::nsw_types::{u2, u5};
+    use rtp_parse::rtcp::{
+        rtcp_fb_header::RtcpFbHeader, rtcp_fb_pli::RtcpFbPliPacket, rtcp_header::RtcpHeader,
+        rtcp_packet::SomeRtcpPacket,
+    };
+    use webrtc_srtp::{config::SessionKeys, protection_profile::ProtectionProfile};
+
+    use crate::srtp::srtcp_decrypt::SrtcpDecrypt;
+
+    use super::*;
+
+    fn loopback_config() -> SharedData<Config> {
+        let master_key = vec![0x42u8; ProtectionProfile::Aes128CmHmacSha1_80.key_len()];
+        let master_salt = vec![0x24u8; ProtectionProfile::Aes128CmHmacSha1_80.salt_len()];
+        SharedData::new(Config {
+            keys: SessionKeys {
+                local_master_key: master_key.clone(),
+                local_master_salt: master_salt.clone(),
+                remote_master_key: master_key,
+                remote_master_salt: master_salt,
+            },
+            profile: ProtectionProfile::Aes128CmHmacSha1_80,
+            ..Default::default()
+        })
+    }
+
+    fn round_trip(packet: SomePacket) -> Vec<u8> {
+        let config = loopback_config();
+        let mut encrypt = SrtcpEncrypt {
+            contexts: HashMap::new(),
+            config: config.clone(),
+        };
+        let mut decrypt = SrtcpDecrypt {
+            contexts: HashMap::new(),
+            config,
+        };
+
+        let encrypted = encrypt
+            .transform(PacketInfo::new(packet, Instant::now()))
+            .unwrap();
+        let decrypted = decrypt.transform(encrypted).unwrap();
+        match decrypted.packet {
+            SomePacket::UnparsedPacket(data) => data,
+            _ => panic!("wrong output"),
+        }
+    }
+
+    #[test]
+    fn test_srtcp_encrypt_serialized() {
+        #[rustfmt::skip]
+        let rr: Vec<u8> = vec![
+            0x80, 0xC9, 0x00, 0x01,
+            0x01, 0x02, 0x03, 0x04,
+        ];
+
+        assert_eq!(round_trip(SomePacket::UnparsedRtcpPacket(rr.clone())), rr);
+    }
+
+    #[test]
+    fn test_srtcp_encrypt_parsed() {
+        let pli = SomeRtcpPacket::RtcpFbPliPacket(RtcpFbPliPacket {
+            header: RtcpHeader {
+                version: u2::new(2),
+                has_padding: false,
+                report_count: u5::new(1),
+                packet_type: 206,
+                length_field: 2,
+            },
+            fb_header: RtcpFbHeader {
+                sender_ssrc: 0x01020304,
+                media_source_ssrc: 0x05060708,
+            },
+        });
+        let expected = write_rtcp_packet(&pli).unwrap();
+
+        assert_eq!(round_trip(SomePacket::RtcpPacket(pli)), expected);
+    }
+}
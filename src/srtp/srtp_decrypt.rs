@@ -1,10 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{hash_map::Entry, HashMap};
 
 use crate::{
+    error::PipelineError,
     packet_info::{PacketInfo, SomePacket},
     util::SharedData,
 };
-use anyhow::{bail, Result};
+use anyhow::Result;
 use data_pipeline_rs::data_handler::{DataTransformer, SomeDataHandler};
 use rtp_parse::rtp::rtp_header::RtpHeader;
 use webrtc_srtp::{config::Config, context::Context as SrtpContext};
@@ -41,19 +42,23 @@ pub struct SrtpDecrypt {
 }
 
 impl SrtpDecrypt {
-    fn get_context(&mut self, ssrc: u32) -> &mut SrtpContext {
-        self.contexts.entry(ssrc).or_insert_with(|| {
-            let config = self.config.read();
-            SrtpContext::new(
-                &config.keys.local_master_key,
-                &config.keys.local_master_salt,
-                config.profile,
-                // TODO: should pass options in here
-                None,
-                None,
-            )
-            .unwrap()
-        })
+    fn get_context(&mut self, ssrc: u32) -> Result<&mut SrtpContext> {
+        match self.contexts.entry(ssrc) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let config = self.config.read();
+                let context = SrtpContext::new(
+                    &config.keys.local_master_key,
+                    &config.keys.local_master_salt,
+                    config.profile,
+                    // TODO: should pass options in here
+                    None,
+                    None,
+                )
+                .map_err(|e| PipelineError::from_srtp(ssrc, e))?;
+                Ok(entry.insert(context))
+            }
+        }
     }
 }
 
@@ -62,22 +67,19 @@ impl DataTransformer<PacketInfo> for SrtpDecrypt {
         match data.packet {
             SomePacket::UnparsedPacket(ref buf) => {
                 let ssrc = RtpHeader::ssrc(buf);
-                let context = self.get_context(ssrc);
-                match context.decrypt_rtp(buf) {
-                    Ok(bytes) => {
-                        // TODO: we should look at using 'Bytes' everywhere, most likely, but
-                        // it's also a bit annoying that webrtc-rs parses the header as part of
-                        // the decrypt, using its own types.  need to dig into what to do
-                        // there overall.
-                        data.packet = SomePacket::UnparsedPacket(bytes.to_vec());
-                    }
-                    Err(e) => {
-                        println!("Error decrypting packet: {e}");
-                        bail!("Error decrypting packet: {e}");
-                    }
-                }
+                let context = self.get_context(ssrc)?;
+                let bytes = context
+                    .decrypt_rtp(buf)
+                    .map_err(|e| PipelineError::from_srtp(ssrc, e))?;
+                // TODO: we should look at using 'Bytes' everywhere, most likely, but
+                // it's also a bit annoying that webrtc-rs parses the header as part of
+                // the decrypt, using its own types.  need to dig into what to do
+                // there overall.
+                data.packet = SomePacket::UnparsedPacket(bytes.to_vec());
+            }
+            ref packet => {
+                return Err(PipelineError::unexpected_packet_type("SrtpDecrypt", packet).into())
             }
-            _ => panic!("Unsupported packet type passed to srtp decrypt"),
         }
 
         Ok(data)
@@ -97,10 +99,52 @@ mod test {
         time::Instant,
     };
 
-    use webrtc_srtp::protection_profile::ProtectionProfile;
+    use webrtc_srtp::{
+        config::SessionKeys, option::srtp_replay_protection, protection_profile::ProtectionProfile,
+    };
+
+    use crate::srtp::srtp_encrypt::SrtpEncrypt;
 
     use super::*;
 
+    #[rustfmt::skip]
+    const PACKET: [u8; 52] = [
+        0x90, 0xEF, 0x43, 0xD7, 0xCF, 0x6F, 0xDE, 0x8F,
+        0x56, 0x29, 0x97, 0x7A, 0xBE, 0xDE, 0x00, 0x01,
+        0x10, 0xFF, 0x00, 0x00, 0x78, 0x0B, 0xE4, 0xC1,
+        0x36, 0xEC, 0xC5, 0x8D, 0x8C, 0x49, 0x46, 0x99,
+        0x04, 0xC5, 0xAA, 0xED, 0x92, 0xE7, 0x63, 0x4A,
+        0x3A, 0x18, 0x98, 0xEE, 0x62, 0xCB, 0x60, 0xFF,
+        0x6C, 0x1B, 0x29, 0x00
+    ];
+
+    fn loopback_config() -> SharedData<Config> {
+        let master_key = vec![0x42u8; ProtectionProfile::Aes128CmHmacSha1_80.key_len()];
+        let master_salt = vec![0x24u8; ProtectionProfile::Aes128CmHmacSha1_80.salt_len()];
+        // The sender's remote keys are the receiver's local keys, so use the same values for
+        // both here and loop the packet back through decrypt.
+        SharedData::new(Config {
+            keys: SessionKeys {
+                local_master_key: master_key.clone(),
+                local_master_salt: master_salt.clone(),
+                remote_master_key: master_key,
+                remote_master_salt: master_salt,
+            },
+            profile: ProtectionProfile::Aes128CmHmacSha1_80,
+            ..Default::default()
+        })
+    }
+
+    fn encrypt_packet(config: &SharedData<Config>) -> PacketInfo {
+        let mut encrypt = SrtpEncrypt {
+            contexts: HashMap::new(),
+            config: config.clone(),
+        };
+        encrypt
+            .transform(PacketInfo::new_unparsed(PACKET.to_vec(), Instant::now()))
+            .unwrap()
+    }
+
     #[test]
     fn test_srtp_decrypt() {
         #[rustfmt::skip]
@@ -179,6 +223,7 @@ mod test {
             .transform(PacketInfo {
                 packet: SomePacket::UnparsedPacket(packet),
                 should_discard: false,
+                is_retransmission: false,
                 received_time: Instant::now(),
             })
             .unwrap();
@@ -190,4 +235,82 @@ mod test {
             _ => panic!("wrong output"),
         }
     }
+
+    #[test]
+    fn test_srtp_decrypt_auth_failure() {
+        let config = loopback_config();
+        let mut encrypted = encrypt_packet(&config);
+        match encrypted.packet {
+            SomePacket::UnparsedPacket(ref mut data) => data[30] ^= 0xFF,
+            _ => panic!("wrong output"),
+        }
+        let mut decrypt = SrtpDecrypt {
+            contexts: HashMap::new(),
+            config,
+        };
+
+        let error = decrypt.transform(encrypted).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<PipelineError>(),
+            Some(PipelineError::AuthFailure { ssrc: 0x5629977a })
+        ));
+    }
+
+    #[test]
+    fn test_srtp_decrypt_replay() {
+        let config = loopback_config();
+        let encrypted = encrypt_packet(&config);
+        let data = match encrypted.packet {
+            SomePacket::UnparsedPacket(data) => data,
+            _ => panic!("wrong output"),
+        };
+        let context = {
+            let config = config.read();
+            SrtpContext::new(
+                &config.keys.local_master_key,
+                &config.keys.local_master_salt,
+                config.profile,
+                Some(srtp_replay_protection(64)),
+                None,
+            )
+            .unwrap()
+        };
+        let mut decrypt = SrtpDecrypt {
+            contexts: HashMap::from([(0x5629977a, context)]),
+            config,
+        };
+
+        decrypt
+            .transform(PacketInfo::new_unparsed(data.clone(), Instant::now()))
+            .unwrap();
+        let error = decrypt
+            .transform(PacketInfo::new_unparsed(data, Instant::now()))
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<PipelineError>(),
+            Some(PipelineError::Replay { ssrc: 0x5629977a })
+        ));
+    }
+
+    #[test]
+    fn test_srtp_decrypt_unexpected_packet_type() {
+        let mut decrypt = SrtpDecrypt {
+            contexts: HashMap::new(),
+            config: loopback_config(),
+        };
+
+        let error = decrypt
+            .transform(PacketInfo::new(
+                SomePacket::UnparsedRtcpPacket(vec![]),
+                Instant::now(),
+            ))
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<PipelineError>(),
+            Some(PipelineError::UnexpectedPacketType {
+                handler: "SrtpDecrypt",
+                packet_type: "UnparsedRtcpPacket"
+            })
+        ));
+    }
 }
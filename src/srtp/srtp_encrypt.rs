@@ -0,0 +1,140 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use crate::{
+    error::PipelineError,
+    packet_info::{PacketInfo, SomePacket},
+    util::SharedData,
+};
+use anyhow::Result;
+use data_pipeline_rs::data_handler::{DataTransformer, SomeDataHandler};
+use rtp_parse::rtp::rtp_header::RtpHeader;
+use webrtc_srtp::{config::Config, context::Context as SrtpContext};
+
+pub struct SrtpEncrypt {
+    pub contexts: HashMap<u32, SrtpContext>,
+    pub config: SharedData<Config>,
+}
+
+impl SrtpEncrypt {
+    fn get_context(&mut self, ssrc: u32) -> Result<&mut SrtpContext> {
+        match self.contexts.entry(ssrc) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let config = self.config.read();
+                let context = SrtpContext::new(
+                    &config.keys.remote_master_key,
+                    &config.keys.remote_master_salt,
+                    config.profile,
+                    // TODO: should pass options in here
+                    None,
+                    None,
+                )
+                .map_err(|e| PipelineError::from_srtp(ssrc, e))?;
+                Ok(entry.insert(context))
+            }
+        }
+    }
+}
+
+impl DataTransformer<PacketInfo> for SrtpEncrypt {
+    fn transform(&mut self, mut data: PacketInfo) -> Result<PacketInfo> {
+        let buf = match data.packet {
+            SomePacket::UnparsedPacket(ref buf) => buf.as_slice(),
+            SomePacket::RtpPacket(ref rtp)
+            | SomePacket::AudioRtpPacket(ref rtp)
+            | SomePacket::VideoRtpPacket(ref rtp) => rtp.buf(),
+            ref packet => {
+                return Err(PipelineError::unexpected_packet_type("SrtpEncrypt", packet).into())
+            }
+        };
+        let ssrc = RtpHeader::ssrc(buf);
+        let context = self.get_context(ssrc)?;
+        let bytes = context
+            .encrypt_rtp(buf)
+            .map_err(|e| PipelineError::from_srtp(ssrc, e))?;
+        data.packet = SomePacket::UnparsedPacket(bytes.to_vec());
+
+        Ok(data)
+    }
+}
+
+impl From<SrtpEncrypt> for SomeDataHandler<PacketInfo> {
+    fn from(value: SrtpEncrypt) -> Self {
+        SomeDataHandler::Transformer(Box::new(value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use webrtc_srtp::{config::SessionKeys, protection_profile::ProtectionProfile};
+
+    use crate::srtp::srtp_decrypt::SrtpDecrypt;
+
+    use super::*;
+
+    #[rustfmt::skip]
+    const PACKET: [u8; 52] = [
+        0x90, 0xEF, 0x43, 0xD7, 0xCF, 0x6F, 0xDE, 0x8F,
+        0x56, 0x29, 0x97, 0x7A, 0xBE, 0xDE, 0x00, 0x01,
+        0x10, 0xFF, 0x00, 0x00, 0x78, 0x0B, 0xE4, 0xC1,
+        0x36, 0xEC, 0xC5, 0x8D, 0x8C, 0x49, 0x46, 0x99,
+        0x04, 0xC5, 0xAA, 0xED, 0x92, 0xE7, 0x63, 0x4A,
+        0x3A, 0x18, 0x98, 0xEE, 0x62, 0xCB, 0x60, 0xFF,
+        0x6C, 0x1B, 0x29, 0x00
+    ];
+
+    fn loopback_config() -> SharedData<Config> {
+        let master_key = vec![0x42u8; ProtectionProfile::Aes128CmHmacSha1_80.key_len()];
+        let master_salt = vec![0x24u8; ProtectionProfile::Aes128CmHmacSha1_80.salt_len()];
+        // The sender's remote keys are the receiver's local keys, so use the same values for
+        // both here and loop the packet back through decrypt.
+        SharedData::new(Config {
+            keys: SessionKeys {
+                local_master_key: master_key.clone(),
+                local_master_salt: master_salt.clone(),
+                remote_master_key: master_key,
+                remote_master_salt: master_salt,
+            },
+            profile: ProtectionProfile::Aes128CmHmacSha1_80,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_srtp_encrypt_round_trip() {
+        let packet = PACKET.to_vec();
+        let config = loopback_config();
+
+        let mut encrypt = SrtpEncrypt {
+            contexts: HashMap::new(),
+            config: config.clone(),
+        };
+        let mut decrypt = SrtpDecrypt {
+            contexts: HashMap::new(),
+            config,
+        };
+
+        let encrypted = encrypt
+            .transform(PacketInfo::new_unparsed(packet.clone(), Instant::now()))
+            .unwrap();
+        assert!(encrypt.contexts.contains_key(&0x5629977a));
+        match encrypted.packet {
+            SomePacket::UnparsedPacket(ref data) => {
+                assert_ne!(data, &packet);
+                // Header is sent in the clear
+                assert_eq!(data[..20], packet[..20]);
+            }
+            _ => panic!("wrong output"),
+        }
+
+        let decrypted = decrypt.transform(encrypted).unwrap();
+        match decrypted.packet {
+            SomePacket::UnparsedPacket(data) => {
+                assert_eq!(data, packet);
+            }
+            _ => panic!("wrong output"),
+        }
+    }
+}
@@ -0,0 +1,326 @@
+use data_pipeline_rs::data_handler::{DataObserver, SomeDataHandler};
+use rtp_parse::rtp::rtp_header::RtpHeader;
+
+use crate::{
+    error::{DroppedPackets, PipelineError},
+    packet_info::{PacketInfo, SomePacket},
+    rtp_parser::MediaType,
+    rtp_util,
+    stream_information_store::{SessionInfo, StreamDirection, StreamInfo, StreamInformationStore},
+    util::{LiveStateReader, SharedData},
+};
+
+pub const MID_URI: &str = "urn:ietf:params:rtp-hdrext:sdes:mid";
+pub const RID_URI: &str = "urn:ietf:params:rtp-hdrext:sdes:rtp-stream-id";
+pub const REPAIRED_RID_URI: &str = "urn:ietf:params:rtp-hdrext:sdes:repaired-rtp-stream-id";
+
+/// Learns remote ssrcs which weren't signaled (e.g. the layers of a simulcast stream) from the
+/// mid, rid and repaired rid header extensions, and registers them in the
+/// [`StreamInformationStore`].  RTX streams are paired with their primary stream once the primary
+/// stream with the same mid and rid has been seen.  This should be placed after decryption but
+/// before the [`crate::rtx_handler::RtxHandler`], which can only handle RTX packets once their
+/// ssrc has been paired.
+pub struct SsrcLearner {
+    store: SharedData<StreamInformationStore>,
+    session: LiveStateReader<SessionInfo>,
+    dropped_packets: SharedData<DroppedPackets>,
+}
+
+impl SsrcLearner {
+    pub fn new(
+        store: SharedData<StreamInformationStore>,
+        dropped_packets: SharedData<DroppedPackets>,
+    ) -> Self {
+        let session = store.read().subscribe_to_session_changes();
+        Self {
+            store,
+            session,
+            dropped_packets,
+        }
+    }
+
+    fn read_extension(buf: &[u8], session: &SessionInfo, uri: &str) -> Option<String> {
+        let ext_id = *session.header_extension_ids.get(uri)?;
+        rtp_util::get_extension_by_id(buf, ext_id)
+            .and_then(|data| std::str::from_utf8(data).ok())
+            .map(|value| value.trim_end_matches('\0').to_owned())
+            .filter(|value| !value.is_empty())
+    }
+
+    /// The media type of the packet's payload type, or of the primary payload type if it's RTX
+    fn media_type(buf: &[u8], session: &SessionInfo) -> Option<MediaType> {
+        let pt = rtp_util::payload_type(buf);
+        let pt = session.rtx_info.primary_payload_type(pt).unwrap_or(pt);
+        session.payload_types.media_type(&pt).cloned()
+    }
+
+    /// The stream to register for the packet, if it tells us anything we don't already know
+    fn learn(&self, buf: &[u8]) -> Option<StreamInfo> {
+        let ssrc = RtpHeader::ssrc(buf);
+        // Everything is read from a single snapshot, so a renegotiation can't change it part way
+        // through
+        let session = self.session.value();
+        let known = session.streams.get(ssrc).cloned();
+        // Once a stream is bound to a mid there's nothing more to learn about it
+        if known.as_ref().is_some_and(|stream| stream.mid.is_some()) {
+            return None;
+        }
+        let mid = Self::read_extension(buf, &session, MID_URI)?;
+        let media_type = Self::media_type(buf, &session);
+        if let Some(repaired_rid) = Self::read_extension(buf, &session, REPAIRED_RID_URI) {
+            // Wait until the primary stream is known, so the rtx stream can be paired with it
+            let primary = session
+                .streams
+                .by_mid_and_rid(&mid, &repaired_rid)
+                .cloned()?;
+            // The store publishes a new snapshot, which can't happen while we're reading one
+            drop(session);
+            self.store.write().add_stream(StreamInfo {
+                rtx_ssrc: Some(ssrc),
+                ..primary
+            });
+            // The rid is left unset so that looking up the layer by rid finds the primary stream
+            return Some(Self::new_stream(known, ssrc, media_type, mid));
+        }
+        let rid = Self::read_extension(buf, &session, RID_URI);
+        Some(StreamInfo {
+            rid,
+            ..Self::new_stream(known, ssrc, media_type, mid)
+        })
+    }
+
+    fn new_stream(
+        known: Option<StreamInfo>,
+        ssrc: u32,
+        media_type: Option<MediaType>,
+        mid: String,
+    ) -> StreamInfo {
+        let stream = known.unwrap_or_else(|| StreamInfo::new(ssrc, StreamDirection::Remote));
+        StreamInfo {
+            media_type: stream.media_type.clone().or(media_type),
+            mid: Some(mid),
+            ..stream
+        }
+    }
+}
+
+impl DataObserver<PacketInfo> for SsrcLearner {
+    fn observe(&mut self, data: &PacketInfo) {
+        let buf = match data.packet {
+            SomePacket::UnparsedPacket(ref buf) => buf,
+            ref packet => {
+                self.dropped_packets
+                    .write()
+                    .record(&PipelineError::unexpected_packet_type(
+                        "SsrcLearner",
+                        packet,
+                    ));
+                return;
+            }
+        };
+        // Anything too short to be RTP will be dropped by the rest of the pipeline
+        if rtp_util::header_length(buf).is_err() {
+            return;
+        }
+
+        if let Some(stream) = self.learn(buf) {
+            self.store.write().add_stream(stream);
+        }
+    }
+}
+
+impl From<SsrcLearner> for SomeDataHandler<PacketInfo> {
+    fn from(value: SsrcLearner) -> Self {
+        SomeDataHandler::Observer(Box::new(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use bit_cursor::nsw_types::u7;
+    use data_pipeline_rs::pipeline_builder::PipelineBuilder;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+    use crate::{
+        rtp_parser::RtpParser, rtx, rtx_handler::RtxHandler, stream_information_store::PayloadType,
+    };
+
+    use super::*;
+
+    const MID_ID: u8 = 1;
+    const RID_ID: u8 = 2;
+    const REPAIRED_RID_ID: u8 = 3;
+
+    /// A VP8 packet with the given one-byte header extensions
+    fn video_packet(ssrc: u32, extensions: &[(u8, &str)]) -> Vec<u8> {
+        let mut ext_data = Vec::new();
+        for (id, value) in extensions {
+            ext_data.push((id << 4) | (value.len() as u8 - 1));
+            ext_data.extend_from_slice(value.as_bytes());
+        }
+        ext_data.resize(ext_data.len().div_ceil(4) * 4, 0);
+
+        let mut packet = vec![0x90, 0x60, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00];
+        packet.extend_from_slice(&ssrc.to_be_bytes());
+        packet.extend_from_slice(&[0xBE, 0xDE]);
+        packet.extend_from_slice(&((ext_data.len() / 4) as u16).to_be_bytes());
+        packet.extend_from_slice(&ext_data);
+        packet.extend_from_slice(&[0xAA, 0xBB]);
+        packet
+    }
+
+    fn packet_info(packet: Vec<u8>) -> PacketInfo {
+        PacketInfo::new(SomePacket::UnparsedPacket(packet), Instant::now())
+    }
+
+    fn create_store() -> SharedData<StreamInformationStore> {
+        let store = SharedData::new(StreamInformationStore::new());
+        {
+            let mut store = store.write();
+            store.add_payload_type(PayloadType::new(
+                u7::new(96),
+                MediaType::Video,
+                "VP8",
+                90000,
+            ));
+            store.add_rtx_payload_type(u7::new(97), u7::new(96));
+            store.add_header_extension(String::from(MID_URI), MID_ID);
+            store.add_header_extension(String::from(RID_URI), RID_ID);
+            store.add_header_extension(String::from(REPAIRED_RID_URI), REPAIRED_RID_ID);
+        }
+        store
+    }
+
+    fn create_learner() -> (SsrcLearner, SharedData<StreamInformationStore>) {
+        let store = create_store();
+        (
+            SsrcLearner::new(store.clone(), SharedData::default()),
+            store,
+        )
+    }
+
+    #[test]
+    fn test_learn_simulcast_ssrcs() {
+        let (mut learner, store) = create_learner();
+        let streams = store.read().subscribe_to_stream_changes();
+
+        learner.observe(&packet_info(video_packet(
+            1,
+            &[(MID_ID, "1"), (RID_ID, "q")],
+        )));
+        learner.observe(&packet_info(video_packet(
+            2,
+            &[(MID_ID, "1"), (RID_ID, "h")],
+        )));
+        // No mid, so nothing can be learned
+        learner.observe(&packet_info(video_packet(3, &[(RID_ID, "f")])));
+
+        let stream = streams.value().get(1).cloned().unwrap();
+        assert_eq!(stream.direction, StreamDirection::Remote);
+        assert_eq!(stream.media_type, Some(MediaType::Video));
+        assert_eq!(stream.mid.as_deref(), Some("1"));
+        assert_eq!(stream.rid.as_deref(), Some("q"));
+        assert_eq!(streams.value().by_mid_and_rid("1", "h").unwrap().ssrc, 2);
+        assert!(streams.value().get(3).is_none());
+    }
+
+    #[test]
+    fn test_learn_rtx_ssrc() {
+        let (mut learner, store) = create_learner();
+        let streams = store.read().subscribe_to_stream_changes();
+        let rtx = store.read().subscribe_to_rtx_changes();
+
+        // The primary stream hasn't been seen yet, so the rtx stream can't be paired
+        let rtx_packet = video_packet(11, &[(MID_ID, "1"), (REPAIRED_RID_ID, "q")]);
+        learner.observe(&packet_info(rtx_packet.clone()));
+        assert!(streams.value().get(11).is_none());
+
+        learner.observe(&packet_info(video_packet(
+            1,
+            &[(MID_ID, "1"), (RID_ID, "q")],
+        )));
+        learner.observe(&packet_info(rtx_packet));
+        assert_eq!(streams.value().get(1).unwrap().rtx_ssrc, Some(11));
+        assert_eq!(streams.value().primary_ssrc(11), Some(1));
+        assert_eq!(rtx.value().primary_ssrc(11), Some(1));
+        assert_eq!(streams.value().get(11).unwrap().mid.as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn test_signaled_ssrc_is_bound_to_mid() {
+        let (mut learner, store) = create_learner();
+        let streams = store.read().subscribe_to_stream_changes();
+        store.write().add_stream(StreamInfo {
+            cname: Some(String::from("cname")),
+            ..StreamInfo::new(1, StreamDirection::Remote)
+        });
+
+        learner.observe(&packet_info(video_packet(
+            1,
+            &[(MID_ID, "0"), (RID_ID, "q")],
+        )));
+        let stream = streams.value().get(1).cloned().unwrap();
+        assert_eq!(stream.cname.as_deref(), Some("cname"));
+        assert_eq!(stream.mid.as_deref(), Some("0"));
+        assert_eq!(stream.rid.as_deref(), Some("q"));
+    }
+
+    /// Collects the (ssrc, is retransmission) of each packet which makes it through the pipeline
+    struct Output(UnboundedSender<(u32, bool)>);
+
+    impl DataObserver<PacketInfo> for Output {
+        fn observe(&mut self, data: &PacketInfo) {
+            if let SomePacket::VideoRtpPacket(ref rtp) = data.packet {
+                let _ = self.0.send((rtp.ssrc(), data.is_retransmission));
+            }
+        }
+    }
+
+    impl From<Output> for SomeDataHandler<PacketInfo> {
+        fn from(value: Output) -> Self {
+            SomeDataHandler::Observer(Box::new(value))
+        }
+    }
+
+    #[test]
+    fn test_unsignaled_rtx_in_pipeline() {
+        let store = create_store();
+        let (tx, mut rx) = unbounded_channel();
+        let pipeline = {
+            let (rtx_info, payload_types) = {
+                let store = store.read();
+                (
+                    store.subscribe_to_rtx_changes(),
+                    store.subscribe_to_pt_changes(),
+                )
+            };
+            PipelineBuilder::new()
+                .attach_handler(
+                    "SSRC learner",
+                    SsrcLearner::new(store.clone(), SharedData::default()),
+                )
+                .attach_handler("RTX handler", RtxHandler::new(rtx_info))
+                .attach_handler("RTP parser", RtpParser::new(payload_types))
+                .attach_handler("output", Output(tx))
+                .build()
+        };
+
+        let primary = video_packet(1, &[(MID_ID, "1"), (RID_ID, "q")]);
+        let rtx_packet = rtx::encapsulate(
+            &video_packet(1, &[(MID_ID, "1"), (REPAIRED_RID_ID, "q")]),
+            11,
+            u7::new(97),
+            1,
+        )
+        .unwrap();
+        pipeline.process_data(packet_info(primary));
+        // The first rtx packet is enough to pair the unsignaled rtx ssrc, so it isn't dropped
+        pipeline.process_data(packet_info(rtx_packet));
+
+        assert_eq!(rx.try_recv().unwrap(), (1, false));
+        assert_eq!(rx.try_recv().unwrap(), (1, true));
+        assert!(rx.try_recv().is_err());
+    }
+}
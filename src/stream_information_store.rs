@@ -7,16 +7,82 @@ use crate::{
     util::{LiveStateReader, LiveStateWriter},
 };
 
-// TODO: this should map to a 'PayloadType' struct which contains other information
-#[derive(Default)]
-pub struct PayloadTypes(HashMap<u7, MediaType>);
+/// An RTCP feedback type supported for a payload type, e.g. `nack`, `nack pli` or
+/// `transport-cc`.  https://datatracker.ietf.org/doc/html/rfc4585#section-4.2
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RtcpFeedback {
+    pub feedback_type: String,
+    pub parameter: Option<String>,
+}
+
+impl RtcpFeedback {
+    pub fn new(feedback_type: &str, parameter: Option<&str>) -> Self {
+        Self {
+            feedback_type: feedback_type.to_owned(),
+            parameter: parameter.map(str::to_owned),
+        }
+    }
+}
+
+/// Everything signaled about a single payload type
+#[derive(Clone, Debug, PartialEq)]
+pub struct PayloadType {
+    pub pt: u7,
+    pub media_type: MediaType,
+    /// The encoding name, e.g. `opus`, `VP8` or `rtx`
+    pub codec: String,
+    pub clock_rate: u32,
+    /// The number of audio channels, if signaled
+    pub channels: Option<u16>,
+    /// The format specific (fmtp) parameters, e.g. `apt` for rtx or `packetization-mode` for
+    /// H264.  Parameters without a value map to an empty string.
+    pub parameters: HashMap<String, String>,
+    pub rtcp_feedback: Vec<RtcpFeedback>,
+}
+
+impl PayloadType {
+    pub fn new(pt: u7, media_type: MediaType, codec: &str, clock_rate: u32) -> Self {
+        Self {
+            pt,
+            media_type,
+            codec: codec.to_owned(),
+            clock_rate,
+            channels: None,
+            parameters: HashMap::new(),
+            rtcp_feedback: Vec::new(),
+        }
+    }
+
+    pub fn is_rtx(&self) -> bool {
+        self.codec.eq_ignore_ascii_case("rtx")
+    }
+
+    /// For an rtx payload type, the payload type it carries retransmissions of
+    pub fn associated_payload_type(&self) -> Option<u7> {
+        let apt = self.parameters.get("apt")?.parse::<u8>().ok()?;
+        (apt <= 127).then(|| u7::new(apt))
+    }
+
+    pub fn supports_feedback(&self, feedback_type: &str, parameter: Option<&str>) -> bool {
+        self.rtcp_feedback.iter().any(|feedback| {
+            feedback.feedback_type == feedback_type && feedback.parameter.as_deref() == parameter
+        })
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct PayloadTypes(HashMap<u7, PayloadType>);
 
 impl PayloadTypes {
-    pub fn insert(&mut self, k: u7, v: MediaType) -> Option<MediaType> {
-        self.0.insert(k, v)
+    pub fn insert(&mut self, payload_type: PayloadType) -> Option<PayloadType> {
+        self.0.insert(payload_type.pt, payload_type)
+    }
+
+    pub fn remove(&mut self, pt: &u7) -> Option<PayloadType> {
+        self.0.remove(pt)
     }
 
-    pub fn get<Q>(&self, k: &Q) -> Option<&MediaType>
+    pub fn get<Q>(&self, k: &Q) -> Option<&PayloadType>
     where
         u7: Borrow<Q>,
         Q: Hash + Eq,
@@ -27,20 +93,40 @@ impl PayloadTypes {
     pub fn is_empty(&self) -> bool {
         self.0.is_empty()
     }
+
+    pub fn media_type(&self, pt: &u7) -> Option<&MediaType> {
+        self.0.get(pt).map(|payload_type| &payload_type.media_type)
+    }
+
+    /// The RTP clock rate for the given payload type
+    pub fn clock_rate(&self, pt: &u7) -> Option<u32> {
+        self.0.get(pt).map(|payload_type| payload_type.clock_rate)
+    }
 }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct HeaderExtensionIds(HashMap<String, u8>);
 
 impl HeaderExtensionIds {
+    /// Map `k` to id `v`.  An id can only be mapped to a single uri, so any other uri previously
+    /// mapped to `v` is removed.
     pub fn insert(&mut self, k: String, v: u8) -> Option<u8> {
+        self.0.retain(|uri, id| *id != v || *uri == k);
         self.0.insert(k, v)
     }
 
-    pub fn get<Q>(&self, k: &Q) -> Option<&u8>
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<u8>
     where
         String: Borrow<Q>,
         Q: Hash + Eq,
+    {
+        self.0.remove(k)
+    }
+
+    pub fn get<Q>(&self, k: &Q) -> Option<&u8>
+    where
+        String: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
     {
         self.0.get(k)
     }
@@ -50,11 +136,172 @@ impl HeaderExtensionIds {
     }
 }
 
+/// The RFC 4588 RTX associations for the streams in a session: which payload type carries
+/// retransmissions of which, and which ssrc carries retransmissions of which.
+#[derive(Clone, Default)]
+pub struct RtxInfo {
+    // Primary payload type -> rtx payload type
+    payload_types: HashMap<u7, u7>,
+    // Primary ssrc -> rtx ssrc
+    ssrcs: HashMap<u32, u32>,
+}
+
+impl RtxInfo {
+    pub fn rtx_payload_type(&self, primary_pt: u7) -> Option<u7> {
+        self.payload_types.get(&primary_pt).copied()
+    }
+
+    pub fn primary_payload_type(&self, rtx_pt: u7) -> Option<u7> {
+        self.payload_types
+            .iter()
+            .find(|(_, &pt)| pt == rtx_pt)
+            .map(|(&primary_pt, _)| primary_pt)
+    }
+
+    pub fn rtx_ssrc(&self, primary_ssrc: u32) -> Option<u32> {
+        self.ssrcs.get(&primary_ssrc).copied()
+    }
+
+    pub fn primary_ssrc(&self, rtx_ssrc: u32) -> Option<u32> {
+        self.ssrcs
+            .iter()
+            .find(|(_, &ssrc)| ssrc == rtx_ssrc)
+            .map(|(&primary_ssrc, _)| primary_ssrc)
+    }
+}
+
+/// Whether a stream is one we send or one we receive
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamDirection {
+    Local,
+    Remote,
+}
+
+/// What's known about a single ssrc
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamInfo {
+    pub ssrc: u32,
+    pub direction: StreamDirection,
+    pub media_type: Option<MediaType>,
+    pub mid: Option<String>,
+    /// The rtp stream id, identifying a simulcast layer within a mid
+    /// https://datatracker.ietf.org/doc/html/rfc8852
+    pub rid: Option<String>,
+    pub cname: Option<String>,
+    /// The ssrc carrying RTX retransmissions of this stream (from a FID group)
+    pub rtx_ssrc: Option<u32>,
+}
+
+impl StreamInfo {
+    pub fn new(ssrc: u32, direction: StreamDirection) -> Self {
+        Self {
+            ssrc,
+            direction,
+            media_type: None,
+            mid: None,
+            rid: None,
+            cname: None,
+            rtx_ssrc: None,
+        }
+    }
+}
+
+/// The registry of known streams and how they relate to each other
+#[derive(Clone, Debug, Default)]
+pub struct Streams {
+    streams: HashMap<u32, StreamInfo>,
+    /// The primary ssrcs of each simulcast group (from SIM groups), lowest layer first
+    simulcast_groups: Vec<Vec<u32>>,
+}
+
+impl Streams {
+    pub fn get(&self, ssrc: u32) -> Option<&StreamInfo> {
+        self.streams.get(&ssrc)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &StreamInfo> {
+        self.streams.values()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    pub fn by_mid<'a>(&'a self, mid: &'a str) -> impl Iterator<Item = &'a StreamInfo> {
+        self.streams
+            .values()
+            .filter(move |stream| stream.mid.as_deref() == Some(mid))
+    }
+
+    pub fn by_mid_and_rid(&self, mid: &str, rid: &str) -> Option<&StreamInfo> {
+        self.streams
+            .values()
+            .find(|stream| stream.mid.as_deref() == Some(mid) && stream.rid.as_deref() == Some(rid))
+    }
+
+    /// The stream which `rtx_ssrc` carries retransmissions of, if any
+    pub fn primary_ssrc(&self, rtx_ssrc: u32) -> Option<u32> {
+        self.streams
+            .values()
+            .find(|stream| stream.rtx_ssrc == Some(rtx_ssrc))
+            .map(|stream| stream.ssrc)
+    }
+
+    pub fn is_rtx(&self, ssrc: u32) -> bool {
+        self.primary_ssrc(ssrc).is_some()
+    }
+
+    /// The ssrcs of every layer in the simulcast group `ssrc` belongs to, lowest layer first
+    pub fn simulcast_layers(&self, ssrc: u32) -> Option<&[u32]> {
+        self.simulcast_groups
+            .iter()
+            .find(|group| group.contains(&ssrc))
+            .map(Vec::as_slice)
+    }
+
+    /// The position of `ssrc` within its simulcast group, where 0 is the lowest layer
+    pub fn simulcast_layer(&self, ssrc: u32) -> Option<usize> {
+        self.simulcast_layers(ssrc)?
+            .iter()
+            .position(|layer| *layer == ssrc)
+    }
+}
+
+/// Everything negotiated for a session, for replacing the contents of a
+/// [`StreamInformationStore`] in one go (see
+/// [`StreamInformationStore::apply_negotiated_session`]).
+#[derive(Clone, Debug, Default)]
+pub struct NegotiatedSession {
+    pub payload_types: Vec<PayloadType>,
+    /// Header extension uri -> id
+    pub header_extensions: HashMap<String, u8>,
+    /// Rtx payload type -> primary payload type
+    pub rtx_payload_types: HashMap<u7, u7>,
+    /// Rtx ssrc -> primary ssrc
+    pub rtx_ssrcs: HashMap<u32, u32>,
+    pub streams: Vec<StreamInfo>,
+    /// The primary ssrcs of each simulcast group, lowest layer first
+    pub simulcast_groups: Vec<Vec<u32>>,
+}
+
+/// Everything in a [`StreamInformationStore`] at once, for subscribers which use more than one
+/// kind of information and need them to agree with each other
+#[derive(Clone, Default)]
+pub struct SessionInfo {
+    pub payload_types: PayloadTypes,
+    pub rtx_info: RtxInfo,
+    pub streams: Streams,
+    pub header_extension_ids: HeaderExtensionIds,
+}
+
 pub struct StreamInformationStore {
     payload_types: LiveStateWriter<PayloadTypes>,
+    rtx_info: LiveStateWriter<RtxInfo>,
+    streams: LiveStateWriter<Streams>,
     header_extension_ids: LiveStateWriter<HeaderExtensionIds>,
     // For parties who are interested in only a single mapping
     header_extension_id_writers: HashMap<String, LiveStateWriter<Option<u8>>>,
+    session: LiveStateWriter<SessionInfo>,
 }
 
 impl StreamInformationStore {
@@ -64,41 +311,226 @@ impl StreamInformationStore {
 
         StreamInformationStore {
             payload_types,
+            rtx_info: LiveStateWriter::new(RtxInfo::default()),
+            streams: LiveStateWriter::new(Streams::default()),
             header_extension_ids,
             header_extension_id_writers: HashMap::default(),
+            session: LiveStateWriter::new(SessionInfo::default()),
         }
     }
 
-    pub fn add_payload_type(&mut self, pt: u7, media_type: MediaType) {
+    pub fn add_payload_type(&mut self, payload_type: PayloadType) {
         self.payload_types
-            .modify(|pts| _ = pts.insert(pt, media_type));
+            .modify(|pts| _ = pts.insert(payload_type));
+        self.publish_session();
+    }
+
+    pub fn add_payload_types(&mut self, new_pts: Vec<PayloadType>) {
+        self.payload_types.modify(|pts| {
+            pts.0.extend(
+                new_pts
+                    .into_iter()
+                    .map(|payload_type| (payload_type.pt, payload_type)),
+            )
+        });
+        self.publish_session();
+    }
+
+    pub fn remove_payload_type(&mut self, pt: u7) {
+        self.payload_types.modify(|pts| _ = pts.remove(&pt));
+        self.publish_session();
     }
 
-    pub fn add_payload_types(&mut self, new_pts: HashMap<u7, MediaType>) {
-        self.payload_types.modify(|pts| pts.0.extend(new_pts));
+    pub fn clear_payload_types(&mut self) {
+        self.payload_types.set(PayloadTypes::default());
+        self.publish_session();
     }
 
     pub fn subscribe_to_pt_changes(&self) -> LiveStateReader<PayloadTypes> {
         self.payload_types.reader()
     }
 
+    /// Signal that `rtx_pt` carries RTX retransmissions of packets sent with `primary_pt`
+    pub fn add_rtx_payload_type(&mut self, rtx_pt: u7, primary_pt: u7) {
+        self.rtx_info
+            .modify(|rtx| _ = rtx.payload_types.insert(primary_pt, rtx_pt));
+        self.publish_session();
+    }
+
+    /// Signal that `rtx_ssrc` carries RTX retransmissions of the stream with `primary_ssrc`
+    pub fn add_rtx_ssrc(&mut self, rtx_ssrc: u32, primary_ssrc: u32) {
+        self.rtx_info
+            .modify(|rtx| _ = rtx.ssrcs.insert(primary_ssrc, rtx_ssrc));
+        self.publish_session();
+    }
+
+    pub fn remove_rtx_payload_type(&mut self, rtx_pt: u7) {
+        self.rtx_info
+            .modify(|rtx| rtx.payload_types.retain(|_, pt| *pt != rtx_pt));
+        self.publish_session();
+    }
+
+    pub fn remove_rtx_ssrc(&mut self, rtx_ssrc: u32) {
+        self.rtx_info
+            .modify(|rtx| rtx.ssrcs.retain(|_, ssrc| *ssrc != rtx_ssrc));
+        self.publish_session();
+    }
+
+    pub fn clear_rtx(&mut self) {
+        self.rtx_info.set(RtxInfo::default());
+        self.publish_session();
+    }
+
+    pub fn subscribe_to_rtx_changes(&self) -> LiveStateReader<RtxInfo> {
+        self.rtx_info.reader()
+    }
+
+    /// Add (or replace) the stream for `stream.ssrc`.  If it has an rtx ssrc, the pairing is
+    /// also added to the [`RtxInfo`].
+    pub fn add_stream(&mut self, stream: StreamInfo) {
+        if let Some(rtx_ssrc) = stream.rtx_ssrc {
+            self.rtx_info
+                .modify(|rtx| _ = rtx.ssrcs.insert(stream.ssrc, rtx_ssrc));
+        }
+        self.streams
+            .modify(|streams| _ = streams.streams.insert(stream.ssrc, stream));
+        self.publish_session();
+    }
+
+    /// Remove the stream for `ssrc`, along with its rtx pairing and simulcast group membership
+    pub fn remove_stream(&mut self, ssrc: u32) {
+        self.streams.modify(|streams| {
+            streams.streams.remove(&ssrc);
+            for stream in streams.streams.values_mut() {
+                stream.rtx_ssrc.take_if(|rtx_ssrc| *rtx_ssrc == ssrc);
+            }
+            for group in &mut streams.simulcast_groups {
+                group.retain(|layer| *layer != ssrc);
+            }
+            streams.simulcast_groups.retain(|group| !group.is_empty());
+        });
+        self.rtx_info.modify(|rtx| {
+            rtx.ssrcs.remove(&ssrc);
+            rtx.ssrcs.retain(|_, rtx_ssrc| *rtx_ssrc != ssrc);
+        });
+        self.publish_session();
+    }
+
+    /// Signal that `ssrcs` are the primary ssrcs of the layers of a simulcast stream, lowest
+    /// layer first
+    pub fn add_simulcast_group(&mut self, ssrcs: Vec<u32>) {
+        self.streams
+            .modify(|streams| streams.simulcast_groups.push(ssrcs));
+        self.publish_session();
+    }
+
+    pub fn clear_streams(&mut self) {
+        self.streams.set(Streams::default());
+        self.publish_session();
+    }
+
+    pub fn subscribe_to_stream_changes(&self) -> LiveStateReader<Streams> {
+        self.streams.reader()
+    }
+
+    /// Map `uri` to `id`, replacing any previous mapping for either of them.
     pub fn add_header_extension(&mut self, uri: String, id: u8) {
-        let uri_insert: String = uri.clone();
         self.header_extension_ids
-            .modify(|hes| _ = hes.insert(uri_insert, id));
-        if let Some(w) = self.header_extension_id_writers.get(&uri) {
-            w.set(Some(id))
-        }
+            .modify(|hes| _ = hes.insert(uri, id));
+        self.update_header_extension_id_writers();
     }
 
     pub fn add_header_extensions(&mut self, new_hes: HashMap<String, u8>) {
-        for (uri, id) in &new_hes {
-            if let Some(w) = self.header_extension_id_writers.get(uri) {
-                w.set(Some(*id))
+        self.header_extension_ids.modify(|hes| {
+            for (uri, id) in new_hes {
+                hes.insert(uri, id);
             }
-        }
+        });
+        self.update_header_extension_id_writers();
+    }
+
+    pub fn remove_header_extension(&mut self, uri: &str) {
         self.header_extension_ids
-            .modify(|hes| hes.0.extend(new_hes));
+            .modify(|hes| _ = hes.0.remove(uri));
+        self.update_header_extension_id_writers();
+    }
+
+    pub fn clear_header_extensions(&mut self) {
+        self.header_extension_ids.set(HeaderExtensionIds::default());
+        self.update_header_extension_id_writers();
+    }
+
+    /// Replace everything in the store with what was negotiated for `session` (e.g. after a
+    /// renegotiation).  [`StreamInformationStore::subscribe_to_session_changes`] sees it all
+    /// change at once.
+    pub fn apply_negotiated_session(&mut self, session: NegotiatedSession) {
+        self.payload_types.set(PayloadTypes(
+            session
+                .payload_types
+                .into_iter()
+                .map(|payload_type| (payload_type.pt, payload_type))
+                .collect(),
+        ));
+        let mut rtx_ssrcs = session.rtx_ssrcs;
+        rtx_ssrcs.extend(
+            session
+                .streams
+                .iter()
+                .filter_map(|stream| Some((stream.rtx_ssrc?, stream.ssrc))),
+        );
+        self.rtx_info.set(RtxInfo {
+            payload_types: session
+                .rtx_payload_types
+                .into_iter()
+                .map(|(rtx_pt, primary_pt)| (primary_pt, rtx_pt))
+                .collect(),
+            ssrcs: rtx_ssrcs
+                .into_iter()
+                .map(|(rtx_ssrc, primary_ssrc)| (primary_ssrc, rtx_ssrc))
+                .collect(),
+        });
+        self.streams.set(Streams {
+            streams: session
+                .streams
+                .into_iter()
+                .map(|stream| (stream.ssrc, stream))
+                .collect(),
+            simulcast_groups: session.simulcast_groups,
+        });
+        let mut header_extension_ids = HeaderExtensionIds::default();
+        for (uri, id) in session.header_extensions {
+            header_extension_ids.insert(uri, id);
+        }
+        self.header_extension_ids.set(header_extension_ids);
+        self.update_header_extension_id_writers();
+    }
+
+    /// Bring the single-uri subscriptions in line with the current header extension ids,
+    /// including unmapping any uris which are no longer present.
+    fn update_header_extension_id_writers(&self) {
+        let header_extension_ids = self.header_extension_ids.value();
+        for (uri, writer) in &self.header_extension_id_writers {
+            let id = header_extension_ids.get(uri).copied();
+            if *writer.value() != id {
+                writer.set(id);
+            }
+        }
+        drop(header_extension_ids);
+        self.publish_session();
+    }
+
+    fn publish_session(&self) {
+        self.session.set(SessionInfo {
+            payload_types: self.payload_types.value().clone(),
+            rtx_info: self.rtx_info.value().clone(),
+            streams: self.streams.value().clone(),
+            header_extension_ids: self.header_extension_ids.value().clone(),
+        });
+    }
+
+    /// Everything in the store, which changes in a single update whenever anything in it does
+    pub fn subscribe_to_session_changes(&self) -> LiveStateReader<SessionInfo> {
+        self.session.reader()
     }
 
     pub fn subscribe_to_header_extension_id_changes(&self) -> LiveStateReader<HeaderExtensionIds> {
@@ -113,15 +545,12 @@ impl StreamInformationStore {
         String: Borrow<T>,
         T: Hash + Eq + Into<String>,
     {
-        let writer = if let Some(id) = self.header_extension_ids.value().get(&uri) {
-            LiveStateWriter::new(Some(*id))
-        } else {
-            LiveStateWriter::new(None)
-        };
-        let reader = writer.reader();
-        self.header_extension_id_writers.insert(uri.into(), writer);
-
-        reader
+        let id = self.header_extension_ids.value().get(&uri).copied();
+        // Reuse an existing writer so that earlier subscribers keep getting updates
+        self.header_extension_id_writers
+            .entry(uri.into())
+            .or_insert_with(|| LiveStateWriter::new(id))
+            .reader()
     }
 }
 
@@ -150,11 +579,18 @@ mod tests {
             Some(10).as_ref()
         );
         assert!(pt_reader.value().is_empty());
-        store.add_payload_type(u7::new(100), MediaType::Video);
+        let mut vp8 = PayloadType::new(u7::new(100), MediaType::Video, "VP8", 90000);
+        vp8.rtcp_feedback
+            .push(RtcpFeedback::new("nack", Some("pli")));
+        store.add_payload_type(vp8.clone());
+        assert_eq!(pt_reader.value().get(&u7::new(100)), Some(&vp8));
         assert_eq!(
-            pt_reader.value().get(&u7::new(100)),
-            Some(MediaType::Video).as_ref()
+            pt_reader.value().media_type(&u7::new(100)),
+            Some(&MediaType::Video)
         );
+        assert_eq!(pt_reader.value().clock_rate(&u7::new(100)), Some(90000));
+        assert!(vp8.supports_feedback("nack", Some("pli")));
+        assert!(!vp8.supports_feedback("nack", None));
     }
 
     #[test]
@@ -169,4 +605,233 @@ mod tests {
         let reader2 = store.subscribe_to_header_extension_id_change(String::from("foo"));
         assert_eq!(*reader2.value(), Some(10));
     }
+
+    #[test]
+    fn test_payload_type_apt() {
+        let mut rtx = PayloadType::new(u7::new(97), MediaType::Video, "rtx", 90000);
+        assert!(rtx.is_rtx());
+        assert_eq!(rtx.associated_payload_type(), None);
+        rtx.parameters
+            .insert(String::from("apt"), String::from("96"));
+        assert_eq!(rtx.associated_payload_type(), Some(u7::new(96)));
+    }
+
+    #[test]
+    fn test_rtx_info() {
+        let mut store = StreamInformationStore::new();
+        let reader = store.subscribe_to_rtx_changes();
+
+        store.add_rtx_payload_type(u7::new(97), u7::new(96));
+        store.add_rtx_ssrc(5678, 1234);
+        assert_eq!(
+            reader.value().rtx_payload_type(u7::new(96)),
+            Some(u7::new(97))
+        );
+        assert_eq!(
+            reader.value().primary_payload_type(u7::new(97)),
+            Some(u7::new(96))
+        );
+        assert_eq!(reader.value().primary_payload_type(u7::new(96)), None);
+        assert_eq!(reader.value().rtx_ssrc(1234), Some(5678));
+        assert_eq!(reader.value().primary_ssrc(5678), Some(1234));
+    }
+
+    #[test]
+    fn test_remove_and_remap_header_extension() {
+        let mut store = StreamInformationStore::new();
+        let tcc_reader = store.subscribe_to_header_extension_id_change(String::from("tcc"));
+        let tcc_reader2 = store.subscribe_to_header_extension_id_change(String::from("tcc"));
+        let mid_reader = store.subscribe_to_header_extension_id_change(String::from("mid"));
+
+        store.add_header_extension(String::from("tcc"), 5);
+        assert_eq!(*tcc_reader.value(), Some(5));
+        assert_eq!(*tcc_reader2.value(), Some(5));
+
+        // Re-mapping the id to a different uri unmaps the old one
+        store.add_header_extension(String::from("mid"), 5);
+        assert_eq!(*tcc_reader.value(), None);
+        assert_eq!(*mid_reader.value(), Some(5));
+        let hes = store.subscribe_to_header_extension_id_changes();
+        assert_eq!(hes.value().get(&String::from("tcc")), None);
+
+        store.add_header_extension(String::from("tcc"), 3);
+        store.remove_header_extension("tcc");
+        assert_eq!(*tcc_reader.value(), None);
+        assert_eq!(*mid_reader.value(), Some(5));
+
+        store.clear_header_extensions();
+        assert_eq!(*mid_reader.value(), None);
+        assert!(hes.value().is_empty());
+    }
+
+    #[test]
+    fn test_remove_payload_types_and_rtx() {
+        let mut store = StreamInformationStore::new();
+        let pts = store.subscribe_to_pt_changes();
+        let rtx = store.subscribe_to_rtx_changes();
+
+        store.add_payload_type(PayloadType::new(
+            u7::new(96),
+            MediaType::Video,
+            "VP8",
+            90000,
+        ));
+        store.add_payload_type(PayloadType::new(
+            u7::new(111),
+            MediaType::Audio,
+            "opus",
+            48000,
+        ));
+        store.add_rtx_payload_type(u7::new(97), u7::new(96));
+        store.add_rtx_ssrc(5678, 1234);
+
+        store.remove_payload_type(u7::new(96));
+        assert!(pts.value().get(&u7::new(96)).is_none());
+        assert!(pts.value().get(&u7::new(111)).is_some());
+        store.clear_payload_types();
+        assert!(pts.value().is_empty());
+
+        store.remove_rtx_payload_type(u7::new(97));
+        assert_eq!(rtx.value().primary_payload_type(u7::new(97)), None);
+        store.remove_rtx_ssrc(5678);
+        assert_eq!(rtx.value().primary_ssrc(5678), None);
+    }
+
+    #[test]
+    fn test_apply_negotiated_session() {
+        let mut store = StreamInformationStore::new();
+        let pts = store.subscribe_to_pt_changes();
+        let rtx = store.subscribe_to_rtx_changes();
+        let tcc_reader = store.subscribe_to_header_extension_id_change(String::from("tcc"));
+        let mid_reader = store.subscribe_to_header_extension_id_change(String::from("mid"));
+        store.add_payload_type(PayloadType::new(
+            u7::new(96),
+            MediaType::Video,
+            "VP8",
+            90000,
+        ));
+        store.add_rtx_payload_type(u7::new(97), u7::new(96));
+        store.add_header_extension(String::from("tcc"), 5);
+
+        store.apply_negotiated_session(NegotiatedSession {
+            payload_types: vec![PayloadType::new(
+                u7::new(98),
+                MediaType::Video,
+                "VP9",
+                90000,
+            )],
+            header_extensions: HashMap::from([(String::from("mid"), 5)]),
+            rtx_payload_types: HashMap::from([(u7::new(99), u7::new(98))]),
+            rtx_ssrcs: HashMap::from([(5678, 1234)]),
+            ..Default::default()
+        });
+        assert!(pts.value().get(&u7::new(96)).is_none());
+        assert_eq!(pts.value().get(&u7::new(98)).unwrap().codec, "VP9");
+        assert_eq!(rtx.value().primary_payload_type(u7::new(97)), None);
+        assert_eq!(
+            rtx.value().primary_payload_type(u7::new(99)),
+            Some(u7::new(98))
+        );
+        assert_eq!(rtx.value().primary_ssrc(5678), Some(1234));
+        assert_eq!(*tcc_reader.value(), None);
+        assert_eq!(*mid_reader.value(), Some(5));
+    }
+
+    #[test]
+    fn test_streams() {
+        let mut store = StreamInformationStore::new();
+        let streams = store.subscribe_to_stream_changes();
+        let rtx = store.subscribe_to_rtx_changes();
+
+        for (ssrc, rtx_ssrc, rid) in [(1, 11, "q"), (2, 12, "h"), (3, 13, "f")] {
+            store.add_stream(StreamInfo {
+                media_type: Some(MediaType::Video),
+                mid: Some(String::from("1")),
+                rid: Some(String::from(rid)),
+                cname: Some(String::from("cname")),
+                rtx_ssrc: Some(rtx_ssrc),
+                ..StreamInfo::new(ssrc, StreamDirection::Remote)
+            });
+            store.add_stream(StreamInfo {
+                mid: Some(String::from("1")),
+                ..StreamInfo::new(rtx_ssrc, StreamDirection::Remote)
+            });
+        }
+        store.add_stream(StreamInfo {
+            media_type: Some(MediaType::Audio),
+            mid: Some(String::from("0")),
+            ..StreamInfo::new(4, StreamDirection::Local)
+        });
+        store.add_simulcast_group(vec![1, 2, 3]);
+
+        assert_eq!(streams.value().by_mid("1").count(), 6);
+        assert_eq!(streams.value().by_mid_and_rid("1", "h").unwrap().ssrc, 2);
+        assert_eq!(
+            streams.value().get(4).unwrap().direction,
+            StreamDirection::Local
+        );
+        assert_eq!(streams.value().primary_ssrc(12), Some(2));
+        assert!(streams.value().is_rtx(13));
+        assert!(!streams.value().is_rtx(3));
+        assert_eq!(rtx.value().primary_ssrc(11), Some(1));
+        assert_eq!(streams.value().simulcast_layers(3), Some(&[1, 2, 3][..]));
+        assert_eq!(streams.value().simulcast_layer(3), Some(2));
+        assert_eq!(streams.value().simulcast_layer(4), None);
+
+        store.remove_stream(2);
+        assert!(streams.value().get(2).is_none());
+        assert_eq!(streams.value().simulcast_layers(1), Some(&[1, 3][..]));
+        assert_eq!(rtx.value().primary_ssrc(12), None);
+        store.remove_stream(11);
+        assert_eq!(streams.value().get(1).unwrap().rtx_ssrc, None);
+        assert_eq!(rtx.value().rtx_ssrc(1), None);
+
+        store.clear_streams();
+        assert!(streams.value().is_empty());
+    }
+
+    #[test]
+    fn test_apply_negotiated_session_atomic() {
+        const NUM_SESSIONS: u32 = 200;
+        let mut store = StreamInformationStore::new();
+        let session = store.subscribe_to_session_changes();
+
+        // Every kind of information in session n carries n, so that we can tell which session
+        // each part of a snapshot came from
+        let writer = std::thread::spawn(move || {
+            for n in 1..=NUM_SESSIONS {
+                store.apply_negotiated_session(NegotiatedSession {
+                    payload_types: vec![PayloadType::new(u7::new(96), MediaType::Video, "VP8", n)],
+                    header_extensions: HashMap::from([(String::from("tcc"), n as u8)]),
+                    streams: vec![StreamInfo {
+                        rtx_ssrc: Some(n),
+                        ..StreamInfo::new(1, StreamDirection::Remote)
+                    }],
+                    ..Default::default()
+                });
+            }
+        });
+
+        loop {
+            let session = session.value();
+            let pt = session.payload_types.clock_rate(&u7::new(96)).unwrap_or(0);
+            let rtx_ssrc = session.rtx_info.rtx_ssrc(1).unwrap_or(0);
+            let stream = session
+                .streams
+                .get(1)
+                .and_then(|stream| stream.rtx_ssrc)
+                .unwrap_or(0);
+            let header_extension_id = session
+                .header_extension_ids
+                .get("tcc")
+                .map_or(0, |&id| u32::from(id));
+            assert_eq!(rtx_ssrc, pt);
+            assert_eq!(stream, pt);
+            assert_eq!(header_extension_id, pt);
+            if pt == NUM_SESSIONS {
+                break;
+            }
+        }
+        writer.join().unwrap();
+    }
 }
@@ -1,21 +1,219 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
 use data_pipeline_rs::data_handler::{DataObserver, SomeDataHandler};
-use rtp_parse::rtp::tcc_header_extension::get_tcc_seq_num;
+use rtp_parse::{
+    rtcp::{
+        rtcp_fb_header::RtcpFbHeader,
+        rtcp_fb_tcc::{PacketReport, RtcpFbTccPacket},
+        rtcp_packet::SomeRtcpPacket,
+    },
+    rtp::tcc_header_extension::get_tcc_seq_num,
+};
+use tokio::sync::mpsc::UnboundedSender;
 
 use crate::{
+    error::{DroppedPackets, PipelineError},
     packet_info::{PacketInfo, SomePacket},
-    util::LiveStateReader,
+    rfc_3711_index::Rfc3711IndexTracker,
+    rtcp_writer::{new_rtcp_header, FMT_TCC, RTCP_PT_RTPFB},
+    timer::{TimerHandle, TimerHandler, TimerScheduler},
+    util::{LiveStateReader, SharedData},
 };
 
 pub const TCC_URI: &str =
     "http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01";
 
+/// The reference time in a TCC feedback packet is expressed in multiples of 64ms
+const REFERENCE_TIME_UNIT: Duration = Duration::from_millis(64);
+/// Receive deltas are expressed in multiples of 250µs
+const DELTA_TICK_MICROS: i64 = 250;
+/// The reference time field is 24 bits wide
+const REFERENCE_TIME_MASK: u32 = 0x00FF_FFFF;
+
+struct TccState {
+    sender_ssrc: u32,
+    feedback_interval: Duration,
+    feedback_tx: UnboundedSender<SomeRtcpPacket>,
+    index_tracker: Rfc3711IndexTracker,
+    /// Arrival times of packets which haven't been included in a feedback packet yet, keyed by
+    /// their extended transport sequence number
+    packet_arrival_times: BTreeMap<u32, Instant>,
+    /// The index of the first packet which hasn't been reported yet.  Anything older than this
+    /// arrived too late to be included in feedback.
+    next_index_to_report: Option<u32>,
+    /// All reference times are relative to the arrival of the first packet we saw
+    time_base: Option<Instant>,
+    last_feedback_time: Option<Instant>,
+    media_source_ssrc: u32,
+    feedback_packet_count: u8,
+}
+
+impl TccState {
+    fn new(
+        sender_ssrc: u32,
+        feedback_interval: Duration,
+        feedback_tx: UnboundedSender<SomeRtcpPacket>,
+    ) -> Self {
+        Self {
+            sender_ssrc,
+            feedback_interval,
+            feedback_tx,
+            index_tracker: Rfc3711IndexTracker::default(),
+            packet_arrival_times: BTreeMap::new(),
+            next_index_to_report: None,
+            time_base: None,
+            last_feedback_time: None,
+            media_source_ssrc: 0,
+            feedback_packet_count: 0,
+        }
+    }
+
+    fn packet_received(&mut self, tcc_seq_num: u16, received_time: Instant) {
+        let index = self.index_tracker.update(tcc_seq_num);
+        if self
+            .next_index_to_report
+            .is_some_and(|next_index| index < next_index)
+        {
+            return;
+        }
+        self.time_base.get_or_insert(received_time);
+        self.last_feedback_time.get_or_insert(received_time);
+        self.packet_arrival_times
+            .entry(index)
+            .or_insert(received_time);
+    }
+
+    /// When the pending packets should be reported, if there are any
+    fn next_feedback_time(&self) -> Option<Instant> {
+        if self.packet_arrival_times.is_empty() {
+            return None;
+        }
+        self.last_feedback_time
+            .map(|last_feedback_time| last_feedback_time + self.feedback_interval)
+    }
+
+    /// Build a feedback packet from the packets received since the last one was sent.  If not all
+    /// of the pending packets can be described by a single feedback packet (because a receive
+    /// delta is too large to be encoded) then the remainder is left for a subsequent call.
+    fn build_feedback(&mut self) -> Option<RtcpFbTccPacket> {
+        let time_base = self.time_base?;
+        let (&base_index, &first_arrival) = self.packet_arrival_times.first_key_value()?;
+
+        let reference_time =
+            first_arrival.duration_since(time_base).as_micros() / REFERENCE_TIME_UNIT.as_micros();
+        let mut prev_time = time_base + REFERENCE_TIME_UNIT * reference_time as u32;
+        let mut next_index = base_index;
+        let mut packet_reports = Vec::new();
+        for (&index, &arrival_time) in &self.packet_arrival_times {
+            if index - base_index >= u16::MAX as u32 {
+                break;
+            }
+            let delta_ticks = delta_ticks(prev_time, arrival_time);
+            let Ok(delta_ticks) = i16::try_from(delta_ticks) else {
+                break;
+            };
+            while next_index < index {
+                packet_reports.push(PacketReport::UnreceivedPacket {
+                    seq_num: next_index as u16,
+                });
+                next_index += 1;
+            }
+            let seq_num = index as u16;
+            match u8::try_from(delta_ticks) {
+                Ok(delta_ticks) => packet_reports.push(PacketReport::ReceivedPacketSmallDelta {
+                    seq_num,
+                    delta_ticks,
+                }),
+                Err(_) => packet_reports.push(PacketReport::ReceivedPacketLargeOrNegativeDelta {
+                    seq_num,
+                    delta_ticks,
+                }),
+            }
+            // Track the time the receiver will compute rather than the actual arrival time, so
+            // that rounding errors don't accumulate across deltas.
+            prev_time = add_ticks(prev_time, delta_ticks);
+            next_index = index + 1;
+        }
+        self.packet_arrival_times = self.packet_arrival_times.split_off(&next_index);
+        self.next_index_to_report = Some(next_index);
+
+        let feedback_packet_count = self.feedback_packet_count;
+        self.feedback_packet_count = self.feedback_packet_count.wrapping_add(1);
+
+        Some(RtcpFbTccPacket {
+            header: new_rtcp_header(FMT_TCC, RTCP_PT_RTPFB),
+            fb_header: RtcpFbHeader {
+                sender_ssrc: self.sender_ssrc,
+                media_source_ssrc: self.media_source_ssrc,
+            },
+            packet_reports,
+            reference_time: reference_time as u32 & REFERENCE_TIME_MASK,
+            feedback_packet_count,
+        })
+    }
+}
+
+impl TimerHandler for TccState {
+    fn on_timer(&mut self, now: Instant) -> Option<Instant> {
+        while let Some(feedback) = self.build_feedback() {
+            let _ = self
+                .feedback_tx
+                .send(SomeRtcpPacket::RtcpFbTccPacket(feedback));
+        }
+        self.last_feedback_time = Some(now);
+        None
+    }
+}
+
+/// Records the arrival times of packets carrying a transport-wide sequence number and reports
+/// them back to the sender every `feedback_interval`.  Feedback is sent from a [`TimerHandler`],
+/// so the packets received at the end of a burst are reported without waiting for another one.
 pub struct TccGenerator {
     tcc_ext_id: LiveStateReader<Option<u8>>,
+    state: SharedData<TccState>,
+    timer: TimerHandle,
+    dropped_packets: SharedData<DroppedPackets>,
 }
 
 impl TccGenerator {
-    pub fn new(tcc_ext_id: LiveStateReader<Option<u8>>) -> Self {
-        TccGenerator { tcc_ext_id }
+    pub fn new(
+        tcc_ext_id: LiveStateReader<Option<u8>>,
+        sender_ssrc: u32,
+        feedback_interval: Duration,
+        feedback_tx: UnboundedSender<SomeRtcpPacket>,
+        timers: &TimerScheduler,
+        dropped_packets: SharedData<DroppedPackets>,
+    ) -> Self {
+        let state = SharedData::new(TccState::new(sender_ssrc, feedback_interval, feedback_tx));
+        let timer = timers.register(state.clone());
+        Self {
+            tcc_ext_id,
+            state,
+            timer,
+            dropped_packets,
+        }
+    }
+}
+
+/// Get the delta between `from` and `to` in 250µs ticks.  The delta is negative if `to` is
+/// earlier than `from`.
+fn delta_ticks(from: Instant, to: Instant) -> i64 {
+    if to >= from {
+        to.duration_since(from).as_micros() as i64 / DELTA_TICK_MICROS
+    } else {
+        -(from.duration_since(to).as_micros() as i64 / DELTA_TICK_MICROS)
+    }
+}
+
+fn add_ticks(time: Instant, ticks: i16) -> Instant {
+    let delta = Duration::from_micros(ticks.unsigned_abs() as u64 * DELTA_TICK_MICROS as u64);
+    if ticks >= 0 {
+        time + delta
+    } else {
+        time - delta
     }
 }
 
@@ -24,13 +222,27 @@ impl DataObserver<PacketInfo> for TccGenerator {
         let rtp_packet = match data.packet {
             SomePacket::AudioRtpPacket(ref rtp) => rtp,
             SomePacket::VideoRtpPacket(ref rtp) => rtp,
-            _ => panic!("TccGenerator shouldn't see non rtp packet"),
+            ref packet => {
+                self.dropped_packets
+                    .write()
+                    .record(&PipelineError::unexpected_packet_type(
+                        "TccGenerator",
+                        packet,
+                    ));
+                return;
+            }
         };
 
-        if let Some(tcc_ext_id) = *self.tcc_ext_id.value() {
+        let tcc_ext_id = *self.tcc_ext_id.value();
+        if let Some(tcc_ext_id) = tcc_ext_id {
             if let Some(tcc) = rtp_packet.get_extension_by_id(tcc_ext_id) {
-                let _seq_num = get_tcc_seq_num(tcc);
-                // TODO: rest of tcc feedback generation
+                let seq_num = get_tcc_seq_num(tcc);
+                let mut state = self.state.write();
+                state.media_source_ssrc = rtp_packet.ssrc();
+                state.packet_received(seq_num, data.received_time);
+                if let Some(next_feedback_time) = state.next_feedback_time() {
+                    self.timer.schedule(next_feedback_time);
+                }
             }
         }
     }
@@ -41,3 +253,199 @@ impl From<TccGenerator> for SomeDataHandler<PacketInfo> {
         SomeDataHandler::Observer(Box::new(value))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rtp_parse::rtp::rtp_packet::read_rtp_packet;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use crate::{
+        clock::{Clock, ManualClock},
+        rtcp_writer::write_rtcp_packet,
+        util::LiveStateWriter,
+    };
+
+    use super::*;
+
+    fn create_generator(feedback_interval: Duration) -> TccState {
+        let (tx, _rx) = unbounded_channel();
+        TccState::new(0x01020304, feedback_interval, tx)
+    }
+
+    #[test]
+    fn test_feedback_with_loss() {
+        let mut generator = create_generator(Duration::from_millis(100));
+        generator.media_source_ssrc = 0x05060708;
+        let start = Instant::now();
+
+        generator.packet_received(10, start);
+        generator.packet_received(11, start + Duration::from_millis(1));
+        // 12 is lost
+        generator.packet_received(13, start + Duration::from_millis(2));
+
+        let feedback = generator.build_feedback().unwrap();
+        assert_eq!(
+            feedback.packet_reports,
+            vec![
+                PacketReport::ReceivedPacketSmallDelta {
+                    seq_num: 10,
+                    delta_ticks: 0
+                },
+                PacketReport::ReceivedPacketSmallDelta {
+                    seq_num: 11,
+                    delta_ticks: 4
+                },
+                PacketReport::UnreceivedPacket { seq_num: 12 },
+                PacketReport::ReceivedPacketSmallDelta {
+                    seq_num: 13,
+                    delta_ticks: 4
+                },
+            ]
+        );
+
+        #[rustfmt::skip]
+        let expected: Vec<u8> = vec![
+            0x8F, 0xCD, 0x00, 0x06,
+            0x01, 0x02, 0x03, 0x04,
+            0x05, 0x06, 0x07, 0x08,
+            // base seq num 10, status count 4
+            0x00, 0x0A, 0x00, 0x04,
+            // reference time 0, fb packet count 0
+            0x00, 0x00, 0x00, 0x00,
+            // 1 bit status vector chunk, deltas
+            0xB4, 0x00, 0x00, 0x04,
+            0x04, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(
+            write_rtcp_packet(&SomeRtcpPacket::RtcpFbTccPacket(feedback)).unwrap(),
+            expected
+        );
+        assert!(generator.build_feedback().is_none());
+    }
+
+    #[test]
+    fn test_feedback_large_delta_and_reference_time() {
+        let mut generator = create_generator(Duration::from_millis(100));
+        let start = Instant::now();
+
+        generator.packet_received(1, start);
+        let first = generator.build_feedback().unwrap();
+        assert_eq!(first.feedback_packet_count, 0);
+
+        // 130ms later is 2 reference time units past the time base, plus 2ms
+        generator.packet_received(2, start + Duration::from_millis(130));
+        generator.packet_received(3, start + Duration::from_millis(230));
+        let feedback = generator.build_feedback().unwrap();
+        assert_eq!(feedback.reference_time, 2);
+        assert_eq!(feedback.feedback_packet_count, 1);
+        assert_eq!(
+            feedback.packet_reports,
+            vec![
+                PacketReport::ReceivedPacketSmallDelta {
+                    seq_num: 2,
+                    delta_ticks: 8
+                },
+                PacketReport::ReceivedPacketLargeOrNegativeDelta {
+                    seq_num: 3,
+                    delta_ticks: 400
+                },
+            ]
+        );
+
+        #[rustfmt::skip]
+        let expected: Vec<u8> = vec![
+            0x8F, 0xCD, 0x00, 0x06,
+            0x01, 0x02, 0x03, 0x04,
+            0x00, 0x00, 0x00, 0x00,
+            // base seq num 2, status count 2
+            0x00, 0x02, 0x00, 0x02,
+            // reference time 2, fb packet count 1
+            0x00, 0x00, 0x02, 0x01,
+            // 2 bit status vector chunk, deltas
+            0xD8, 0x00, 0x08, 0x01,
+            0x90, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(
+            write_rtcp_packet(&SomeRtcpPacket::RtcpFbTccPacket(feedback)).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_feedback_wraparound_and_late_packets() {
+        let mut generator = create_generator(Duration::from_millis(100));
+        let start = Instant::now();
+
+        generator.packet_received(65534, start);
+        generator.packet_received(0, start + Duration::from_millis(1));
+        let feedback = generator.build_feedback().unwrap();
+        assert_eq!(
+            feedback.packet_reports,
+            vec![
+                PacketReport::ReceivedPacketSmallDelta {
+                    seq_num: 65534,
+                    delta_ticks: 0
+                },
+                PacketReport::UnreceivedPacket { seq_num: 65535 },
+                PacketReport::ReceivedPacketSmallDelta {
+                    seq_num: 0,
+                    delta_ticks: 4
+                },
+            ]
+        );
+
+        // 65535 arrives after it was already reported as lost, so it's ignored
+        generator.packet_received(65535, start + Duration::from_millis(2));
+        assert!(generator.build_feedback().is_none());
+    }
+
+    #[test]
+    fn test_feedback_sent_without_more_packets() {
+        let clock = Arc::new(ManualClock::new());
+        let timers = TimerScheduler::new(clock.clone());
+        let tcc_ext_id = LiveStateWriter::new(Some(5));
+        let (tx, mut rx) = unbounded_channel();
+        let mut generator = TccGenerator::new(
+            tcc_ext_id.reader(),
+            0x01020304,
+            Duration::from_millis(100),
+            tx,
+            &timers,
+            SharedData::default(),
+        );
+        let start = clock.now();
+
+        for tcc_seq_num in 0..5u16 {
+            #[rustfmt::skip]
+            let mut packet = vec![
+                0x90, 0x60, 0x00, 0x01,
+                0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x16, 0x2E,
+                0xBE, 0xDE, 0x00, 0x01,
+                0x51,
+            ];
+            packet.extend_from_slice(&u16::to_be_bytes(tcc_seq_num));
+            packet.push(0x00);
+            generator.observe(&PacketInfo::new(
+                SomePacket::VideoRtpPacket(read_rtp_packet(packet).unwrap()),
+                clock.now(),
+            ));
+            assert_eq!(timers.fire_due(), Some(start + Duration::from_millis(100)));
+            clock.advance(Duration::from_millis(20));
+        }
+        assert!(rx.try_recv().is_err());
+
+        // No more packets arrive, but the ones we have are still reported on time
+        assert_eq!(timers.fire_due(), None);
+        match rx.try_recv().unwrap() {
+            SomeRtcpPacket::RtcpFbTccPacket(tcc) => {
+                assert_eq!(tcc.fb_header.media_source_ssrc, 0x162E);
+                assert_eq!(tcc.packet_reports.len(), 5);
+            }
+            p => panic!("unexpected packet {p:?}"),
+        }
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use tokio::sync::Notify;
+
+use crate::{clock::Clock, util::SharedData};
+
+/// Something which needs to act at a point in time, regardless of whether any packets arrive
+/// (e.g. retrying nacks or releasing packets from a jitter buffer).
+pub trait TimerHandler: Send {
+    /// Called once a deadline registered via the handler's [`TimerHandle`] has passed.  Returns
+    /// the next deadline the handler needs, if any.
+    fn on_timer(&mut self, now: Instant) -> Option<Instant>;
+}
+
+/// Lets a pipeline node and a [`TimerScheduler`] share a handler.
+impl<T: TimerHandler + Sync> TimerHandler for SharedData<T> {
+    fn on_timer(&mut self, now: Instant) -> Option<Instant> {
+        self.write().on_timer(now)
+    }
+}
+
+type SharedHandler = Arc<Mutex<Box<dyn TimerHandler>>>;
+
+struct Registration {
+    handler: SharedHandler,
+    deadline: Option<Instant>,
+}
+
+#[derive(Default)]
+struct Registry {
+    registrations: HashMap<u64, Registration>,
+    next_id: u64,
+}
+
+impl Registry {
+    fn schedule(&mut self, id: u64, deadline: Instant) {
+        // The handler may have been removed while it was being called
+        if let Some(registration) = self.registrations.get_mut(&id) {
+            registration.deadline = Some(
+                registration
+                    .deadline
+                    .map_or(deadline, |current| current.min(deadline)),
+            );
+        }
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.registrations
+            .values()
+            .filter_map(|registration| registration.deadline)
+            .min()
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    registry: Mutex<Registry>,
+    notify: Notify,
+}
+
+struct HandleInner {
+    id: u64,
+    shared: Arc<Shared>,
+}
+
+impl Drop for HandleInner {
+    fn drop(&mut self) {
+        self.shared
+            .registry
+            .lock()
+            .unwrap()
+            .registrations
+            .remove(&self.id);
+    }
+}
+
+/// Used by a [`TimerHandler`] (or whatever feeds it) to register a deadline at which the handler
+/// should be called.  The handler is removed from the scheduler once every clone of its handle
+/// has been dropped, so the handle should be kept by whatever owns the handler (e.g. its pipeline
+/// node).
+#[derive(Clone)]
+pub struct TimerHandle(Arc<HandleInner>);
+
+impl TimerHandle {
+    /// Have the handler called at `deadline`.  If there's already an earlier deadline pending
+    /// this has no effect, since the handler can return the next deadline it needs when it's
+    /// called.
+    pub fn schedule(&self, deadline: Instant) {
+        let shared = &self.0.shared;
+        shared
+            .registry
+            .lock()
+            .unwrap()
+            .schedule(self.0.id, deadline);
+        shared.notify.notify_one();
+    }
+}
+
+/// Calls [`TimerHandler`]s when their deadlines pass.  Time is taken from the given [`Clock`], so
+/// tests can use a fake clock and drive the scheduler via [`TimerScheduler::fire_due`] rather
+/// than [`TimerScheduler::run`].  Clones share the same handlers, so handlers can still be
+/// registered via a clone once another one has been given to [`TimerScheduler::run`].
+#[derive(Clone)]
+pub struct TimerScheduler {
+    clock: Arc<dyn Clock>,
+    shared: Arc<Shared>,
+}
+
+impl TimerScheduler {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock,
+            shared: Arc::default(),
+        }
+    }
+
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    /// Add a handler, returning the [`TimerHandle`] used to schedule it.  The handler won't be
+    /// called until a deadline is scheduled, and is removed once the handle is dropped.
+    pub fn register<H: TimerHandler + 'static>(&self, handler: H) -> TimerHandle {
+        let mut registry = self.shared.registry.lock().unwrap();
+        let id = registry.next_id;
+        registry.next_id += 1;
+        registry.registrations.insert(
+            id,
+            Registration {
+                handler: Arc::new(Mutex::new(Box::new(handler))),
+                deadline: None,
+            },
+        );
+        TimerHandle(Arc::new(HandleInner {
+            id,
+            shared: self.shared.clone(),
+        }))
+    }
+
+    /// Call every handler whose deadline has passed, returning the earliest pending deadline.
+    pub fn fire_due(&self) -> Option<Instant> {
+        let now = self.clock.now();
+        let due = self
+            .shared
+            .registry
+            .lock()
+            .unwrap()
+            .registrations
+            .iter_mut()
+            .filter_map(|(id, registration)| {
+                registration
+                    .deadline
+                    .take_if(|deadline| *deadline <= now)
+                    .map(|_| (*id, registration.handler.clone()))
+            })
+            .collect::<Vec<_>>();
+        // The registry isn't locked while handlers are called, since they may schedule themselves
+        for (id, handler) in due {
+            if let Some(next_deadline) = handler.lock().unwrap().on_timer(now) {
+                self.shared
+                    .registry
+                    .lock()
+                    .unwrap()
+                    .schedule(id, next_deadline);
+            }
+        }
+        self.shared.registry.lock().unwrap().next_deadline()
+    }
+
+    /// Call handlers as their deadlines pass, until this task is dropped.
+    pub async fn run(self) {
+        loop {
+            match self.fire_due() {
+                Some(next_deadline) => tokio::select! {
+                    _ = tokio::time::sleep_until(next_deadline.into()) => {}
+                    _ = self.shared.notify.notified() => {}
+                },
+                None => self.shared.notify.notified().await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::clock::{ManualClock, SystemClock};
+
+    use super::*;
+
+    /// Records when it was called and asks to be called again every 10ms, 3 times
+    #[derive(Default)]
+    struct Ticker {
+        calls: Vec<Instant>,
+    }
+
+    impl TimerHandler for Ticker {
+        fn on_timer(&mut self, now: Instant) -> Option<Instant> {
+            self.calls.push(now);
+            (self.calls.len() < 3).then(|| now + Duration::from_millis(10))
+        }
+    }
+
+    #[test]
+    fn test_timer_scheduler() {
+        let clock = Arc::new(ManualClock::new());
+        let scheduler = TimerScheduler::new(clock.clone());
+        let ticker = SharedData::new(Ticker::default());
+        let handle = scheduler.register(ticker.clone());
+        let idle = scheduler.register(SharedData::new(Ticker::default()));
+        let start = clock.now();
+
+        // Nothing is scheduled yet
+        assert_eq!(scheduler.fire_due(), None);
+
+        handle.schedule(start + Duration::from_millis(20));
+        // An earlier deadline takes precedence
+        handle.schedule(start + Duration::from_millis(5));
+        assert_eq!(scheduler.fire_due(), Some(start + Duration::from_millis(5)));
+        assert!(ticker.read().calls.is_empty());
+
+        for _ in 0..5 {
+            clock.advance(Duration::from_millis(5));
+            scheduler.fire_due();
+        }
+        assert_eq!(
+            ticker.read().calls,
+            vec![
+                start + Duration::from_millis(5),
+                start + Duration::from_millis(15),
+                start + Duration::from_millis(25),
+            ]
+        );
+        assert_eq!(scheduler.fire_due(), None);
+
+        idle.schedule(clock.now());
+        assert_eq!(
+            scheduler.fire_due(),
+            Some(clock.now() + Duration::from_millis(10))
+        );
+    }
+
+    #[test]
+    fn test_handler_removed_when_handle_dropped() {
+        let clock = Arc::new(ManualClock::new());
+        let scheduler = TimerScheduler::new(clock.clone());
+        let ticker = SharedData::new(Ticker::default());
+        let handle = scheduler.register(ticker.clone());
+        let handle_clone = handle.clone();
+        handle.schedule(clock.now());
+
+        drop(handle);
+        // A clone keeps the handler registered
+        assert_eq!(
+            scheduler.fire_due(),
+            Some(clock.now() + Duration::from_millis(10))
+        );
+        drop(handle_clone);
+        assert_eq!(scheduler.fire_due(), None);
+        clock.advance(Duration::from_millis(10));
+        scheduler.fire_due();
+        assert_eq!(ticker.read().calls.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_register_while_running() {
+        let scheduler = TimerScheduler::new(Arc::new(SystemClock));
+        tokio::spawn(scheduler.clone().run());
+        tokio::task::yield_now().await;
+
+        let ticker = SharedData::new(Ticker::default());
+        let handle = scheduler.register(ticker.clone());
+        handle.schedule(Instant::now() + Duration::from_millis(5));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(ticker.read().calls.len(), 3);
+    }
+}
@@ -40,6 +40,12 @@ impl<T> SharedData<T> {
 /// [`LiveStateReader`] is a read-only view of some state that is updated elsewhere.
 pub struct LiveStateReader<T>(tokio::sync::watch::Receiver<T>);
 
+impl<T> Clone for LiveStateReader<T> {
+    fn clone(&self) -> Self {
+        LiveStateReader(self.0.clone())
+    }
+}
+
 impl<T> LiveStateReader<T> {
     // pub fn value(&self) -> &T {
     //     self.0.borrow().deref()
@@ -48,6 +54,17 @@ impl<T> LiveStateReader<T> {
     pub fn value(&self) -> tokio::sync::watch::Ref<'_, T> {
         self.0.borrow()
     }
+
+    /// Whether the value has changed since it was last read via [`LiveStateReader::latest`]
+    pub fn has_changed(&self) -> bool {
+        // An error means the writer is gone, so the value can't change anymore
+        self.0.has_changed().unwrap_or(false)
+    }
+
+    /// The current value, which is then no longer considered changed
+    pub fn latest(&mut self) -> tokio::sync::watch::Ref<'_, T> {
+        self.0.borrow_and_update()
+    }
 }
 
 pub struct LiveStateWriter<T> {
@@ -62,8 +79,8 @@ impl<T> LiveStateWriter<T> {
     }
 
     pub fn set(&self, new_value: T) {
-        // TODO: bubble up return?
-        let _ = self.inner.send(new_value);
+        // Unlike send, send_replace stores the value even if there are no readers yet
+        self.inner.send_replace(new_value);
     }
 
     pub fn value(&self) -> tokio::sync::watch::Ref<'_, T> {